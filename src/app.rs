@@ -1,6 +1,7 @@
-use std::sync::{ Arc, Mutex, mpsc };
+use std::sync::{ Arc, Mutex, RwLock, mpsc };
+use std::time::Instant;
 use piston_window::PistonWindow;
-use piston::input::{ self, UpdateArgs, RenderArgs };
+use piston::input::{ self, UpdateArgs, Button };
 use slog::Logger;
 use gfx;
 use gfx_device_gl;
@@ -12,6 +13,8 @@ use types::*;
 use globe;
 use cell_dweller;
 use input_adapter::InputAdapter;
+use input_recording::InputSource;
+use input_bindings::{ Action, InputBindings, SharedInputBindings };
 
 fn get_projection(w: &PistonWindow) -> [[f32; 4]; 4] {
     use piston::window::Window;
@@ -24,12 +27,33 @@ fn get_projection(w: &PistonWindow) -> [[f32; 4]; 4] {
     }.projection()
 }
 
-pub struct App {
-    t: TimeDelta,
-    log: Logger,
-    planner: specs::Planner<TimeDelta>,
+/// How many simulation ticks to run per second of real time
+/// when running in a window.
+pub const TICKS_PER_SECOND: f64 = 60.0;
+
+// If we fall this many ticks behind, give up trying to catch up,
+// so that we don't spiral into doing nothing but simulating.
+const MAX_TICKS_PER_UPDATE: usize = 10;
+
+// How many times to step the simulation at startup waiting
+// for the chunks where the player will start to be loaded.
+const MAX_SPAWN_WAIT_TICKS: usize = 1000;
+
+/// An input event to feed to an `App`'s input adapters
+/// just before a given tick of `App::run_headless`.
+#[derive(Clone)]
+pub struct ScriptedInput {
+    /// Counted from zero at the start of the run.
+    pub tick: u64,
+    pub input: input::Input,
+}
+
+// Everything the `App` needs to draw to a window.
+// Headless apps don't have one of these.
+struct Renderer {
+    render_sys: render::System<gfx_device_gl::Resources, gfx_device_gl::CommandBuffer>,
+    camera_sys: render::player_camera::System,
     encoder_channel: render::EncoderChannel<gfx_device_gl::Resources, gfx_device_gl::CommandBuffer>,
-    input_adapters: Vec<Box<InputAdapter>>,
     // TEMP: Share with rendering system until the rendering system
     // is smart enough to take full ownership of it.
     projection: Arc<Mutex<[[f32; 4]; 4]>>,
@@ -38,11 +62,34 @@ pub struct App {
     output_color: gfx::handle::RenderTargetView<gfx_device_gl::Resources, (gfx::format::R8_G8_B8_A8, gfx::format::Srgb)>,
     output_stencil: gfx::handle::DepthStencilView<gfx_device_gl::Resources, (gfx::format::D24_S8, gfx::format::Unorm)>,
     mesh_repo: Arc<Mutex<MeshRepository<gfx_device_gl::Resources>>>,
+    // When we last drew a frame. Piston's `ext_dt` is the time
+    // since the last _update_, which isn't what the camera wants.
+    last_render_time: Instant,
+}
+
+pub struct App {
+    t: TimeDelta,
+    // Real time that has passed but hasn't been simulated yet.
+    unsimulated_time: TimeDelta,
+    log: Logger,
+    planner: specs::Planner<TimeDelta>,
+    input_adapters: Vec<Box<InputAdapter>>,
+    // Shared with input adapters, so we can change them on the fly.
+    input_bindings: SharedInputBindings,
+    // Buttons that have been pressed and not yet released,
+    // so we can let go of them if they get rebound.
+    held_buttons: Vec<Button>,
+    // Whether the cursor is captured so that moving
+    // the mouse turns and tilts the player.
+    mouse_look: bool,
+    input_sources: Vec<Box<InputSource>>,
+    // How many ticks we've run since the app was created.
+    ticks: u64,
+    renderer: Option<Renderer>,
 }
 
 impl App {
-    pub fn new(parent_log: &Logger, window: &mut PistonWindow,
-               camera_input_sender: mpsc::Sender<input::Input>) -> App {
+    pub fn new(parent_log: &Logger, window: &mut PistonWindow) -> App {
         use camera_controllers::{
             FirstPersonSettings,
             FirstPerson,
@@ -74,11 +121,9 @@ impl App {
         // the video card in one go.)
         let enc1 = window.factory.create_command_buffer().into();
         let enc2 = window.factory.create_command_buffer().into();
-        // TODO: this carefully sending one encoder to each
-        // channel is only because I'm temporarily calling
-        // the rendering system synchronously until I get
-        // around to turning it into a Specs system. Juggling like
-        // this prevents deadlock.
+        // The rendering system is called synchronously from this
+        // thread, so carefully send one encoder to each channel.
+        // Juggling like this prevents deadlock.
         render_sys_encoder_channel.sender.send(enc1).unwrap();
         device_encoder_channel.sender.send(enc2).unwrap();
 
@@ -104,6 +149,15 @@ impl App {
             mesh_repo_ptr.clone(),
         );
 
+        let input_bindings = Arc::new(RwLock::new(InputBindings::default()));
+
+        // Event channel for camera system
+        let (camera_input_sender, camera_input_receiver) = mpsc::channel();
+        let camera_sys = render::player_camera::System::new(
+            camera_input_receiver,
+            input_bindings.clone(),
+        );
+
         // Create SPECS world and, system execution planner
         // for it with two threads.
         //
@@ -111,21 +165,56 @@ impl App {
         // i.e. the interaction between sets of components.
         let world = specs::World::new();
 
-        let mut planner = specs::Planner::new(world, 2);
-        planner.add_system(render_sys, "render", 50);
+        // Rendering happens outside of the planner, so that it
+        // isn't tied to the simulation's fixed tick rate.
+        let planner = specs::Planner::new(world, 2);
+
+        App {
+            t: 0.0,
+            unsimulated_time: 0.0,
+            log: log,
+            planner: planner,
+            input_adapters: Vec::new(),
+            input_bindings: input_bindings,
+            held_buttons: Vec::new(),
+            mouse_look: false,
+            input_sources: Vec::new(),
+            ticks: 0,
+            renderer: Some(Renderer {
+                render_sys: render_sys,
+                camera_sys: camera_sys,
+                encoder_channel: device_encoder_channel,
+                projection: projection,
+                camera_input_sender: camera_input_sender,
+                factory: factory.clone(),
+                output_color: window.output_color.clone(),
+                output_stencil: window.output_stencil.clone(),
+                mesh_repo: mesh_repo_ptr,
+                last_render_time: Instant::now(),
+            }),
+        }
+    }
 
+    /// Make an app with no window and nothing to draw to.
+    ///
+    /// This can only run the simulation, via `tick` or `run_headless`,
+    /// so it doesn't need a GPU; e.g. for tests.
+    pub fn new_headless(parent_log: &Logger) -> App {
+        let log = parent_log.new(o!());
+        let world = specs::World::new();
+        let planner = specs::Planner::new(world, 2);
         App {
             t: 0.0,
+            unsimulated_time: 0.0,
             log: log,
             planner: planner,
-            encoder_channel: device_encoder_channel,
             input_adapters: Vec::new(),
-            projection: projection,
-            camera_input_sender: camera_input_sender,
-            factory: factory.clone(),
-            output_color: window.output_color.clone(),
-            output_stencil: window.output_stencil.clone(),
-            mesh_repo: mesh_repo_ptr,
+            input_bindings: Arc::new(RwLock::new(InputBindings::default())),
+            held_buttons: Vec::new(),
+            mouse_look: false,
+            input_sources: Vec::new(),
+            ticks: 0,
+            renderer: None,
         }
     }
 
@@ -149,52 +238,114 @@ impl App {
             .with(globe)
             .build();
 
+        // Draw the whole globe from far away, without
+        // having to load all of its chunks.
+        self.planner.mut_world().create_now()
+            .with(globe::LodView::new(globe_entity))
+            .with(render::Visual::new_empty())
+            .with(Spatial::root())
+            .build();
+
+        // Chunks only get loaded around `CellDweller`s and other chunk
+        // anchors, so hold the area around where we're going to put
+        // the player character until he's there to do it himself.
+        use globe::{ CellPos, Dir, ChunkAnchor };
+        let spawn_anchor_pos = CellPos::default().set_z(
+            ((globe_spec.ocean_radius - globe_spec.floor_radius) / globe_spec.block_height) as i64
+        );
+        let spawn_anchor_entity = self.planner.mut_world().create_now()
+            .with(ChunkAnchor::new(Some(globe_entity), spawn_anchor_pos))
+            .build();
+
         // TEMP
         // Step before adding cell dweller; otherwise there'll be
         // no chunks, so we won't know where to put him!
-        self.planner.dispatch(0.02);
-        self.planner.wait();
-
+        //
+        // Chunks might be generated on background threads,
+        // so keep stepping until the spawn column has turned up.
+        //
         // Find globe surface and put player character on it.
-        use globe::{ CellPos, Dir };
-        use globe::chunk::Material;
-        let mut guy_pos = CellPos::default();
-        guy_pos = {
-            let globes = self.planner
-                .mut_world()
-                .read::<globe::Globe>();
-            let globe = globes
-                .get(globe_entity)
-                .expect("Uh oh, where did our Globe go?");
-            globe.find_lowest_cell_containing(guy_pos, Material::Air)
-                .expect("Uh oh, there's something wrong with our globe.")
+        use globe::material;
+        let mut guy_pos = None;
+        for _ in 0..MAX_SPAWN_WAIT_TICKS {
+            self.planner.dispatch(0.02);
+            self.planner.wait();
+
+            guy_pos = {
+                let globes = self.planner
+                    .mut_world()
+                    .read::<globe::Globe>();
+                let globe = globes
+                    .get(globe_entity)
+                    .expect("Uh oh, where did our Globe go?");
+                globe.find_lowest_cell_containing(CellPos::default(), material::AIR)
+            };
+            if guy_pos.is_some() {
+                break;
+            }
+            // Give the workers a chance to catch up.
+            ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        }
+        let guy_pos = guy_pos.expect("Uh oh, there's something wrong with our globe.");
+        // There's nothing to draw with if we're headless.
+        let snowman_mesh = self.renderer.as_ref().map(|renderer| {
+            let factory = &mut renderer.factory.clone();
+            let mut mesh_repo = renderer.mesh_repo.lock().unwrap();
+            let _axes_mesh = render::make_axes_mesh(
+                factory,
+                &mut mesh_repo,
+            );
+            render::make_obj_mesh(
+                "assets/models/snowman.obj",
+                "assets/models/snowman.mtl",
+                0.01,
+                factory,
+                &mut mesh_repo,
+            )
+        });
+        let make_snowman_visual = || {
+            let mut visual = render::Visual::new_empty();
+            if let Some(snowman_mesh) = snowman_mesh {
+                visual.set_mesh_handle(snowman_mesh);
+            }
+            visual
         };
-        let factory = &mut self.factory.clone();
-        let mut mesh_repo = self.mesh_repo.lock().unwrap();
-        let axes_mesh = render::make_axes_mesh(
-            factory,
-            &mut mesh_repo,
-        );
-        let snowman_mesh = render::make_obj_mesh(
-            "assets/models/snowman.obj",
-            "assets/models/snowman.mtl",
-            0.01,
-            factory,
-            &mut mesh_repo,
-        );
-        let mut cell_dweller_visual = render::Visual::new_empty();
-        cell_dweller_visual.set_mesh_handle(snowman_mesh);
-        self.planner.mut_world().create_now()
-            .with(render::player_camera::ClientPlayer)
-            .with(cell_dweller::CellDweller::new(
+        // Start everyone out drawn where they're standing,
+        // rather than flying in from the center of the globe.
+        let make_cell_dweller = || {
+            let mut cd = cell_dweller::CellDweller::new(
                 guy_pos,
                 Dir::default(),
                 globe_spec,
                 Some(globe_entity),
-            ))
-            .with(cell_dweller_visual)
-            .with(Spatial::root())
+            );
+            let spatial = Spatial::new(cd.get_real_transform_and_mark_as_clean());
+            (cd, spatial)
+        };
+        let (player_cd, player_spatial) = make_cell_dweller();
+        let player_entity = self.planner.mut_world().create_now()
+            .with(cell_dweller::ClientPlayer)
+            .with(player_cd)
+            .with(cell_dweller::BlockInventory::new())
+            .with(make_snowman_visual())
+            .with(player_spatial)
             .build();
+
+        // Give him a friend to follow him around.
+        let (friend_cd, friend_spatial) = make_cell_dweller();
+        self.planner.mut_world().create_now()
+            .with(friend_cd)
+            .with(cell_dweller::MovementIntent::new())
+            // He just follows; he never digs or builds.
+            .with(cell_dweller::MiningIntent::new())
+            .with(cell_dweller::Behaviour::new(cell_dweller::Goal::Follow(player_entity)))
+            .with(make_snowman_visual())
+            .with(friend_spatial)
+            .build();
+
+        // The player character will keep the chunks around him
+        // loaded from here on.
+        self.planner.mut_world().delete_later(spawn_anchor_entity);
     }
 
     pub fn run(&mut self, mut window: &mut PistonWindow) {
@@ -203,13 +354,14 @@ impl App {
         info!(self.log, "Starting event loop");
 
         let mut events = window.events;
+        let mut is_cursor_captured = false;
         while let Some(e) = events.next(window) {
-            if let Some(r) = e.render_args() {
-                self.render(&r, &mut window);
+            if e.render_args().is_some() {
+                self.render(&mut window);
             }
 
             if e.resize_args().is_some() {
-                let mut projection = self.projection.lock().unwrap();
+                let mut projection = self.renderer().projection.lock().unwrap();
                 *projection = get_projection(window);
             }
 
@@ -218,24 +370,42 @@ impl App {
             }
 
             // Dispatch input events to any systems that care.
-            for adapter in &self.input_adapters {
-                adapter.handle(&e);
+            self.handle_input(&e);
+
+            if self.mouse_look != is_cursor_captured {
+                use piston::window::AdvancedWindow;
+                window.set_capture_cursor(self.mouse_look);
+                is_cursor_captured = self.mouse_look;
             }
 
-            self.camera_input_sender.send(e);
+            self.renderer().camera_input_sender.send(e);
         }
 
         info!(self.log, "Quitting");
     }
 
-    fn render(&mut self, _args: &RenderArgs, window: &mut PistonWindow) {
-        // TODO: Systems are currently run on the main thread,
+    fn render(&mut self, window: &mut PistonWindow) {
+        // Draw everything where it would be at this point between
+        // the last two ticks, so that motion looks smooth regardless
+        // of how the frame rate lines up with the tick rate.
+        let alpha = (self.unsimulated_time * TICKS_PER_SECOND).min(1.0);
+        let renderer = self.renderer.as_mut()
+            .expect("Headless apps can't draw anything; use `run_headless` instead");
+        let now = Instant::now();
+        let since_last_render = now.duration_since(renderer.last_render_time);
+        renderer.last_render_time = now;
+        let frame_dt = since_last_render.as_secs() as TimeDelta
+            + since_last_render.subsec_nanos() as TimeDelta * 1e-9;
+        {
+            let world = self.planner.mut_world();
+            renderer.camera_sys.update(world, alpha, frame_dt);
+            renderer.render_sys.draw_world(world, alpha);
+        }
+
+        // The render system is called on this thread,
         // so we need to `try_recv` to avoid deadlock.
-        // This is only because I don't want to burn CPU, and I've yet
-        // to get around to frame/update rate limiting, so I'm
-        // relying on Piston's for now.
         use std::sync::mpsc::TryRecvError;
-        let mut encoder = match self.encoder_channel.receiver.try_recv() {
+        let mut encoder = match renderer.encoder_channel.receiver.try_recv() {
             Ok(encoder) => encoder,
             Err(TryRecvError::Empty) => return,
             Err(TryRecvError::Disconnected) => panic!("Render system hung up. That wasn't supposed to happen!"),
@@ -248,14 +418,113 @@ impl App {
 
         encoder.flush(&mut window.device);
 
-        self.encoder_channel.sender.send(encoder).unwrap();
+        renderer.encoder_channel.sender.send(encoder).unwrap();
     }
 
     fn update(&mut self, args: &UpdateArgs) {
-        self.t += args.dt;
-        self.planner.dispatch(args.dt);
+        // Piston's update rate needn't match our tick rate,
+        // so run however many whole ticks have accumulated,
+        // and leave the remainder for next time.
+        let tick_dt = 1.0 / TICKS_PER_SECOND;
+        self.unsimulated_time += args.dt;
+        let mut ticks_run = 0;
+        while self.unsimulated_time >= tick_dt {
+            if ticks_run >= MAX_TICKS_PER_UPDATE {
+                warn!(self.log, "Simulation can't keep up; skipping ahead"; "unsimulated_time" => self.unsimulated_time);
+                self.unsimulated_time = 0.0;
+                break;
+            }
+            self.tick(tick_dt);
+            self.unsimulated_time -= tick_dt;
+            ticks_run += 1;
+        }
+    }
 
-        self.realize_proto_meshes();
+    /// Advance the simulation by `dt` seconds, and wait for
+    /// all the systems to finish before returning.
+    pub fn tick(&mut self, dt: TimeDelta) {
+        // Remember where everything was, so it can be drawn
+        // part way between here and wherever it ends up.
+        {
+            use specs::Join;
+            use ::Spatial;
+            let world = self.planner.mut_world();
+            let mut spatials = world.write::<Spatial>();
+            for spatial in (&mut spatials).iter() {
+                spatial.start_tick();
+            }
+        }
+
+        for input_source in &mut self.input_sources {
+            input_source.pump(self.ticks);
+        }
+
+        self.t += dt;
+        self.planner.dispatch(dt);
+        self.planner.wait();
+        self.ticks += 1;
+
+        if self.renderer.is_some() {
+            self.realize_proto_meshes();
+        }
+    }
+
+    /// Run the simulation for `ticks` ticks of `dt` seconds each,
+    /// without waiting for or drawing to a window.
+    ///
+    /// Each entry in `script` is passed to the input adapters
+    /// right before its tick, so the same script run against
+    /// the same world should always give the same results.
+    pub fn run_headless(&mut self, ticks: u64, dt: TimeDelta, script: &[ScriptedInput]) {
+        info!(self.log, "Starting headless run"; "ticks" => ticks);
+
+        // Sorting is stable, so inputs for the same tick
+        // still arrive in the order they were given.
+        let mut script: Vec<&ScriptedInput> = script.iter().collect();
+        script.sort_by_key(|scripted_input| scripted_input.tick);
+        let mut next_input_index = 0;
+        for tick in 0..ticks {
+            while next_input_index < script.len() && script[next_input_index].tick == tick {
+                self.handle_input(&script[next_input_index].input);
+                next_input_index += 1;
+            }
+            self.tick(dt);
+        }
+
+        info!(self.log, "Finished headless run");
+    }
+
+    fn handle_input(&mut self, input_event: &input::Input) {
+        use piston::input::{ PressEvent, ReleaseEvent, MouseRelativeEvent };
+
+        if let Some(button) = input_event.press_args() {
+            if !self.held_buttons.contains(&button) {
+                self.held_buttons.push(button);
+            }
+            let action = self.input_bindings.read().unwrap().action(button);
+            if action == Some(Action::ToggleMouseLook) {
+                self.mouse_look = !self.mouse_look;
+                debug!(self.log, "Toggled mouse-look"; "enabled" => self.mouse_look);
+            }
+        }
+        if let Some(button) = input_event.release_args() {
+            self.held_buttons.retain(|&held_button| held_button != button);
+        }
+
+        // Only look around with the mouse while it's captured; otherwise
+        // the player is probably just trying to click on something else.
+        if !self.mouse_look && input_event.mouse_relative_args().is_some() {
+            return;
+        }
+
+        for adapter in &self.input_adapters {
+            adapter.handle(input_event);
+        }
+    }
+
+    fn renderer(&mut self) -> &mut Renderer {
+        self.renderer.as_mut()
+            .expect("Headless apps can't draw anything; use `run_headless` instead")
     }
 
     // This whole thing is a horrible hack around
@@ -268,8 +537,10 @@ impl App {
         // NOTE: it is essential that we lock the world first.
         // Otherwise we could dead-lock against, e.g., the render
         // system while it's trying to lock the mesh repository.
+        let renderer = self.renderer.as_mut()
+            .expect("Headless apps don't have any meshes to realize");
         let world = self.planner.mut_world();
-        let mut mesh_repo = self.mesh_repo.lock().unwrap();
+        let mut mesh_repo = renderer.mesh_repo.lock().unwrap();
         let mut visuals = world.write::<Visual>();
         use specs::Join;
         for visual in (&mut visuals).iter() {
@@ -282,11 +553,11 @@ impl App {
             }
             let proto_mesh = visual.proto_mesh.clone().expect("Just ensured this above...");
             let mesh = Mesh::new(
-                &mut self.factory,
+                &mut renderer.factory,
                 proto_mesh.vertexes.clone(),
                 proto_mesh.indexes.clone(),
-                self.output_color.clone(),
-                self.output_stencil.clone(),
+                renderer.output_color.clone(),
+                renderer.output_stencil.clone(),
             );
             if let Some(existing_mesh_handle) = visual.mesh_handle() {
                 // We're replacing an existing mesh that got dirty.
@@ -303,6 +574,49 @@ impl App {
     pub fn add_input_adapter(&mut self, adapter: Box<InputAdapter>) {
         self.input_adapters.push(adapter);
     }
+
+    /// The bindings that input adapters should look up buttons in.
+    pub fn input_bindings(&self) -> SharedInputBindings {
+        self.input_bindings.clone()
+    }
+
+    /// Turn mouse-look mode on or off. While it's on, the cursor is
+    /// captured, and moving the mouse turns and tilts the player.
+    ///
+    /// Also toggled by `Action::ToggleMouseLook`.
+    pub fn set_mouse_look(&mut self, enabled: bool) {
+        self.mouse_look = enabled;
+    }
+
+    /// Change which buttons do what, effective immediately.
+    ///
+    /// Any button that's held down while its action changes is let go of
+    /// first, as far as the input adapters are concerned; otherwise they'd
+    /// never hear about it being released, and its old action would be
+    /// stuck on. It has to be pressed again to do its new thing.
+    pub fn set_input_bindings(&mut self, bindings: InputBindings) {
+        let rebound_buttons: Vec<Button> = {
+            let old_bindings = self.input_bindings.read().unwrap();
+            self.held_buttons.iter()
+                .cloned()
+                .filter(|&button| old_bindings.action(button) != bindings.action(button))
+                .collect()
+        };
+        for button in rebound_buttons {
+            self.held_buttons.retain(|&held_button| held_button != button);
+            let release = input::Input::Release(button);
+            for adapter in &self.input_adapters {
+                adapter.handle(&release);
+            }
+        }
+        *self.input_bindings.write().unwrap() = bindings;
+    }
+
+    /// Add something to feed input events to systems
+    /// right before each tick; see `input_recording`.
+    pub fn add_input_source(&mut self, source: Box<InputSource>) {
+        self.input_sources.push(source);
+    }
 }
 
 impl<'a> App {
@@ -1,6 +1,35 @@
 extern crate planetkit as pk;
 
+use std::env;
+use std::fs::File;
+
 fn main() {
-    let (mut app, mut window) = pk::simple::new();
+    let args: Vec<String> = env::args().collect();
+
+    // Pass `--record <path>` to record all input to a file,
+    // e.g. to attach to a bug report.
+    let (mut app, mut window) = match arg_value(&args, "--record") {
+        Some(path) => {
+            let file = File::create(path).expect("Couldn't create input recording");
+            pk::simple::new_recording(Box::new(file))
+        },
+        None => pk::simple::new(),
+    };
+
+    // Pass `--bindings <path>` to use your own key bindings;
+    // see `pk::input_bindings` for the format.
+    if let Some(path) = arg_value(&args, "--bindings") {
+        let bindings = pk::input_bindings::InputBindings::load_from_file(path)
+            .expect("Couldn't load input bindings");
+        app.set_input_bindings(bindings);
+    }
+
     app.run(&mut window);
 }
+
+// The argument following `name`, if there is one.
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .map(|index| args.get(index + 1).expect("Expected a value after argument").as_str())
+}
@@ -0,0 +1,251 @@
+use specs;
+use slog::Logger;
+use rand::{ Rng, XorShiftRng };
+
+use types::*;
+use super::{
+    CellDweller,
+    MovementIntent,
+    Behaviour,
+    Goal,
+    ForwardOrBackward,
+    walkable_step,
+};
+use globe::{ Globe, IntCoord, CellPos, Dir, PosInOwningRoot, MaterialRegistry };
+use movement::{ TurnDir, turn_by_one_hex_edge };
+use navigation;
+
+// How long to wait before looking for a new path after failing
+// to find one, or to catch up with someone who has moved.
+const SECONDS_BETWEEN_REPLANS: TimeDelta = 1.0;
+// Don't bother looking for paths through more cells than this.
+const MAX_CELLS_TO_SEARCH: usize = 1000;
+// How many steps to take in a random direction each time we wander.
+const WANDER_STEPS: usize = 6;
+// How many steps behind whoever we're following we're happy to be.
+const FOLLOW_DISTANCE: usize = 2;
+
+/// Drives `CellDweller`s that have a `Behaviour`, by filling in their
+/// `MovementIntent` for `MovementSystem` to act on.
+pub struct AiSystem {
+    log: Logger,
+    // Always seeded the same way, so that simulations are repeatable.
+    rng: XorShiftRng,
+}
+
+impl AiSystem {
+    pub fn new(parent_log: &Logger) -> AiSystem {
+        AiSystem {
+            log: parent_log.new(o!()),
+            rng: XorShiftRng::new_unseeded(),
+        }
+    }
+
+    // Make up a path to somewhere nearby by taking a few steps
+    // in random directions.
+    fn plan_wander(
+        &mut self,
+        cd: &CellDweller,
+        globe: &Globe,
+        materials: &MaterialRegistry,
+    ) -> Vec<(CellPos, Dir)> {
+        let resolution = globe.spec().root_resolution;
+        let mut pos = cd.pos;
+        let mut dir = cd.dir;
+        let mut last_turn_bias = cd.last_turn_bias;
+        let mut path = vec![(pos, dir)];
+        for _ in 0..WANDER_STEPS {
+            let turn_dir = if self.rng.gen() { TurnDir::Left } else { TurnDir::Right };
+            let turn_count = self.rng.gen_range(0, 3);
+            for _ in 0..turn_count {
+                turn_by_one_hex_edge(&mut pos, &mut dir, resolution, turn_dir)
+                    .expect("CellDweller should have been in good state.");
+                path.push((pos, dir));
+            }
+            let step = walkable_step(
+                globe,
+                materials,
+                pos,
+                dir,
+                last_turn_bias,
+                ForwardOrBackward::Forward,
+            );
+            let (new_pos, new_dir, new_last_turn_bias) = match step {
+                Some(step) => step,
+                // Can't go that way; maybe we'll turn somewhere better next time.
+                None => continue,
+            };
+            match navigation::landing_pos(globe, materials, new_pos) {
+                Some(landing_pos) => {
+                    pos = landing_pos;
+                    dir = new_dir;
+                    last_turn_bias = new_last_turn_bias;
+                    path.push((pos, dir));
+                },
+                None => break,
+            }
+        }
+        path
+    }
+
+    fn plan_path_to(
+        &self,
+        cd: &CellDweller,
+        globe: &Globe,
+        materials: &MaterialRegistry,
+        target: CellPos,
+    ) -> Vec<(CellPos, Dir)> {
+        match navigation::find_path(globe, materials, cd.pos, cd.dir, cd.last_turn_bias, target, MAX_CELLS_TO_SEARCH) {
+            Some(path) => path,
+            None => {
+                debug!(self.log, "Couldn't find a path"; "from" => format!("{:?}", cd.pos), "to" => format!("{:?}", target));
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl specs::System<TimeDelta> for AiSystem {
+    fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
+        use specs::Join;
+        let (cell_dwellers, mut behaviours, mut intents, globes, materials) = arg.fetch(|w|
+            (
+                w.read::<CellDweller>(),
+                w.write::<Behaviour>(),
+                w.write::<MovementIntent>(),
+                w.read::<Globe>(),
+                w.read_resource::<MaterialRegistry>(),
+            )
+        );
+        for (cd, behaviour, intent) in (&cell_dwellers, &mut behaviours, &mut intents).iter() {
+            // Stand still unless we decide otherwise below.
+            intent.clear();
+
+            // Get the associated globe, complaining loudly if we fail.
+            let globe_entity = match cd.globe_entity {
+                Some(globe_entity) => globe_entity,
+                None => {
+                    warn!(self.log, "There was no associated globe entity or it wasn't actually a Globe! Can't proceed!");
+                    continue;
+                },
+            };
+            let globe = match globes.get(globe_entity) {
+                Some(globe) => globe,
+                None => {
+                    warn!(self.log, "The globe associated with this CellDweller is not alive! Can't proceed!");
+                    continue;
+                },
+            };
+            let resolution = globe.spec().root_resolution;
+
+            // Count down until we're allowed to look for a new path.
+            if behaviour.seconds_until_replan > 0.0 {
+                behaviour.seconds_until_replan = (behaviour.seconds_until_replan - dt).max(0.0);
+            }
+
+            // Work out where we are on the path we're following, if anywhere.
+            // We want the last state in our current cell, because any
+            // before that are for turning on the spot to get there.
+            let pos_in_owning_root = PosInOwningRoot::new(cd.pos, resolution);
+            let maybe_index = behaviour.path.iter().rposition(|&(pos, _)| {
+                PosInOwningRoot::new(pos, resolution) == pos_in_owning_root
+            });
+            let is_at_end_of_path = maybe_index.map_or(false, |index| index + 1 == behaviour.path.len());
+
+            // Decide whether we need a new path.
+            let target = match behaviour.goal() {
+                Goal::Idle => {
+                    behaviour.path.clear();
+                    continue;
+                },
+                Goal::Wander => None,
+                Goal::GoTo(target) => Some(target),
+                Goal::Follow(target_entity) => match cell_dwellers.get(target_entity) {
+                    Some(target_cd) => Some(target_cd.pos),
+                    None => {
+                        warn!(self.log, "The CellDweller we were following is gone; giving up");
+                        behaviour.set_goal(Goal::Idle);
+                        continue;
+                    },
+                },
+            };
+            let has_target_moved = match (target, behaviour.path.last()) {
+                (Some(target), Some(&(path_end, _))) => {
+                    PosInOwningRoot::new(target, resolution) != PosInOwningRoot::new(path_end, resolution)
+                },
+                _ => false,
+            };
+            let is_lost = maybe_index.is_none();
+            let needs_new_path =
+                is_lost ||
+                has_target_moved ||
+                (is_at_end_of_path && behaviour.goal() == Goal::Wander);
+            // Don't try to plan while falling; wait until we land.
+            let is_on_solid_ground = cd.pos.z > 0 && globe.loaded_cell(cd.pos.set_z(cd.pos.z - 1))
+                .map_or(false, |cell| materials.is_solid(cell.material));
+            if needs_new_path && is_on_solid_ground && behaviour.seconds_until_replan <= 0.0 {
+                behaviour.path = match target {
+                    Some(target) => self.plan_path_to(cd, globe, &materials, target),
+                    None => self.plan_wander(cd, globe, &materials),
+                };
+                behaviour.seconds_until_replan = SECONDS_BETWEEN_REPLANS;
+                trace!(self.log, "Planned new path"; "steps" => behaviour.path.len());
+            } else if is_lost {
+                // Wait until we're allowed to try again.
+                continue;
+            }
+
+            // Follow the path.
+            let index = match behaviour.path.iter().rposition(|&(pos, _)| {
+                PosInOwningRoot::new(pos, resolution) == pos_in_owning_root
+            }) {
+                Some(index) => index,
+                // Nowhere to go.
+                None => continue,
+            };
+            let remaining_steps = behaviour.path[index..].windows(2)
+                .filter(|states| {
+                    PosInOwningRoot::new(states[0].0, resolution) != PosInOwningRoot::new(states[1].0, resolution)
+                })
+                .count();
+            let close_enough = match behaviour.goal() {
+                Goal::Follow(_) => remaining_steps <= FOLLOW_DISTANCE,
+                _ => remaining_steps == 0,
+            };
+            if close_enough {
+                continue;
+            }
+
+            // Turn until we're facing the way the path says,
+            // and then step forward into the next cell.
+            let (_, path_dir) = behaviour.path[index];
+            if cd.dir == path_dir {
+                intent.step_forward = true;
+            } else {
+                match turn_toward(cd.pos, cd.dir, path_dir, resolution) {
+                    Some(TurnDir::Left) => intent.turn_left = true,
+                    Some(TurnDir::Right) => intent.turn_right = true,
+                    None => {
+                        // Something has gone wrong; find a new path.
+                        behaviour.path.clear();
+                    },
+                }
+            }
+        }
+    }
+}
+
+// Which way to turn to face `target_dir` soonest,
+// or `None` if turning on the spot won't get us there.
+fn turn_toward(pos: CellPos, dir: Dir, target_dir: Dir, resolution: [IntCoord; 2]) -> Option<TurnDir> {
+    let mut turned_pos = pos;
+    let mut turned_dir = dir;
+    for turn_count in 1..6 {
+        turn_by_one_hex_edge(&mut turned_pos, &mut turned_dir, resolution, TurnDir::Left)
+            .expect("CellDweller should have been in good state.");
+        if turned_dir == target_dir {
+            return Some(if turn_count <= 3 { TurnDir::Left } else { TurnDir::Right });
+        }
+    }
+    None
+}
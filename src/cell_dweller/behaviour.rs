@@ -0,0 +1,59 @@
+use specs;
+
+use types::*;
+use globe::{ CellPos, Dir };
+
+/// What an NPC `CellDweller` wants to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Goal {
+    /// Stand still.
+    Idle,
+    /// Amble around at random.
+    Wander,
+    /// Keep close to another `CellDweller`.
+    Follow(specs::Entity),
+    /// Walk to the given cell, and then stay there.
+    GoTo(CellPos),
+}
+
+/// Lets `AiSystem` control a `CellDweller`.
+///
+/// The `CellDweller` also needs a `MovementIntent`; that's what
+/// `AiSystem` fills in to make it move.
+pub struct Behaviour {
+    goal: Goal,
+    // TODO: make these private and use guts trait pattern to expose them to `AiSystem`.
+    // Path we're currently following, from `navigation::find_path`.
+    // Empty if we don't have one.
+    pub path: Vec<(CellPos, Dir)>,
+    // Count down until we're allowed to look for a new path,
+    // so we don't run the pathfinder every frame when there's
+    // nowhere to go.
+    pub seconds_until_replan: TimeDelta,
+}
+
+impl Behaviour {
+    pub fn new(goal: Goal) -> Behaviour {
+        Behaviour {
+            goal: goal,
+            path: Vec::new(),
+            seconds_until_replan: 0.0,
+        }
+    }
+
+    pub fn goal(&self) -> Goal {
+        self.goal
+    }
+
+    /// Start doing something else, forgetting about
+    /// any path we were following.
+    pub fn set_goal(&mut self, goal: Goal) {
+        self.goal = goal;
+        self.path.clear();
+        self.seconds_until_replan = 0.0;
+    }
+}
+
+impl specs::Component for Behaviour {
+    type Storage = specs::HashMapStorage<Behaviour>;
+}
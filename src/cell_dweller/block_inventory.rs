@@ -0,0 +1,49 @@
+use specs;
+
+use globe::MaterialId;
+
+/// A single block that a `CellDweller` has picked up,
+/// and can put back down somewhere else.
+///
+/// `MiningSystem` only lets `CellDweller`s that have one of these
+/// place blocks. Those without one can still pick blocks up,
+/// but the blocks are just thrown away.
+pub struct BlockInventory {
+    carried: Option<MaterialId>,
+}
+
+impl BlockInventory {
+    pub fn new() -> BlockInventory {
+        BlockInventory {
+            carried: None,
+        }
+    }
+
+    /// The material of the block being carried, if any.
+    pub fn carried(&self) -> Option<MaterialId> {
+        self.carried
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.carried.is_none()
+    }
+
+    /// Start carrying a block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if already carrying a block; there's only room for one.
+    pub fn put(&mut self, material: MaterialId) {
+        assert!(self.is_empty(), "Already carrying a block");
+        self.carried = Some(material);
+    }
+
+    /// Stop carrying the block, if any, and return its material.
+    pub fn take(&mut self) -> Option<MaterialId> {
+        self.carried.take()
+    }
+}
+
+impl specs::Component for BlockInventory {
+    type Storage = specs::HashMapStorage<BlockInventory>;
+}
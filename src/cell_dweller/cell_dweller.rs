@@ -105,6 +105,18 @@ impl CellDweller {
         self.is_real_space_transform_dirty = true;
     }
 
+    /// Look up (positive) or down (negative) by `tilt` radians,
+    /// but no further than straight up or down.
+    pub fn tilt(&mut self, tilt: f64) {
+        use std::f64;
+        if tilt == 0.0 {
+            return;
+        }
+        let max_pitch = f64::consts::FRAC_PI_2;
+        self.pitch = (self.pitch + tilt).max(-max_pitch).min(max_pitch);
+        self.is_real_space_transform_dirty = true;
+    }
+
     pub fn turn(&mut self, turn_dir: TurnDir) {
         turn_by_one_hex_edge(
             &mut self.pos,
@@ -132,9 +144,13 @@ impl CellDweller {
         // the center of the world already!
         let up = eye.to_vector();
         let aim = Vec3::new(0.0, self.yaw, 0.0);
+        // Rotating about x turns z (forward) toward -y (down),
+        // so positive pitch (looking up) is a negative rotation.
+        let tilt = Vec3::new(-self.pitch, 0.0, 0.0);
 
         let mut rotation = Rot3::new_observer_frame(&(target - eye), &up);
         rotation.prepend_rotation_mut(&aim);
+        rotation.prepend_rotation_mut(&tilt);
         Iso3::from_rotation_matrix(eye.to_vector(), rotation)
     }
 
@@ -153,3 +169,38 @@ impl CellDweller {
 impl specs::Component for CellDweller {
     type Storage = specs::HashMapStorage<CellDweller>;
 }
+
+#[cfg(test)]
+mod test {
+    use std::f64;
+    use slog;
+
+    use super::*;
+    use globe::{ Globe, CellPos, Dir };
+
+    // How much the way `cd` is facing points away from the globe.
+    fn upness(cd: &mut CellDweller) -> f64 {
+        use na::{ Rotate, Dot, Norm };
+        let transform = cd.get_real_transform_and_mark_as_clean();
+        let forward = transform.rotation.rotate(&Vec3::new(0.0, 0.0, 1.0));
+        forward.dot(&transform.translation.normalize())
+    }
+
+    #[test]
+    fn tilt_to_look_up_and_down() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let spec = Globe::new_small_flat(&log).spec();
+        let pos = CellPos::default().set_x(2).set_y(2).set_z(17);
+        let mut cd = CellDweller::new(pos, Dir::default(), spec, None);
+
+        let level = upness(&mut cd);
+        cd.tilt(0.5);
+        assert!(upness(&mut cd) > level);
+        cd.tilt(-1.0);
+        assert!(upness(&mut cd) < level);
+
+        // Can't look any further down than straight down.
+        cd.tilt(-10.0);
+        assert!((cd.pitch + f64::consts::FRAC_PI_2).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,12 @@
+use specs;
+
+/// Marks the `CellDweller` controlled by whoever is at this computer.
+///
+/// Only it gets steered by keyboard, mouse and gamepad input,
+/// and the camera follows it around.
+#[derive(Default)]
+pub struct ClientPlayer;
+
+impl specs::Component for ClientPlayer {
+    type Storage = specs::NullStorage<ClientPlayer>;
+}
@@ -0,0 +1,27 @@
+use specs;
+
+/// What a particular `CellDweller` is trying to do with blocks.
+///
+/// `MiningSystem` uses this instead of keyboard input from
+/// `MiningInputAdapter` for `CellDweller`s that have one; e.g.
+/// those controlled by a remote player.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct MiningIntent {
+    pub pick_up: bool,
+    pub place: bool,
+}
+
+impl MiningIntent {
+    pub fn new() -> MiningIntent {
+        MiningIntent::default()
+    }
+
+    /// Stop trying to pick anything up or put anything down.
+    pub fn clear(&mut self) {
+        *self = MiningIntent::default();
+    }
+}
+
+impl specs::Component for MiningIntent {
+    type Storage = specs::HashMapStorage<MiningIntent>;
+}
@@ -4,47 +4,50 @@ use slog::Logger;
 use piston::input::Input;
 
 use types::*;
-use super::CellDweller;
+use super::{ CellDweller, BlockInventory, MiningIntent, ClientPlayer };
 use ::movement::*;
-use globe::{ Globe, PosInOwningRoot };
-use globe::chunk::Material;
+use globe::{ Globe, CellPos, PosInOwningRoot, MaterialRegistry };
+use globe::material;
 use ::input_adapter;
+use ::input_bindings::{ Action, SharedInputBindings };
 
 // TODO: own file?
 pub struct MiningInputAdapter {
     sender: mpsc::Sender<MiningEvent>,
+    bindings: SharedInputBindings,
 }
 
 impl MiningInputAdapter {
-    pub fn new(sender: mpsc::Sender<MiningEvent>) -> MiningInputAdapter {
+    pub fn new(sender: mpsc::Sender<MiningEvent>, bindings: SharedInputBindings) -> MiningInputAdapter {
         MiningInputAdapter {
             sender: sender,
+            bindings: bindings,
         }
     }
 }
 
 impl input_adapter::InputAdapter for MiningInputAdapter {
     fn handle(&self, input_event: &Input) {
-        use piston::input::{ Button, PressEvent, ReleaseEvent };
-        use piston::input::keyboard::Key;
+        use piston::input::{ PressEvent, ReleaseEvent };
 
-        if let Some(Button::Keyboard(key)) = input_event.press_args() {
-            match key {
-                Key::U => self.sender.send(MiningEvent::PickUp(true)).unwrap(),
-                _ => (),
-            }
-        }
-        if let Some(Button::Keyboard(key)) = input_event.release_args() {
-            match key {
-                Key::U => self.sender.send(MiningEvent::PickUp(false)).unwrap(),
-                _ => (),
-            }
+        let (button, is_pressed) = match (input_event.press_args(), input_event.release_args()) {
+            (Some(button), _) => (button, true),
+            (_, Some(button)) => (button, false),
+            _ => return,
+        };
+        let action = self.bindings.read().unwrap().action(button);
+        match action {
+            Some(Action::PickUp) => self.sender.send(MiningEvent::PickUp(is_pressed)).unwrap(),
+            Some(Action::Place) => self.sender.send(MiningEvent::Place(is_pressed)).unwrap(),
+            _ => (),
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MiningEvent {
     PickUp(bool),
+    Place(bool),
 }
 
 pub struct MiningSystem {
@@ -55,6 +58,7 @@ pub struct MiningSystem {
     // But Piston seems to have some kind of silly key-repeat thing built in.
     // TODO: clarify.
     pick_up: bool,
+    place: bool,
 }
 
 impl MiningSystem {
@@ -63,6 +67,7 @@ impl MiningSystem {
             input_receiver: input_receiver,
             log: parent_log.new(o!()),
             pick_up: false,
+            place: false,
         }
     }
 
@@ -70,6 +75,7 @@ impl MiningSystem {
         loop {
             match self.input_receiver.try_recv() {
                 Ok(MiningEvent::PickUp(b)) => self.pick_up = b,
+                Ok(MiningEvent::Place(b)) => self.place = b,
                 Err(_) => return,
             }
         }
@@ -79,10 +85,17 @@ impl MiningSystem {
         &self,
         cd: &mut CellDweller,
         globe: &mut Globe,
+        materials: &MaterialRegistry,
+        inventory: Option<&mut BlockInventory>,
     ) {
+        // There's only room to carry one block at a time.
+        if let Some(ref inventory) = inventory {
+            if !inventory.is_empty() {
+                return;
+            }
+        }
+
         // Only allow picking stuff up if you're sitting above solid ground.
-        // (Or, rather, the stuff we consider to be solid for now,
-        // which is anything other than air.)
         //
         // TODO: abstract this whole thing... you need some kind of
         // utilities for a globe.
@@ -97,7 +110,7 @@ impl MiningSystem {
         {
             // Inner scope to fight borrowck.
             let under_cell = globe.maybe_non_authoritative_cell(under_pos);
-            if under_cell.material != Material::Dirt {
+            if !materials.is_solid(under_cell.material) {
                 return;
             }
         }
@@ -109,7 +122,7 @@ impl MiningSystem {
             .expect("CellDweller should have been in good state.");
         let anything_to_pick_up = {
             let cell = globe.maybe_non_authoritative_cell(new_pos);
-            cell.material == Material::Dirt
+            materials.is_minable(cell.material)
         };
         // Also require that there's air above the block;
         // in my initial use case I don't want to allow mining below
@@ -117,7 +130,7 @@ impl MiningSystem {
         let air_above_target = {
             let above_new_pos = new_pos.set_z(new_pos.z + 1);
             let cell = globe.maybe_non_authoritative_cell(above_new_pos);
-            cell.material == Material::Air
+            cell.material == material::AIR
         };
         let can_pick_up = anything_to_pick_up && air_above_target;
         if can_pick_up {
@@ -130,44 +143,99 @@ impl MiningSystem {
                 new_pos,
                 globe.spec().root_resolution
             );
-            globe.authoritative_cell_mut(
-                new_pos_in_owning_root
-            ).material = Material::Air;
-            // Bump the version of the chunk containing this cell.
-            // TODO: this lacks subtlety. Neighbors only actually
-            // care about edge chunks, so do you need multiple
-            // notions of version? Or _only_ have something like "edge_cells_version"
-            // for now?
-            globe.increment_chunk_version_for_cell(new_pos_in_owning_root);
-            // Propagate change to neighbouring chunks.
-            // TODO: we reaaaally need a better interface for mutating chunk data
-            // to make sure this happens automatically.
-            //
-            // TODO: also this is _stupidly_ slow hacks!
-            // Like... _really_ bad. Fixing the interface to cell data
-            // is now a high priority.
-            globe.copy_all_authoritative_cells();
-            // Mark the view for the containing chunk and those containing each cell surrounding
-            // it as being dirty. (This cell might affect the visibility of cells in those chunks.)
-            // TODO: different API where you commit to changing a cell
-            // in a closure you get back that has a reference to it?
-            // Or contains a _wrapper_ around it so it knows if you mutated it? Ooooh.
-            globe.mark_chunk_views_affected_by_cell_as_dirty(new_pos);
-            // TODO: remember on the cell-dweller that it's carrying something?
-            // Or should that be a different kind of component?
+            let mut new_cell = *globe.authoritative_cell(new_pos_in_owning_root);
+            let picked_up_material = new_cell.material;
+            new_cell.material = material::AIR;
+            globe.set_cell(new_pos_in_owning_root, new_cell);
+            if let Some(inventory) = inventory {
+                inventory.put(picked_up_material);
+            }
             debug!(self.log, "Picked up block"; "pos" => format!("{:?}", new_pos));
         }
     }
+
+    fn place_if_possible(
+        &self,
+        cd: &mut CellDweller,
+        globe: &mut Globe,
+        materials: &MaterialRegistry,
+        inventory: &mut BlockInventory,
+        occupied_positions: &[CellPos],
+    ) {
+        let carried_material = match inventory.carried() {
+            Some(carried_material) => carried_material,
+            // Nothing to put down.
+            None => return,
+        };
+
+        // Only allow putting stuff down if you're sitting above solid ground.
+        if cd.pos.z < 1 {
+            // There's nothing below; someone built a silly globe.
+            return;
+        }
+        let under_pos = cd.pos.set_z(cd.pos.z - 1);
+        {
+            // Inner scope to fight borrowck.
+            let under_cell = globe.maybe_non_authoritative_cell(under_pos);
+            if !materials.is_solid(under_cell.material) {
+                return;
+            }
+        }
+
+        // Find the cell in front of us to put the block into.
+        let mut new_pos = cd.pos;
+        let mut new_dir = cd.dir;
+        move_forward(&mut new_pos, &mut new_dir, globe.spec().root_resolution)
+            .expect("CellDweller should have been in good state.");
+        let is_empty = {
+            let cell = globe.maybe_non_authoritative_cell(new_pos);
+            cell.material == material::AIR
+        };
+        // Blocks can't float in the air; there needs to be
+        // something solid underneath.
+        let is_supported = {
+            let under_new_pos = new_pos.set_z(new_pos.z - 1);
+            let cell = globe.maybe_non_authoritative_cell(under_new_pos);
+            materials.is_solid(cell.material)
+        };
+        // Don't bury anyone.
+        let resolution = globe.spec().root_resolution;
+        let new_pos_in_owning_root = PosInOwningRoot::new(new_pos, resolution);
+        let is_occupied = occupied_positions.iter().any(|occupied_pos| {
+            PosInOwningRoot::new(*occupied_pos, resolution) == new_pos_in_owning_root
+        });
+        let can_place = is_empty && is_supported && !is_occupied;
+        if can_place {
+            let mut new_cell = *globe.authoritative_cell(new_pos_in_owning_root);
+            new_cell.material = carried_material;
+            globe.set_cell(new_pos_in_owning_root, new_cell);
+            inventory.take();
+            debug!(self.log, "Placed block"; "pos" => format!("{:?}", new_pos));
+        }
+    }
 }
 
 impl specs::System<TimeDelta> for MiningSystem {
     fn run(&mut self, arg: specs::RunArg, _dt: TimeDelta) {
         use specs::Join;
         self.consume_input();
-        let (mut cell_dwellers, mut globes) = arg.fetch(|w|
-            (w.write::<CellDweller>(), w.write::<Globe>())
+        let (mut cell_dwellers, mut globes, mut inventories, intents, client_players, entities, materials) = arg.fetch(|w|
+            (
+                w.write::<CellDweller>(),
+                w.write::<Globe>(),
+                w.write::<BlockInventory>(),
+                w.read::<MiningIntent>(),
+                w.read::<ClientPlayer>(),
+                w.entities(),
+                w.read_resource::<MaterialRegistry>(),
+            )
         );
-        for cd in (&mut cell_dwellers).iter() {
+        // Remember where everyone is, so we don't place
+        // blocks on top of anyone.
+        let dweller_positions: Vec<(Option<specs::Entity>, CellPos)> = (&cell_dwellers).iter()
+            .map(|cd| (cd.globe_entity, cd.pos))
+            .collect();
+        for (cd, entity) in (&mut cell_dwellers, &entities).iter() {
             // Get the associated globe, complaining loudly if we fail.
             let globe_entity = match cd.globe_entity {
                 Some(globe_entity) => globe_entity,
@@ -184,12 +252,160 @@ impl specs::System<TimeDelta> for MiningSystem {
                 },
             };
 
-            if self.pick_up {
+            // `CellDweller`s with their own intent ignore the keyboard.
+            // So does everyone else but the local player; e.g. a
+            // player on a server whose first intents haven't arrived yet
+            // shouldn't start digging because someone pressed a key
+            // on the server.
+            let (pick_up, place) = match intents.get(entity) {
+                Some(intent) => (intent.pick_up, intent.place),
+                None if client_players.get(entity).is_some() => (self.pick_up, self.place),
+                None => (false, false),
+            };
+
+            if place {
+                if let Some(inventory) = inventories.get_mut(entity) {
+                    let occupied_positions: Vec<CellPos> = dweller_positions.iter()
+                        .filter(|&&(other_globe_entity, _)| other_globe_entity == cd.globe_entity)
+                        .map(|&(_, pos)| pos)
+                        .collect();
+                    self.place_if_possible(
+                        cd,
+                        globe,
+                        &materials,
+                        inventory,
+                        &occupied_positions,
+                    );
+                }
+            }
+
+            if pick_up {
                 self.pick_up_if_possible(
                     cd,
                     globe,
+                    &materials,
+                    inventories.get_mut(entity),
                 );
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use slog;
+
+    use super::*;
+    use globe::{ ChunkSystem, Dir };
+
+    fn discard_log() -> slog::Logger {
+        let drain = slog::Discard;
+        slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")))
+    }
+
+    // The top of the flat land is at z = 16,
+    // so this is where you stand on it.
+    const SURFACE_Z: i64 = 17;
+
+    fn in_front_of(cd: &CellDweller, globe: &Globe) -> PosInOwningRoot {
+        let mut pos = cd.pos;
+        let mut dir = cd.dir;
+        move_forward(&mut pos, &mut dir, globe.spec().root_resolution)
+            .expect("CellDweller should have been in good state.");
+        PosInOwningRoot::new(pos, globe.spec().root_resolution)
+    }
+
+    fn dig_out(globe: &mut Globe, pos: CellPos) {
+        let pos = PosInOwningRoot::new(pos, globe.spec().root_resolution);
+        let mut cell = *globe.authoritative_cell(pos);
+        cell.material = material::AIR;
+        globe.set_cell(pos, cell);
+    }
+
+    #[test]
+    fn place_block_in_front() {
+        let log = discard_log();
+        let (_, input_receiver) = mpsc::channel();
+        let mining_sys = MiningSystem::new(input_receiver, &log);
+        let materials = MaterialRegistry::new();
+        let mut globe = Globe::new_small_flat(&log);
+        let pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+        ChunkSystem::new(&log, 1.0).load_chunks_near(&mut globe, &[pos]);
+        let mut cd = CellDweller::new(pos, Dir::default(), globe.spec(), None);
+        let mut inventory = BlockInventory::new();
+        inventory.put(material::DIRT);
+        let target_pos = in_front_of(&cd, &globe);
+        assert_eq!(material::AIR, globe.authoritative_cell(target_pos).material);
+
+        mining_sys.place_if_possible(&mut cd, &mut globe, &materials, &mut inventory, &[]);
+        assert_eq!(material::DIRT, globe.authoritative_cell(target_pos).material);
+        assert!(inventory.is_empty());
+    }
+
+    #[test]
+    fn cant_place_block_without_support() {
+        let log = discard_log();
+        let (_, input_receiver) = mpsc::channel();
+        let mining_sys = MiningSystem::new(input_receiver, &log);
+        let materials = MaterialRegistry::new();
+        let mut globe = Globe::new_small_flat(&log);
+        let pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+        ChunkSystem::new(&log, 1.0).load_chunks_near(&mut globe, &[pos]);
+        let mut cd = CellDweller::new(pos, Dir::default(), globe.spec(), None);
+        let mut inventory = BlockInventory::new();
+        inventory.put(material::DIRT);
+        let target_pos = in_front_of(&cd, &globe);
+
+        // Take away the ground the block would sit on.
+        let under_target_pos: CellPos = target_pos.into();
+        dig_out(&mut globe, under_target_pos.set_z(SURFACE_Z - 1));
+
+        mining_sys.place_if_possible(&mut cd, &mut globe, &materials, &mut inventory, &[]);
+        assert_eq!(material::AIR, globe.authoritative_cell(target_pos).material);
+        assert_eq!(Some(material::DIRT), inventory.carried());
+    }
+
+    #[test]
+    fn cant_place_block_without_standing_on_solid_ground() {
+        let log = discard_log();
+        let (_, input_receiver) = mpsc::channel();
+        let mining_sys = MiningSystem::new(input_receiver, &log);
+        let materials = MaterialRegistry::new();
+        let mut globe = Globe::new_small_flat(&log);
+        let pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+        ChunkSystem::new(&log, 1.0).load_chunks_near(&mut globe, &[pos]);
+        let mut cd = CellDweller::new(pos, Dir::default(), globe.spec(), None);
+        let mut inventory = BlockInventory::new();
+        inventory.put(material::DIRT);
+        let target_pos = in_front_of(&cd, &globe);
+
+        // Take away the ground we're standing on.
+        dig_out(&mut globe, pos.set_z(SURFACE_Z - 1));
+
+        mining_sys.place_if_possible(&mut cd, &mut globe, &materials, &mut inventory, &[]);
+        assert_eq!(material::AIR, globe.authoritative_cell(target_pos).material);
+        assert_eq!(Some(material::DIRT), inventory.carried());
+    }
+
+    #[test]
+    fn cant_bury_another_cell_dweller() {
+        let log = discard_log();
+        let (_, input_receiver) = mpsc::channel();
+        let mining_sys = MiningSystem::new(input_receiver, &log);
+        let materials = MaterialRegistry::new();
+        let mut globe = Globe::new_small_flat(&log);
+        let pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+        ChunkSystem::new(&log, 1.0).load_chunks_near(&mut globe, &[pos]);
+        let mut cd = CellDweller::new(pos, Dir::default(), globe.spec(), None);
+        let mut inventory = BlockInventory::new();
+        inventory.put(material::DIRT);
+        let target_pos = in_front_of(&cd, &globe);
+
+        // Someone else is standing right where the block would go.
+        let occupied_positions = [cd.pos, target_pos.into()];
+        mining_sys.place_if_possible(&mut cd, &mut globe, &materials, &mut inventory, &occupied_positions);
+        assert_eq!(material::AIR, globe.authoritative_cell(target_pos).material);
+        assert_eq!(Some(material::DIRT), inventory.carried());
+    }
+}
@@ -2,8 +2,30 @@ mod cell_dweller;
 mod movement_system;
 mod mining_system;
 mod physics_system;
+mod block_inventory;
+mod movement_intent;
+mod mining_intent;
+mod behaviour;
+mod ai_system;
+mod client_player;
 
 pub use self::cell_dweller::{ CellDweller };
-pub use self::movement_system::{ MovementSystem, MovementEvent, MovementInputAdapter };
+pub use self::movement_system::{
+    MovementSystem,
+    MovementEvent,
+    MovementInputAdapter,
+    ForwardOrBackward,
+    UpOrDown,
+    walkable_step,
+    swimmable_step,
+    is_submerged,
+    is_at_surface,
+};
 pub use self::mining_system::{ MiningSystem, MiningEvent, MiningInputAdapter };
 pub use self::physics_system::PhysicsSystem;
+pub use self::block_inventory::BlockInventory;
+pub use self::movement_intent::MovementIntent;
+pub use self::mining_intent::MiningIntent;
+pub use self::behaviour::{ Behaviour, Goal };
+pub use self::ai_system::AiSystem;
+pub use self::client_player::ClientPlayer;
@@ -0,0 +1,34 @@
+use specs;
+
+/// How a particular `CellDweller` is trying to move.
+///
+/// `MovementSystem` moves `CellDweller`s that have one of these
+/// according to what it says, instead of according to keyboard input
+/// from `MovementInputAdapter`. This lets several `CellDweller`s move
+/// independently; something else, like `AiSystem`, decides what to
+/// put in it.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct MovementIntent {
+    pub step_forward: bool,
+    pub step_backward: bool,
+    pub turn_left: bool,
+    pub turn_right: bool,
+    /// Only does anything while in a liquid.
+    pub swim_up: bool,
+    pub swim_down: bool,
+}
+
+impl MovementIntent {
+    pub fn new() -> MovementIntent {
+        MovementIntent::default()
+    }
+
+    /// Stop trying to go anywhere.
+    pub fn clear(&mut self) {
+        *self = MovementIntent::default();
+    }
+}
+
+impl specs::Component for MovementIntent {
+    type Storage = specs::HashMapStorage<MovementIntent>;
+}
@@ -5,92 +5,284 @@ use slog::Logger;
 use piston::input::Input;
 
 use types::*;
-use super::CellDweller;
+use super::{ CellDweller, MovementIntent, ClientPlayer };
 use ::Spatial;
 use ::movement::*;
-use globe::Globe;
-use globe::chunk::Material;
+use globe::{ Globe, CellPos, Dir, MaterialRegistry };
 use ::input_adapter;
+use ::input_bindings::{ Action, SharedInputBindings };
+
+// How far to turn or tilt for each pixel the mouse moves in mouse-look mode.
+const MOUSE_LOOK_RADIANS_PER_PIXEL: f64 = 0.003;
+// Which gamepad axes control panning and stepping.
+// TODO: let `InputBindings` choose these too.
+const PAN_AXIS: u8 = 0;
+const STEP_AXIS: u8 = 1;
+// Ignore gamepad sticks that are only this far off center;
+// they never quite come to rest in the middle.
+const STICK_DEAD_ZONE: f64 = 0.2;
 
 // TODO: own file?
 pub struct MovementInputAdapter {
     sender: mpsc::Sender<MovementEvent>,
+    bindings: SharedInputBindings,
 }
 
 impl MovementInputAdapter {
-    pub fn new(sender: mpsc::Sender<MovementEvent>) -> MovementInputAdapter {
+    pub fn new(sender: mpsc::Sender<MovementEvent>, bindings: SharedInputBindings) -> MovementInputAdapter {
         MovementInputAdapter {
             sender: sender,
+            bindings: bindings,
         }
     }
 }
 
 impl input_adapter::InputAdapter for MovementInputAdapter {
     fn handle(&self, input_event: &Input) {
-        use piston::input::{ Button, PressEvent, ReleaseEvent };
-        use piston::input::keyboard::Key;
-
-        if let Some(Button::Keyboard(key)) = input_event.press_args() {
-            match key {
-                Key::I => self.sender.send(MovementEvent::StepForward(true)).unwrap(),
-                Key::K => self.sender.send(MovementEvent::StepBackward(true)).unwrap(),
-                Key::J => self.sender.send(MovementEvent::TurnLeft(true)).unwrap(),
-                Key::L => self.sender.send(MovementEvent::TurnRight(true)).unwrap(),
-                _ => (),
-            }
+        use piston::input::{ PressEvent, ReleaseEvent, MouseRelativeEvent, ControllerAxisEvent };
+
+        // `App` only passes relative mouse motion on to us in mouse-look mode.
+        if let Some(motion) = input_event.mouse_relative_args() {
+            self.sender.send(MovementEvent::Look {
+                // Moving the mouse to the right should turn right.
+                yaw: -motion[0] * MOUSE_LOOK_RADIANS_PER_PIXEL,
+                // Screen coordinates go down, but pitch goes up.
+                pitch: -motion[1] * MOUSE_LOOK_RADIANS_PER_PIXEL,
+            }).unwrap();
+            return;
         }
-        if let Some(Button::Keyboard(key)) = input_event.release_args() {
-            match key {
-                Key::I => self.sender.send(MovementEvent::StepForward(false)).unwrap(),
-                Key::K => self.sender.send(MovementEvent::StepBackward(false)).unwrap(),
-                Key::J => self.sender.send(MovementEvent::TurnLeft(false)).unwrap(),
-                Key::L => self.sender.send(MovementEvent::TurnRight(false)).unwrap(),
+
+        if let Some(axis_args) = input_event.controller_axis_args() {
+            let position = if axis_args.position.abs() < STICK_DEAD_ZONE {
+                0.0
+            } else {
+                axis_args.position.max(-1.0).min(1.0)
+            };
+            // Pushing a stick right or up gives a positive position
+            // for x, but negative for y; we want to pan right (negative)
+            // or step forward (positive) respectively.
+            match axis_args.axis {
+                PAN_AXIS => self.sender.send(MovementEvent::PanRate(-position)).unwrap(),
+                STEP_AXIS => self.sender.send(MovementEvent::StepRate(-position)).unwrap(),
                 _ => (),
             }
+            return;
+        }
+
+        let (button, is_pressed) = match (input_event.press_args(), input_event.release_args()) {
+            (Some(button), _) => (button, true),
+            (_, Some(button)) => (button, false),
+            _ => return,
+        };
+        let action = self.bindings.read().unwrap().action(button);
+        match action {
+            Some(Action::StepForward) => self.sender.send(MovementEvent::StepForward(is_pressed)).unwrap(),
+            Some(Action::StepBackward) => self.sender.send(MovementEvent::StepBackward(is_pressed)).unwrap(),
+            Some(Action::TurnLeft) => self.sender.send(MovementEvent::TurnLeft(is_pressed)).unwrap(),
+            Some(Action::TurnRight) => self.sender.send(MovementEvent::TurnRight(is_pressed)).unwrap(),
+            Some(Action::SwimUp) => self.sender.send(MovementEvent::SwimUp(is_pressed)).unwrap(),
+            Some(Action::SwimDown) => self.sender.send(MovementEvent::SwimDown(is_pressed)).unwrap(),
+            _ => (),
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MovementEvent {
     StepForward(bool),
     StepBackward(bool),
     TurnLeft(bool),
     TurnRight(bool),
+    /// Swim up or down; only does anything while in a liquid.
+    SwimUp(bool),
+    SwimDown(bool),
+    /// How fast to step when neither `StepForward` nor `StepBackward`
+    /// is held, from 1.0 (full speed forward) to -1.0 (full speed
+    /// backward); e.g. from a gamepad stick.
+    StepRate(f64),
+    /// How fast to pan, from 1.0 (full speed left) to -1.0
+    /// (full speed right); e.g. from a gamepad stick.
+    PanRate(f64),
+    /// Turn and tilt by this many radians; e.g. from moving the mouse.
+    Look { yaw: f64, pitch: f64 },
 }
 
 pub struct MovementSystem {
     input_receiver: mpsc::Receiver<MovementEvent>,
     log: Logger,
-    step_forward: bool,
-    step_backward: bool,
-    turn_left: bool,
-    turn_right: bool,
+    // What the keyboard says to do; applies to the local player,
+    // unless they have their own `MovementIntent`.
+    input_intent: MovementIntent,
+    // Analog input; see `MovementEvent::StepRate` and `PanRate`.
+    input_step_rate: f64,
+    input_pan_rate: f64,
+    // Mouse-look yaw and pitch that haven't been applied yet.
+    input_look: (f64, f64),
 }
 
-enum ForwardOrBackward {
+#[derive(Clone, Copy)]
+pub enum ForwardOrBackward {
     Forward,
     Backward,
 }
 
+#[derive(Clone, Copy)]
+pub enum UpOrDown {
+    Up,
+    Down,
+}
+
+/// Work out where a `CellDweller` at `pos` facing `dir` would end up
+/// if it tried to step forward or backward, or `None` if it can't.
+///
+/// They can only step while standing on solid ground or swimming
+/// in a liquid, and can step up onto terrain one cell higher, but
+/// no more. This doesn't include falling off ledges; that's
+/// `PhysicsSystem`'s job.
+///
+/// Cells in chunks that aren't loaded count as impassable.
+pub fn walkable_step(
+    globe: &Globe,
+    materials: &MaterialRegistry,
+    pos: CellPos,
+    dir: Dir,
+    last_turn_bias: TurnDir,
+    forward_or_backward: ForwardOrBackward,
+) -> Option<(CellPos, Dir, TurnDir)> {
+    let is_passable = |pos: CellPos| {
+        globe.loaded_cell(pos).map_or(false, |cell| !materials.is_solid(cell.material))
+    };
+
+    // Only allow movement if you're sitting above solid ground,
+    // or swimming.
+    if pos.z < 1 {
+        // There's nothing below; someone built a silly globe.
+        return None;
+    }
+    // TODO: this reveals that functions like `set_z`
+    // are misleading; this implicitly copies--
+    // not changes the orignal!
+    let under_pos = pos.set_z(pos.z - 1);
+    let is_on_solid_ground = globe.loaded_cell(under_pos)
+        .map_or(false, |cell| materials.is_solid(cell.material));
+    if !is_on_solid_ground && !is_submerged(globe, materials, pos) {
+        return None;
+    }
+
+    // Find out whether we're actually allowed to step there.
+    let mut new_pos = pos;
+    let mut new_dir = dir;
+    let mut new_last_turn_bias = last_turn_bias;
+
+    match forward_or_backward {
+        ForwardOrBackward::Forward => {
+            step_forward_and_face_neighbor(&mut new_pos, &mut new_dir, globe.spec().root_resolution, &mut new_last_turn_bias)
+        },
+        ForwardOrBackward::Backward => {
+            step_backward_and_face_neighbor(&mut new_pos, &mut new_dir, globe.spec().root_resolution, &mut new_last_turn_bias)
+        },
+    }.expect("CellDweller should have been in good state.");
+
+    // Ask the globe if we can go there.
+    if is_passable(new_pos) {
+        return Some((new_pos, new_dir, new_last_turn_bias));
+    }
+
+    // If we can't move there, then try exactly one
+    // cell up as well; we want to allow stepping up
+    // terrain by one cell, but not more.
+    new_pos.z += 1;
+    if is_passable(new_pos) {
+        return Some((new_pos, new_dir, new_last_turn_bias));
+    }
+
+    None
+}
+
+/// Work out where a `CellDweller` at `pos` would end up if it tried
+/// to swim up or down, or `None` if it can't.
+///
+/// They can only swim up while in a liquid, but that includes coming
+/// up for air; once they're at the surface (see `is_at_surface`) there's
+/// nowhere further up to go. They can swim down while in a liquid or from
+/// just above one, e.g. to dive in from the surface. Sinking without trying
+/// is `PhysicsSystem`'s job.
+///
+/// Cells in chunks that aren't loaded count as impassable.
+pub fn swimmable_step(
+    globe: &Globe,
+    materials: &MaterialRegistry,
+    pos: CellPos,
+    up_or_down: UpOrDown,
+) -> Option<CellPos> {
+    let new_pos = match up_or_down {
+        UpOrDown::Up => pos.set_z(pos.z + 1),
+        UpOrDown::Down => {
+            if pos.z < 1 {
+                // There's nothing below; someone built a silly globe.
+                return None;
+            }
+            pos.set_z(pos.z - 1)
+        },
+    };
+    let is_swimming = match up_or_down {
+        UpOrDown::Up => is_submerged(globe, materials, pos),
+        UpOrDown::Down => is_submerged(globe, materials, pos) || is_submerged(globe, materials, new_pos),
+    };
+    let is_passable = globe.loaded_cell(new_pos)
+        .map_or(false, |cell| !materials.is_solid(cell.material));
+    if is_swimming && is_passable {
+        Some(new_pos)
+    } else {
+        None
+    }
+}
+
+/// Whether the cell at `pos` is full of liquid.
+///
+/// Cells in chunks that aren't loaded count as dry.
+pub fn is_submerged(globe: &Globe, materials: &MaterialRegistry, pos: CellPos) -> bool {
+    globe.loaded_cell(pos).map_or(false, |cell| materials.is_liquid(cell.material))
+}
+
+/// Whether `pos` is just above a liquid, but not in it;
+/// e.g. after swimming up for air.
+///
+/// Cells in chunks that aren't loaded count as dry.
+pub fn is_at_surface(globe: &Globe, materials: &MaterialRegistry, pos: CellPos) -> bool {
+    if pos.z < 1 || is_submerged(globe, materials, pos) {
+        return false;
+    }
+    is_submerged(globe, materials, pos.set_z(pos.z - 1))
+}
+
 impl MovementSystem {
     pub fn new(input_receiver: mpsc::Receiver<MovementEvent>, parent_log: &Logger) -> MovementSystem {
         MovementSystem {
             input_receiver: input_receiver,
             log: parent_log.new(o!()),
-            step_forward: false,
-            step_backward: false,
-            turn_left: false,
-            turn_right: false,
+            input_intent: MovementIntent::new(),
+            input_step_rate: 0.0,
+            input_pan_rate: 0.0,
+            input_look: (0.0, 0.0),
         }
     }
 
     fn consume_input(&mut self) {
         loop {
             match self.input_receiver.try_recv() {
-                Ok(MovementEvent::StepForward(b)) => self.step_forward = b,
-                Ok(MovementEvent::StepBackward(b)) => self.step_backward = b,
-                Ok(MovementEvent::TurnLeft(b)) => self.turn_left = b,
-                Ok(MovementEvent::TurnRight(b)) => self.turn_right = b,
+                Ok(MovementEvent::StepForward(b)) => self.input_intent.step_forward = b,
+                Ok(MovementEvent::StepBackward(b)) => self.input_intent.step_backward = b,
+                Ok(MovementEvent::TurnLeft(b)) => self.input_intent.turn_left = b,
+                Ok(MovementEvent::TurnRight(b)) => self.input_intent.turn_right = b,
+                Ok(MovementEvent::SwimUp(b)) => self.input_intent.swim_up = b,
+                Ok(MovementEvent::SwimDown(b)) => self.input_intent.swim_down = b,
+                Ok(MovementEvent::StepRate(rate)) => self.input_step_rate = rate,
+                Ok(MovementEvent::PanRate(rate)) => self.input_pan_rate = rate,
+                Ok(MovementEvent::Look { yaw, pitch }) => {
+                    self.input_look.0 += yaw;
+                    self.input_look.1 += pitch;
+                },
                 Err(_) => return,
             }
         }
@@ -100,68 +292,70 @@ impl MovementSystem {
         &self,
         cd: &mut CellDweller,
         globe: &Globe,
+        materials: &MaterialRegistry,
         forward_or_backward: ForwardOrBackward,
+        // Fraction of full speed, between 0.0 and 1.0.
+        speed: f64,
     ) {
-        // Only allow movement if you're sitting above solid ground.
-        // (Or, rather, the stuff we consider to be solid for now,
-        // which is anything other than air.)
-        if cd.pos.z < 0 {
-            // There's nothing below; someone built a silly globe.
-            return;
-        }
-        // TODO: this reveals that functions like `set_z`
-        // are misleading; this implicitly copies--
-        // not changes the orignal!
-        let under_pos = cd.pos.set_z(cd.pos.z - 1);
-        let under_cell = globe.maybe_non_authoritative_cell(under_pos);
-        if under_cell.material != Material::Dirt {
-            return;
-        }
-
-        // Find out whether we're actually allowed to step there.
-        let mut new_pos = cd.pos;
-        let mut new_dir = cd.dir;
-        let mut new_last_turn_bias = cd.last_turn_bias;
-
-        match forward_or_backward {
-            ForwardOrBackward::Forward => {
-                step_forward_and_face_neighbor(&mut new_pos, &mut new_dir, globe.spec().root_resolution, &mut new_last_turn_bias)
-            },
-            ForwardOrBackward::Backward => {
-                step_backward_and_face_neighbor(&mut new_pos, &mut new_dir, globe.spec().root_resolution, &mut new_last_turn_bias)
-            },
-        }.expect("CellDweller should have been in good state.");
-
-        // Ask the globe if we can go there.
-        let mut cell = globe.maybe_non_authoritative_cell(new_pos);
-        let mut can_move_to_cell = cell.material != Material::Dirt;
-
-        // If we can't move there, then try exactly one
-        // cell up as well; we want to allow stepping up
-        // terrain by one cell, but not more.
-        if !can_move_to_cell {
-            new_pos.z += 1;
-            cell = globe.maybe_non_authoritative_cell(new_pos);
-            can_move_to_cell = cell.material != Material::Dirt;
-        }
-
-        if can_move_to_cell {
+        let step = walkable_step(
+            globe,
+            materials,
+            cd.pos,
+            cd.dir,
+            cd.last_turn_bias,
+            forward_or_backward,
+        );
+        if let Some((new_pos, new_dir, new_last_turn_bias)) = step {
             cd.set_cell_transform(new_pos, new_dir, new_last_turn_bias);
             // REVISIT: += ?
-            cd.seconds_until_next_move = cd.seconds_between_moves;
+            cd.seconds_until_next_move = cd.seconds_between_moves / speed;
             trace!(self.log, "Stepped"; "new_pos" => format!("{:?}", cd.pos()), "new_dir" => format!("{:?}", cd.dir()));
         }
     }
+
+    fn swim_if_possible(
+        &self,
+        cd: &mut CellDweller,
+        globe: &Globe,
+        materials: &MaterialRegistry,
+        up_or_down: UpOrDown,
+    ) {
+        // Tread water; don't sink again before we've had a chance
+        // to swim some more. Leave a bit of slack, so it doesn't
+        // come down to which system gets to run first.
+        let seconds_treading_water = cd.seconds_between_moves * 2.0;
+        if let Some(new_pos) = swimmable_step(globe, materials, cd.pos, up_or_down) {
+            cd.set_cell_pos(new_pos);
+            // Swimming takes as long as stepping.
+            cd.seconds_until_next_move = cd.seconds_between_moves;
+            cd.seconds_until_next_fall = cd.seconds_until_next_fall.max(seconds_treading_water);
+            trace!(self.log, "Swam"; "new_pos" => format!("{:?}", cd.pos()));
+        } else if let UpOrDown::Up = up_or_down {
+            // Keep our head above water for as long as
+            // we keep trying to swim up.
+            if is_at_surface(globe, materials, cd.pos) {
+                cd.seconds_until_next_fall = cd.seconds_until_next_fall.max(seconds_treading_water);
+            }
+        }
+    }
 }
 
 impl specs::System<TimeDelta> for MovementSystem {
     fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
         use specs::Join;
         self.consume_input();
-        let (mut cell_dwellers, mut spatials, globes) = arg.fetch(|w|
-            (w.write::<CellDweller>(), w.write::<Spatial>(), w.read::<Globe>())
+        let (mut cell_dwellers, mut spatials, intents, client_players, globes, entities, materials) = arg.fetch(|w|
+            (
+                w.write::<CellDweller>(),
+                w.write::<Spatial>(),
+                w.read::<MovementIntent>(),
+                w.read::<ClientPlayer>(),
+                w.read::<Globe>(),
+                w.entities(),
+                w.read_resource::<MaterialRegistry>(),
+            )
         );
-        for (cd, spatial) in (&mut cell_dwellers, &mut spatials).iter() {
+        for (cd, spatial, entity) in (&mut cell_dwellers, &mut spatials, &entities).iter() {
             // Get the associated globe, complaining loudly if we fail.
             let globe_entity = match cd.globe_entity {
                 Some(globe_entity) => globe_entity,
@@ -178,6 +372,15 @@ impl specs::System<TimeDelta> for MovementSystem {
                 },
             };
 
+            // `CellDweller`s with their own intent (e.g. NPCs driven
+            // by `AiSystem`) ignore the keyboard, and so does everyone
+            // else but the local player.
+            let (intent, is_following_input) = match intents.get(entity) {
+                Some(intent) => (*intent, false),
+                None if client_players.get(entity).is_some() => (self.input_intent, true),
+                None => (MovementIntent::default(), false),
+            };
+
             // Count down until we're allowed to move next.
             if cd.seconds_until_next_move > 0.0 {
                 cd.seconds_until_next_move = (cd.seconds_until_next_move - dt).max(0.0);
@@ -186,9 +389,17 @@ impl specs::System<TimeDelta> for MovementSystem {
             // We can only step if forward XOR backward.
             // Otherwise we're not trying to go anywhere,
             // or we're trying to go both directions.
-            let forward_xor_backward = self.step_forward != self.step_backward;
-            if !still_waiting_to_move && forward_xor_backward {
-                let forward_or_backward = if self.step_forward {
+            let forward_xor_backward = intent.step_forward != intent.step_backward;
+            // Players can also step more slowly with a gamepad.
+            let step_rate = if forward_xor_backward {
+                if intent.step_forward { 1.0 } else { -1.0 }
+            } else if is_following_input {
+                self.input_step_rate
+            } else {
+                0.0
+            };
+            if !still_waiting_to_move && step_rate != 0.0 {
+                let forward_or_backward = if step_rate > 0.0 {
                     ForwardOrBackward::Forward
                 } else {
                     ForwardOrBackward::Backward
@@ -196,27 +407,60 @@ impl specs::System<TimeDelta> for MovementSystem {
                 self.step_if_possible(
                     cd,
                     globe,
+                    &materials,
                     forward_or_backward,
+                    step_rate.abs(),
                 );
             }
 
+            // Swim up XOR down, if we're not still busy stepping.
+            // Stepping comes first, so holding a step key as well only
+            // swims when there's nowhere to step; e.g. to climb out of
+            // the water up the side of a pool.
+            let still_waiting_to_move = cd.seconds_until_next_move > 0.0;
+            if !still_waiting_to_move && intent.swim_up != intent.swim_down {
+                let up_or_down = if intent.swim_up {
+                    UpOrDown::Up
+                } else {
+                    UpOrDown::Down
+                };
+                self.swim_if_possible(cd, globe, &materials, up_or_down);
+            }
+
             // Count down until we're allowed to turn next.
             if cd.seconds_until_next_turn > 0.0 {
                 cd.seconds_until_next_turn = (cd.seconds_until_next_turn - dt).max(0.0);
             }
             let still_waiting_to_turn = cd.seconds_until_next_turn > 0.0;
-            if !still_waiting_to_turn {
-                if self.turn_left && !self.turn_right  {
-                    //cd.turn(TurnDir::Left);
-                    cd.pan(f64::consts::PI * dt);
-                    //cd.seconds_until_next_turn = cd.seconds_between_turns;
-                    //trace!(self.log, "Turned left"; "new_pos" => format!("{:?}", cd.pos()), "new_dir" => format!("{:?}", cd.dir()));
-                } else if self.turn_right && !self.turn_left {
-                    cd.pan(-f64::consts::PI * dt);
-                    //cd.seconds_until_next_turn = cd.seconds_between_turns;
-                    //cd.turn(TurnDir::Right);
-                    //trace!(self.log, "Turned right"; "new_pos" => format!("{:?}", cd.pos()), "new_dir" => format!("{:?}", cd.dir()));
-                }
+            let maybe_turn_dir = if intent.turn_left && !intent.turn_right {
+                Some(TurnDir::Left)
+            } else if intent.turn_right && !intent.turn_left {
+                Some(TurnDir::Right)
+            } else {
+                None
+            };
+            match (still_waiting_to_turn, maybe_turn_dir) {
+                _ if is_following_input => {
+                    // Players get to pan smoothly, as fast as the keyboard
+                    // or gamepad says, and look around with the mouse.
+                    let keyboard_pan_rate = match maybe_turn_dir {
+                        Some(TurnDir::Left) => 1.0,
+                        Some(TurnDir::Right) => -1.0,
+                        None => 0.0,
+                    };
+                    let pan_rate = (keyboard_pan_rate + self.input_pan_rate).max(-1.0).min(1.0);
+                    cd.pan(pan_rate * f64::consts::PI * dt + self.input_look.0);
+                    cd.tilt(self.input_look.1);
+                },
+                (false, Some(turn_dir)) => {
+                    // Everyone else turns by a whole hex edge at a time,
+                    // so they always end up facing straight at a neighbor,
+                    // which is what `navigation` expects.
+                    cd.turn(turn_dir);
+                    cd.seconds_until_next_turn = cd.seconds_between_turns;
+                    trace!(self.log, "Turned"; "new_pos" => format!("{:?}", cd.pos()), "new_dir" => format!("{:?}", cd.dir()));
+                },
+                _ => (),
             }
 
             // Update real-space coordinates if necessary.
@@ -228,5 +472,8 @@ impl specs::System<TimeDelta> for MovementSystem {
                 spatial.transform = cd.get_real_transform_and_mark_as_clean();
             }
         }
+
+        // The local player has had a chance to look around now.
+        self.input_look = (0.0, 0.0);
     }
 }
@@ -2,10 +2,13 @@ use specs;
 use slog::Logger;
 
 use types::*;
-use super::CellDweller;
+use super::{ CellDweller, is_submerged };
 use ::Spatial;
-use globe::Globe;
-use globe::chunk::Material;
+use globe::{ Globe, CellPos, MaterialRegistry };
+
+// How many times longer it takes to sink through
+// a cell of liquid than to fall through a cell of air.
+const SINKING_SLOWDOWN: f64 = 4.0;
 
 pub struct PhysicsSystem {
     log: Logger,
@@ -20,13 +23,25 @@ impl PhysicsSystem {
         }
     }
 
+    fn seconds_between_falls_at(&self, globe: &Globe, materials: &MaterialRegistry, pos: CellPos) -> TimeDelta {
+        if is_submerged(globe, materials, pos) {
+            self.seconds_between_falls * SINKING_SLOWDOWN
+        } else {
+            self.seconds_between_falls
+        }
+    }
+
     // Fall under the force of gravity if there's anywhere to fall to.
     // Note that "gravity" moves you down at a constant speed;
     // i.e. it doesn't accelerate you like in the real world.
+    //
+    // Liquids hold you up a bit, so you sink through them more
+    // slowly; slowly enough to swim back up (see `MovementSystem`).
     fn maybe_fall(
         &self,
         cd: &mut CellDweller,
         globe: &Globe,
+        materials: &MaterialRegistry,
         dt: TimeDelta,
     ) {
         // Only make you fall if there's air below you.
@@ -39,11 +54,11 @@ impl PhysicsSystem {
         // not changes the orignal!
         let under_pos = cd.pos.set_z(cd.pos.z - 1);
         let under_cell = globe.maybe_non_authoritative_cell(under_pos);
-        if under_cell.material == Material::Dirt {
+        if materials.is_solid(under_cell.material) {
             // Reset time until we can fall to the time
             // between falls; we don't want to instantly
             // fall down every step of size 1.
-            cd.seconds_until_next_fall = self.seconds_between_falls;
+            cd.seconds_until_next_fall = self.seconds_between_falls_at(globe, materials, cd.pos);
             return;
         }
 
@@ -59,7 +74,7 @@ impl PhysicsSystem {
         // Move down by one cell.
         cd.set_cell_pos(under_pos);
         // REVISIT: += ?
-        cd.seconds_until_next_fall = self.seconds_between_falls;
+        cd.seconds_until_next_fall = self.seconds_between_falls_at(globe, materials, under_pos);
         trace!(self.log, "Fell under force of gravity"; "new_pos" => format!("{:?}", cd.pos()));
     }
 }
@@ -67,8 +82,8 @@ impl PhysicsSystem {
 impl specs::System<TimeDelta> for PhysicsSystem {
     fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
         use specs::Join;
-        let (mut cell_dwellers, mut spatials, globes) = arg.fetch(|w|
-            (w.write::<CellDweller>(), w.write::<Spatial>(), w.read::<Globe>())
+        let (mut cell_dwellers, mut spatials, globes, materials) = arg.fetch(|w|
+            (w.write::<CellDweller>(), w.write::<Spatial>(), w.read::<Globe>(), w.read_resource::<MaterialRegistry>())
         );
         for (cd, spatial) in (&mut cell_dwellers, &mut spatials).iter() {
             // Get the associated globe, complaining loudly if we fail.
@@ -87,7 +102,7 @@ impl specs::System<TimeDelta> for PhysicsSystem {
                 },
             };
 
-            self.maybe_fall(cd, globe, dt);
+            self.maybe_fall(cd, globe, &materials, dt);
 
             // Update real-space coordinates if necessary.
             // TODO: do this in a separate system; it needs to be done before
@@ -0,0 +1,294 @@
+use noise;
+
+use types::*;
+use super::spec::Spec;
+use super::{ CellPos, ChunkOrigin };
+use super::chunk::Cell;
+use super::material;
+use super::material::MaterialId;
+use super::gen::{ WorldGen, SimpleGen };
+use super::icosahedron::VERTICES;
+
+// Offsets added to the globe's seed for each of the noise
+// fields, so they don't all come out looking the same as
+// each other (or as the terrain).
+const TEMPERATURE_SEED_OFFSET: u32 = 1;
+const MOISTURE_SEED_OFFSET: u32 = 2;
+
+// How much the noise is allowed to push temperature around
+// compared to latitude. Latitude always wins near the poles
+// and the equator.
+const TEMPERATURE_NOISE_WEIGHT: f64 = 0.15;
+
+// How much colder it gets per unit of height above the ocean.
+// (Units are the same as `Spec::ocean_radius`.)
+const LAPSE_RATE: f64 = 2.5;
+
+// Columns below this temperature get snow caps, regardless
+// of anything else. Note that the poles are always below
+// this; see `TEMPERATURE_NOISE_WEIGHT`.
+const POLAR_TEMPERATURE: f64 = 0.2;
+const TUNDRA_TEMPERATURE: f64 = 0.35;
+const DESERT_TEMPERATURE: f64 = 0.6;
+const DESERT_MOISTURE: f64 = 0.35;
+
+// How far above the ocean (in cells) land counts as beach.
+const BEACH_CELLS: f64 = 2.0;
+
+/// Broad classification of the climate and terrain of a column
+/// of cells on the surface of a globe.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Biome {
+    /// Land below sea level.
+    Ocean,
+    /// Land just above sea level.
+    Beach,
+    /// Snow caps near the poles and on top of tall mountains.
+    Polar,
+    Tundra,
+    Desert,
+    Grassland,
+}
+
+/// A band of material at the top of a column of land.
+///
+/// Layers are stacked from the surface downwards;
+/// anything below the last layer is `material::ROCK`.
+pub struct Layer {
+    pub material: MaterialId,
+    /// Thickness of the layer in cells.
+    pub depth: i64,
+}
+
+impl Biome {
+    pub fn layers(&self) -> &'static [Layer] {
+        match *self {
+            Biome::Ocean => &OCEAN_LAYERS,
+            Biome::Beach => &BEACH_LAYERS,
+            Biome::Polar => &POLAR_LAYERS,
+            Biome::Tundra => &TUNDRA_LAYERS,
+            Biome::Desert => &DESERT_LAYERS,
+            Biome::Grassland => &GRASSLAND_LAYERS,
+        }
+    }
+
+    /// Material `depth` cells below the surface of a column
+    /// of land in this biome, where the topmost cell of land
+    /// has depth zero.
+    pub fn material_at_depth(&self, mut depth: i64) -> MaterialId {
+        for layer in self.layers() {
+            if depth < layer.depth {
+                return layer.material;
+            }
+            depth -= layer.depth;
+        }
+        material::ROCK
+    }
+}
+
+static OCEAN_LAYERS: [Layer; 2] = [
+    Layer { material: material::SAND, depth: 1 },
+    Layer { material: material::DIRT, depth: 2 },
+];
+static BEACH_LAYERS: [Layer; 1] = [
+    Layer { material: material::SAND, depth: 3 },
+];
+static POLAR_LAYERS: [Layer; 1] = [
+    Layer { material: material::SNOW, depth: 2 },
+];
+static TUNDRA_LAYERS: [Layer; 2] = [
+    Layer { material: material::SNOW, depth: 1 },
+    Layer { material: material::DIRT, depth: 2 },
+];
+static DESERT_LAYERS: [Layer; 1] = [
+    Layer { material: material::SAND, depth: 4 },
+];
+static GRASSLAND_LAYERS: [Layer; 2] = [
+    Layer { material: material::GRASS, depth: 1 },
+    Layer { material: material::DIRT, depth: 3 },
+];
+
+// Everything we need to know about a column of cells
+// to fill it in, calculated once per column.
+struct Column {
+    land_height: f64,
+    biome: Biome,
+}
+
+/// Terrain with climate.
+///
+/// Uses the same elevation as `SimpleGen`, but then classifies every
+/// column into a `Biome` based on its elevation, temperature, and moisture.
+/// Temperature mostly depends on latitude, so the poles are cold and
+/// the equator is hot, and gets colder the higher you go.
+///
+/// Everything is derived from `Spec::seed`, so the same spec
+/// will always produce the same terrain.
+pub struct BiomeGen {
+    spec: Spec,
+    elevation: SimpleGen,
+    // Permutation tables for noise
+    temperature_pt: noise::Seed,
+    moisture_pt: noise::Seed,
+    // Unit vector pointing at the north pole.
+    north: Vec3,
+}
+
+impl BiomeGen {
+    pub fn new(spec: Spec) -> BiomeGen {
+        assert!(spec.is_valid(), "Invalid globe spec!");
+        // The first vertex of the icosahedron is at the north pole;
+        // it's where every root quad starts.
+        let north = Vec3::new(VERTICES[0][0], VERTICES[0][1], VERTICES[0][2]);
+        BiomeGen {
+            spec: spec,
+            elevation: SimpleGen::new(spec),
+            temperature_pt: noise::Seed::new(spec.seed.wrapping_add(TEMPERATURE_SEED_OFFSET)),
+            moisture_pt: noise::Seed::new(spec.seed.wrapping_add(MOISTURE_SEED_OFFSET)),
+            north: north,
+        }
+    }
+
+    /// Temperature of the column containing `cell_pos` at sea level,
+    /// roughly from 0 (at the poles) to 1 (at the equator).
+    /// Ignores `cell_pos.z`.
+    pub fn sea_level_temperature_at(&self, cell_pos: CellPos) -> f64 {
+        use na::Dot;
+
+        let temperature_noise = noise::Brownian3::new(
+            noise::open_simplex3::<f64>, 4
+        ).wavelength(0.5);
+
+        let pt = self.spec.cell_center_on_unit_sphere(cell_pos);
+        // Cosine of the angle from the north pole;
+        // 1 at the north pole, 0 at the equator, and -1 at the south pole.
+        // Both poles should be cold, so ignore the sign.
+        let polarity = pt.as_vector().dot(&self.north).abs();
+        let wobble = temperature_noise.apply(&self.temperature_pt, pt.as_ref());
+        (1.0 - polarity) * (1.0 - TEMPERATURE_NOISE_WEIGHT)
+            + wobble * TEMPERATURE_NOISE_WEIGHT
+    }
+
+    /// Moisture of the column containing `cell_pos`,
+    /// roughly from 0 (dry) to 1 (wet). Ignores `cell_pos.z`.
+    pub fn moisture_at(&self, cell_pos: CellPos) -> f64 {
+        let moisture_noise = noise::Brownian3::new(
+            noise::open_simplex3::<f64>, 4
+        ).wavelength(0.7);
+
+        let pt = self.spec.cell_center_on_unit_sphere(cell_pos);
+        // Noise is roughly in [-1, 1].
+        (moisture_noise.apply(&self.moisture_pt, pt.as_ref()) + 1.0) / 2.0
+    }
+
+    /// Biome of the column containing `cell_pos`. Ignores `cell_pos.z`.
+    pub fn biome_at(&self, cell_pos: CellPos) -> Biome {
+        self.column_at(cell_pos).biome
+    }
+
+    fn column_at(&self, cell_pos: CellPos) -> Column {
+        let land_height = self.elevation.land_height(cell_pos);
+        let biome = self.classify(cell_pos, land_height);
+        Column {
+            land_height: land_height,
+            biome: biome,
+        }
+    }
+
+    fn classify(&self, cell_pos: CellPos, land_height: f64) -> Biome {
+        let height_above_ocean = land_height - self.spec.ocean_radius;
+        if height_above_ocean < 0.0 {
+            return Biome::Ocean;
+        }
+
+        let temperature = self.sea_level_temperature_at(cell_pos)
+            - height_above_ocean * LAPSE_RATE;
+        if temperature < POLAR_TEMPERATURE {
+            return Biome::Polar;
+        }
+
+        if height_above_ocean < self.spec.block_height * BEACH_CELLS {
+            return Biome::Beach;
+        }
+
+        if temperature < TUNDRA_TEMPERATURE {
+            return Biome::Tundra;
+        }
+
+        if temperature > DESERT_TEMPERATURE && self.moisture_at(cell_pos) < DESERT_MOISTURE {
+            return Biome::Desert;
+        }
+
+        Biome::Grassland
+    }
+
+    fn cell_in_column(&self, column: &Column, cell_pos: CellPos) -> Cell {
+        use na::Norm;
+
+        let cell_pt3 = self.spec.cell_center_center(cell_pos);
+        let cell_height = cell_pt3.as_vector().norm();
+        let material = if cell_height < column.land_height {
+            // Count cells down from the top of the land, so that
+            // the layers sit on the surface wherever it is.
+            let depth = ((column.land_height - cell_height) / self.spec.block_height).floor() as i64;
+            column.biome.material_at_depth(depth)
+        } else if cell_height < self.spec.ocean_radius {
+            material::WATER
+        } else {
+            material::AIR
+        };
+        Cell {
+            material: material,
+            // See `SimpleGen`; `Globe` fills this in.
+            shade: 1.0,
+            light: 0,
+            flow: 0,
+        }
+    }
+}
+
+impl WorldGen for BiomeGen {
+    fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    fn cell_at(&self, cell_pos: CellPos) -> Cell {
+        let column = self.column_at(cell_pos);
+        self.cell_in_column(&column, cell_pos)
+    }
+
+    fn land_height_at(&self, pt_on_unit_sphere: Pt3) -> f64 {
+        self.elevation.land_height_at(pt_on_unit_sphere)
+    }
+
+    fn fill_chunk(&self, origin: ChunkOrigin, cells: &mut [Cell]) {
+        assert_eq!(self.spec.cells_per_chunk(), cells.len());
+
+        // Working out the biome is a lot more expensive than
+        // working out what's in each cell once we know it,
+        // so only do it once for each column in the chunk.
+        let origin = *origin.pos();
+        let x_cells = self.spec.chunk_resolution[0] + 1;
+        let y_cells = self.spec.chunk_resolution[1] + 1;
+        for cell_y in origin.y..(origin.y + y_cells) {
+            for cell_x in origin.x..(origin.x + x_cells) {
+                let column_pos = CellPos {
+                    root: origin.root,
+                    x: cell_x,
+                    y: cell_y,
+                    z: origin.z,
+                };
+                let column = self.column_at(column_pos);
+                for cell_z in origin.z..(origin.z + self.spec.chunk_resolution[2]) {
+                    let cell_pos = column_pos.set_z(cell_z);
+                    // Cells are sorted by (z, y, x).
+                    let cell_index =
+                        (cell_z - origin.z) * y_cells * x_cells +
+                        (cell_y - origin.y) * x_cells +
+                        (cell_x - origin.x);
+                    cells[cell_index as usize] = self.cell_in_column(&column, cell_pos);
+                }
+            }
+        }
+    }
+}
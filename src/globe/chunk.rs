@@ -3,13 +3,7 @@ use std::collections::HashMap;
 use specs;
 use globe::{ IntCoord, CellPos, ChunkOrigin, PosInOwningRoot };
 use globe::origin_of_chunk_owning;
-
-#[derive(PartialEq, Eq, Copy, Clone)]
-pub enum Material {
-    Air,
-    Dirt,
-    Water,
-}
+use globe::MaterialId;
 
 // TODO: we should actually have multiple different
 // kinds of Voxmaps. "Chunk" should refer to the coarse
@@ -19,8 +13,16 @@ pub enum Material {
 
 #[derive(Clone, Copy)]
 pub struct Cell {
-    pub material: Material,
+    pub material: MaterialId,
     pub shade: f32,
+    // How brightly lit the cell is, from 0 to `lighting::MAX_LIGHT`.
+    // Not part of the content of the globe; it's worked out again
+    // from the cells around it before drawing; see `lighting`.
+    pub light: u8,
+    // For liquids, how many cells it has flowed sideways from
+    // a source; 0 is a source, which never dries up.
+    // Always 0 for anything else. See `LiquidSystem`.
+    pub flow: u8,
 }
 
 // Stores from (0, 0) to (chunk_resolution, chunk_resolution) _inclusive_.
@@ -28,6 +30,7 @@ pub struct Cell {
 // TODO: copy storage layout and cell ownership rules from:
 // <http://kiwi.atmos.colostate.edu/BUGS/geodesic/text.html>.
 // They seem to have a pretty good grasp on these things. :)
+#[derive(Clone)]
 pub struct Chunk {
     pub origin: ChunkOrigin,
     pub chunk_resolution: [IntCoord; 3],
@@ -157,6 +160,15 @@ impl Chunk {
         pos.z >= self.origin.pos().z && pos.z <= end_z
     }
 
+    /// Returns `true` if this chunk's authoritative data has been changed
+    /// since it was generated, or `false` otherwise.
+    ///
+    /// Chunks that have never been modified don't need to be saved,
+    /// because we can always generate them again.
+    pub fn is_modified(&self) -> bool {
+        self.version > 1
+    }
+
     /// Most `Chunks`s will have an associated `ChunkView`. Indicate that the
     /// chunk has been modified since the view was last updated.
     pub fn mark_view_as_dirty(&mut self) {
@@ -0,0 +1,27 @@
+use specs;
+
+use globe::CellPos;
+
+/// Keeps the `Chunk`s around it loaded.
+///
+/// `ChunkSystem` already treats every `CellDweller` as an anchor,
+/// so you only need this for other things that need to see the
+/// globe around them; e.g. a spawn point that nobody is standing
+/// on yet, or a camera flying around without a body.
+pub struct ChunkAnchor {
+    pub globe_entity: Option<specs::Entity>,
+    pub pos: CellPos,
+}
+
+impl ChunkAnchor {
+    pub fn new(globe_entity: Option<specs::Entity>, pos: CellPos) -> ChunkAnchor {
+        ChunkAnchor {
+            globe_entity: globe_entity,
+            pos: pos,
+        }
+    }
+}
+
+impl specs::Component for ChunkAnchor {
+    type Storage = specs::HashMapStorage<ChunkAnchor>;
+}
@@ -1,115 +1,477 @@
+use std::collections::{ HashMap, HashSet };
+use std::ops::{ Deref, DerefMut };
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, mpsc };
+
 use specs;
 use slog::Logger;
 
-use chrono::Duration;
-
-use rand;
-use rand::Rng;
-
 use types::*;
-use super::{ Globe, CellPos, ChunkOrigin };
+use super::{ Globe, CellPos, PosInOwningRoot, ChunkOrigin, ChunkAnchor, WorldGen, Neighbors };
+use super::origin_of_chunk_owning;
 use super::chunk::{ Chunk, Cell };
-use super::Root;
+use super::material;
+use super::spec::Spec;
+use cell_dweller::CellDweller;
+use worker_pool::{ WorkerPool, Job, Ticket };
+
+// Chunks are only unloaded once they are this much further away from
+// every anchor than the distance within which we load them.
+// This stops us from thrashing when an anchor wanders back and forth
+// across the edge of the loaded region.
+const UNLOAD_RADIUS_FACTOR: f64 = 1.25;
 
-// TODO: lift to module level.
-const ROOT_QUADS: u8 = 5;
+// Adding a chunk means copying cells to and from its neighbours,
+// so don't hold up the simulation by adding too many at once when
+// they're being generated in the background.
+const MAX_GENERATED_CHUNKS_ADDED_PER_TICK: usize = 8;
 
-// TODO: remove me
-const Z_CHUNKS: i64 = 5;
+// Give up on generating a chunk in the background if it
+// goes wrong this many times; it's probably not going to work.
+const MAX_GENERATION_ATTEMPTS: usize = 3;
 
 /// Loads and unloads `Chunk`s for a `Globe`.
 ///
-/// The `Chunk`s may be loaded from disk, or generated fresh if
-/// they have never existed before.
+/// Chunks that have been modified are taken back from the `Globe`,
+/// which keeps them in memory while they're unloaded (or since they
+/// were read from a save file); everything else is generated fresh.
+/// Unloading a chunk never writes it to disk; see `Globe::save_to`.
+///
+/// Keeps all chunks within a given radius of every `CellDweller`
+/// and `ChunkAnchor` loaded, and unloads any chunks that have been
+/// left behind.
+///
+/// By default new chunks are generated right away, on the simulation
+/// thread. Give it a `WorkerPool` with `set_worker_pool` to generate
+/// them in the background instead, closest to an anchor (e.g. the
+/// player) first; they'll be added to the globe a few at a time as
+/// they're finished.
 pub struct ChunkSystem {
     log: Logger,
-    // TEMP
-    have_built_chunks: bool,
+    // Distance in real space from an anchor to the middle of a chunk
+    // within which we'll make sure that chunk is loaded.
+    load_radius: f64,
+    // The chunk containing each anchor the last time we decided
+    // what chunks to load or unload. We only need to think about
+    // it again when one of these changes.
+    last_anchor_chunks: Vec<(specs::Entity, ChunkOrigin)>,
+    worker_pool: Option<Arc<WorkerPool>>,
+    // Chunks being generated in the background,
+    // keyed by globe entity and chunk origin.
+    pending_chunks: HashMap<(specs::Entity, ChunkOrigin), PendingChunk>,
+    next_job_id: u64,
+    generated_sender: mpsc::Sender<GeneratedChunk>,
+    generated_receiver: mpsc::Receiver<GeneratedChunk>,
+    // Finished chunks we haven't got around to adding yet.
+    ready_chunks: Vec<GeneratedChunk>,
+    // How many times generating each chunk in the background has
+    // panicked, so we don't keep trying forever.
+    failed_chunks: HashMap<(specs::Entity, ChunkOrigin), usize>,
+}
+
+struct PendingChunk {
+    ticket: Ticket,
+    // Only results from the most recent job for
+    // each chunk are wanted; see `GeneratedChunk`.
+    job_id: u64,
+    // From the nearest anchor, when we asked for it.
+    distance: f64,
+}
+
+// Cells for a new chunk, generated by a `GenerateChunkJob`.
+struct GeneratedChunk {
+    globe_entity: specs::Entity,
+    origin: ChunkOrigin,
+    job_id: u64,
+    // `None` if the generator panicked.
+    cells: Option<Vec<Cell>>,
+}
+
+struct GenerateChunkJob {
+    globe_entity: specs::Entity,
+    origin: ChunkOrigin,
+    job_id: u64,
+    gen: Arc<Box<WorldGen>>,
+    sender: mpsc::Sender<GeneratedChunk>,
+}
+
+impl Job for GenerateChunkJob {
+    fn run(self: Box<Self>) {
+        // Tell `ChunkSystem` if this goes wrong, rather than leaving
+        // it waiting for a chunk that will never turn up. The worker
+        // pool would carry on without us either way.
+        let gen = &**self.gen;
+        let origin = self.origin;
+        let cells = panic::catch_unwind(AssertUnwindSafe(|| generate_cells(gen, origin))).ok();
+        // Nobody might be listening any more if the
+        // game is shutting down; that's fine.
+        let _ = self.sender.send(GeneratedChunk {
+            globe_entity: self.globe_entity,
+            origin: self.origin,
+            job_id: self.job_id,
+            cells: cells,
+        });
+    }
 }
 
 impl ChunkSystem {
     pub fn new(
         parent_log: &Logger,
+        load_radius: f64,
     ) -> ChunkSystem {
+        let (generated_sender, generated_receiver) = mpsc::channel();
         ChunkSystem {
             log: parent_log.new(o!()),
-            have_built_chunks: false,
+            load_radius: load_radius,
+            last_anchor_chunks: Vec::new(),
+            worker_pool: None,
+            pending_chunks: HashMap::new(),
+            next_job_id: 0,
+            generated_sender: generated_sender,
+            generated_receiver: generated_receiver,
+            ready_chunks: Vec::new(),
+            failed_chunks: HashMap::new(),
+        }
+    }
+
+    /// Generate new chunks on `worker_pool`'s threads,
+    /// rather than on the simulation thread.
+    pub fn set_worker_pool(&mut self, worker_pool: Arc<WorkerPool>) {
+        self.worker_pool = Some(worker_pool);
+    }
+
+    fn unload_radius(&self) -> f64 {
+        self.load_radius * UNLOAD_RADIUS_FACTOR
+    }
+
+    /// Load (or generate) all chunks within the load radius of any
+    /// of the given anchor positions that aren't already loaded.
+    ///
+    /// This always happens right away, even if there's a worker pool.
+    pub fn load_chunks_near(&mut self, globe: &mut Globe, anchor_positions: &[CellPos]) {
+        let missing_chunks = self.missing_chunks_near(globe, anchor_positions);
+        if missing_chunks.is_empty() {
+            return;
+        }
+
+        debug!(self.log, "Loading chunks"; "chunks" => missing_chunks.len());
+        for &(origin, _) in &missing_chunks {
+            self.build_chunk(globe, origin);
+        }
+
+        // Bring the new chunks and their neighbours up to date
+        // with each other.
+        globe.copy_all_authoritative_cells();
+        for &(origin, _) in &missing_chunks {
+            globe.mark_views_of_chunks_adjacent_to_chunk_as_dirty(origin);
+        }
+    }
+
+    // Like `load_chunks_near`, but generates chunks on the worker pool,
+    // and forgets about any that were being generated but aren't
+    // near any of the anchors any more.
+    fn queue_chunks_near(
+        &mut self,
+        worker_pool: &WorkerPool,
+        globe: &mut Globe,
+        globe_entity: specs::Entity,
+        anchor_positions: &[CellPos],
+    ) {
+        let missing_chunks = self.missing_chunks_near(globe, anchor_positions);
+
+        let wanted_origins: HashSet<ChunkOrigin> = missing_chunks.iter()
+            .map(|&(origin, _)| origin)
+            .collect();
+        let unwanted_keys: Vec<(specs::Entity, ChunkOrigin)> = self.pending_chunks.keys()
+            .filter(|&&(pending_globe_entity, origin)| {
+                pending_globe_entity == globe_entity && !wanted_origins.contains(&origin)
+            })
+            .cloned()
+            .collect();
+        for key in unwanted_keys {
+            let pending_chunk = self.pending_chunks.remove(&key).expect("Just found this");
+            // If it's already started, its result will be ignored.
+            worker_pool.cancel(pending_chunk.ticket);
+        }
+
+        let mut loaded_origins: Vec<ChunkOrigin> = Vec::new();
+        let mut queued_chunk_count = 0;
+        for (origin, distance) in missing_chunks {
+            if self.pending_chunks.contains_key(&(globe_entity, origin)) {
+                continue;
+            }
+            let attempts = self.failed_chunks.get(&(globe_entity, origin)).cloned().unwrap_or(0);
+            if attempts >= MAX_GENERATION_ATTEMPTS {
+                // We gave up on this one; see `add_generated_chunks`.
+                continue;
+            }
+
+            // There's no point generating chunks that have been modified;
+            // they're quick to load, so just do it now.
+            if let Some(saved_chunk) = globe.take_saved_chunk(origin) {
+                trace!(self.log, "Loading saved chunk"; "origin" => format!("{:?}", origin));
+                globe.add_chunk(saved_chunk);
+                loaded_origins.push(origin);
+                continue;
+            }
+
+            let job_id = self.next_job_id;
+            self.next_job_id += 1;
+            let job = GenerateChunkJob {
+                globe_entity: globe_entity,
+                origin: origin,
+                job_id: job_id,
+                gen: globe.shared_gen(),
+                sender: self.generated_sender.clone(),
+            };
+            let ticket = worker_pool.submit(distance, Box::new(job));
+            self.pending_chunks.insert((globe_entity, origin), PendingChunk {
+                ticket: ticket,
+                job_id: job_id,
+                distance: distance,
+            });
+            queued_chunk_count += 1;
+        }
+
+        if queued_chunk_count > 0 {
+            debug!(self.log, "Queued chunks for generation"; "chunks" => queued_chunk_count);
+        }
+        if !loaded_origins.is_empty() {
+            globe.copy_all_authoritative_cells();
+            for origin in loaded_origins {
+                globe.mark_views_of_chunks_adjacent_to_chunk_as_dirty(origin);
+            }
         }
     }
 
-    pub fn build_all_chunks(&mut self, globe: &mut Globe) {
+    // Add chunks that have finished being generated in the background
+    // to their globes; the closest first, and only a few at a time.
+    fn add_generated_chunks<
+        A: Deref<Target = specs::Allocator>,
+        Gd: DerefMut<Target = specs::MaskedStorage<Globe>>,
+    >(
+        &mut self,
+        globes: &mut specs::Storage<Globe, A, Gd>,
+    ) {
+        self.ready_chunks.extend(self.generated_receiver.try_iter());
+
+        // Throw away anything we stopped waiting for, or
+        // have asked for again since; there'll be another
+        // result along for the latter soon.
+        {
+            let pending_chunks = &self.pending_chunks;
+            self.ready_chunks.retain(|chunk| {
+                pending_chunks.get(&(chunk.globe_entity, chunk.origin))
+                    .map(|pending_chunk| pending_chunk.job_id == chunk.job_id)
+                    .unwrap_or(false)
+            });
+            // Furthest first, so we can pop the closest off the end.
+            self.ready_chunks.sort_by(|a, b| {
+                let a_distance = pending_chunks[&(a.globe_entity, a.origin)].distance;
+                let b_distance = pending_chunks[&(b.globe_entity, b.origin)].distance;
+                b_distance.partial_cmp(&a_distance).unwrap_or(::std::cmp::Ordering::Equal)
+            });
+        }
+
+        let mut added_chunks: Vec<(specs::Entity, ChunkOrigin)> = Vec::new();
+        while added_chunks.len() < MAX_GENERATED_CHUNKS_ADDED_PER_TICK {
+            let generated_chunk = match self.ready_chunks.pop() {
+                Some(generated_chunk) => generated_chunk,
+                None => break,
+            };
+            let globe_entity = generated_chunk.globe_entity;
+            let origin = generated_chunk.origin;
+            self.pending_chunks.remove(&(globe_entity, origin));
+            let cells = match generated_chunk.cells {
+                Some(cells) => cells,
+                None => {
+                    self.chunk_generation_failed(globe_entity, origin);
+                    continue;
+                },
+            };
+            self.failed_chunks.remove(&(globe_entity, origin));
+
+            let globe = match globes.get_mut(globe_entity) {
+                Some(globe) => globe,
+                None => {
+                    warn!(self.log, "The globe this chunk was generated for is not alive! Can't proceed!");
+                    continue;
+                },
+            };
+            use super::globe::GlobeGuts;
+            if globe.chunks().contains_key(&origin) {
+                continue;
+            }
+            // If it's been modified since we started (e.g. a server
+            // sent us a copy of it) then that trumps what we generated.
+            let chunk = match globe.take_saved_chunk(origin) {
+                Some(saved_chunk) => saved_chunk,
+                None => {
+                    let spec = globe.spec();
+                    Chunk::new(
+                        origin,
+                        cells,
+                        spec.root_resolution,
+                        spec.chunk_resolution,
+                    )
+                },
+            };
+            globe.add_chunk(chunk);
+            added_chunks.push((globe_entity, origin));
+        }
+
+        if added_chunks.is_empty() {
+            return;
+        }
+        trace!(self.log, "Added generated chunks"; "chunks" => added_chunks.len(), "still_pending" => self.pending_chunks.len());
+
+        // Bring the new chunks and their neighbours up to date
+        // with each other.
+        let globe_entities: HashSet<specs::Entity> = added_chunks.iter()
+            .map(|&(globe_entity, _)| globe_entity)
+            .collect();
+        for globe_entity in globe_entities {
+            let globe = globes.get_mut(globe_entity).expect("Just added chunks to this globe");
+            globe.copy_all_authoritative_cells();
+            for &(_, origin) in added_chunks.iter().filter(|&&(entity, _)| entity == globe_entity) {
+                globe.mark_views_of_chunks_adjacent_to_chunk_as_dirty(origin);
+            }
+        }
+    }
+
+    // Generating a chunk in the background panicked.
+    // Try again, unless it keeps happening.
+    fn chunk_generation_failed(&mut self, globe_entity: specs::Entity, origin: ChunkOrigin) {
+        let attempts = {
+            let attempts = self.failed_chunks.entry((globe_entity, origin)).or_insert(0);
+            *attempts += 1;
+            *attempts
+        };
+        if attempts < MAX_GENERATION_ATTEMPTS {
+            warn!(self.log, "Failed to generate chunk; trying again"; "origin" => format!("{:?}", origin), "attempts" => attempts);
+            // Work out what to load again next tick,
+            // even if none of the anchors have moved.
+            self.last_anchor_chunks.clear();
+        } else {
+            error!(self.log, "Failed to generate chunk too many times; giving up on it"; "origin" => format!("{:?}", origin));
+        }
+    }
+
+    // All the chunks within the load radius of any of the given
+    // anchor positions that aren't loaded, along with their distance
+    // from the nearest anchor, closest first.
+    fn missing_chunks_near(&self, globe: &Globe, anchor_positions: &[CellPos]) -> Vec<(ChunkOrigin, f64)> {
+        use na::Norm;
         use super::globe::GlobeGuts;
 
-        // Calculate how many chunks to a root in each direction in (x, y).
         let spec = globe.spec();
-        let chunks_per_root = [
-            spec.root_resolution[0] / spec.chunk_resolution[0],
-            spec.root_resolution[1] / spec.chunk_resolution[1],
-        ];
-
-        debug!(self.log, "Making chunks...");
-
-        let dt = Duration::span(|| {
-            for root_index in 0..ROOT_QUADS {
-                let root = Root { index: root_index };
-                for z in 0..Z_CHUNKS {
-                    for y in 0..chunks_per_root[1] {
-                        for x in 0..chunks_per_root[0] {
-                            let origin = ChunkOrigin::new(
-                                CellPos {
-                                    root: root,
-                                    x: x * spec.chunk_resolution[0],
-                                    y: y * spec.chunk_resolution[1],
-                                    z: z * spec.chunk_resolution[2],
-                                },
-                                spec.root_resolution,
-                                spec.chunk_resolution,
-                            );
-                            self.build_chunk(globe, origin);
-                        }
+
+        // Collect the chunks we're missing first, so we don't build
+        // any chunk twice if it's near more than one anchor.
+        let mut missing_distances: HashMap<ChunkOrigin, f64> = HashMap::new();
+        for anchor_pos in anchor_positions {
+            let anchor_pt = spec.cell_center_center(*anchor_pos);
+
+            // Only bother looking at layers of chunks that could
+            // conceivably be close enough to this anchor. Pad the range by
+            // a whole chunk because we measure to the middle of each chunk.
+            let anchor_radius = anchor_pt.as_vector().norm();
+            let chunk_depth = spec.chunk_resolution[2];
+            let min_z = ((anchor_radius - self.load_radius - spec.floor_radius) / spec.block_height).floor() as i64;
+            let max_z = ((anchor_radius + self.load_radius - spec.floor_radius) / spec.block_height).ceil() as i64;
+            let min_chunk_z = (min_z / chunk_depth - 1).max(0);
+            let max_chunk_z = max_z / chunk_depth + 1;
+            if max_chunk_z < min_chunk_z {
+                continue;
+            }
+
+            // Walk outwards from the anchor's column of chunks, and stop
+            // wherever a column has no chunks close enough to load.
+            let anchor_column = column_containing(&spec, *anchor_pos);
+            let mut visited_columns: HashSet<ChunkOrigin> = HashSet::new();
+            visited_columns.insert(anchor_column);
+            let mut columns_to_visit = vec![anchor_column];
+            while let Some(column) = columns_to_visit.pop() {
+                let mut is_near = false;
+                for z in min_chunk_z..(max_chunk_z + 1) {
+                    let origin = ChunkOrigin::new(
+                        CellPos { z: z * chunk_depth, .. *column.pos() },
+                        spec.root_resolution,
+                        spec.chunk_resolution,
+                    );
+                    let distance = (chunk_center(&spec, origin) - anchor_pt).norm();
+                    if distance > self.load_radius {
+                        continue;
+                    }
+                    is_near = true;
+                    if globe.chunks().contains_key(&origin) {
+                        continue;
+                    }
+                    let nearest_distance = missing_distances.entry(origin).or_insert(distance);
+                    if distance < *nearest_distance {
+                        *nearest_distance = distance;
+                    }
+                }
+
+                // Always look around the anchor's own column, in case the
+                // load radius is smaller than a chunk.
+                if !is_near && column != anchor_column {
+                    continue;
+                }
+                for neighbor_column in columns_next_to(&spec, column) {
+                    if visited_columns.insert(neighbor_column) {
+                        columns_to_visit.push(neighbor_column);
                     }
                 }
             }
-        });
+        }
 
-        debug!(self.log, "Finished making chunks"; "chunks" => globe.chunks().len(), "dt" => format!("{}", dt));
+        let mut missing_chunks: Vec<(ChunkOrigin, f64)> = missing_distances.into_iter().collect();
+        missing_chunks.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal));
+        missing_chunks
+    }
 
-        // TODO: this is _not_ going to fly once we're trickling the
-        // chunks in over time...
-        globe.copy_all_authoritative_cells();
+    /// Unload every chunk that is beyond the unload radius of all
+    /// the given anchor positions. Returns the removed chunks, so
+    /// the caller can clean up anything that refers to them.
+    pub fn unload_chunks_far_from(&mut self, globe: &mut Globe, anchor_positions: &[CellPos]) -> Vec<Chunk> {
+        use na::Norm;
+        use super::globe::GlobeGuts;
+
+        let spec = globe.spec();
+        let unload_radius = self.unload_radius();
+        let anchor_pts: Vec<Pt3> = anchor_positions.iter()
+            .map(|pos| spec.cell_center_center(*pos))
+            .collect();
+        let distant_origins: Vec<ChunkOrigin> = globe.chunks().keys()
+            .filter(|origin| {
+                let center = chunk_center(&spec, **origin);
+                anchor_pts.iter().all(|anchor_pt| (center - *anchor_pt).norm() > unload_radius)
+            })
+            .cloned()
+            .collect();
+
+        if !distant_origins.is_empty() {
+            debug!(self.log, "Unloading chunks"; "chunks" => distant_origins.len());
+        }
 
-        self.have_built_chunks = true;
+        distant_origins.into_iter()
+            .map(|origin| globe.remove_chunk(origin))
+            .collect()
     }
 
     // TODO: rip all this out into a system.
     pub fn build_chunk(&mut self, globe: &mut Globe, origin: ChunkOrigin) {
-        let spec = globe.spec();
-
-        let mut cells: Vec<Cell> = Vec::new();
-        // Include cells _on_ the far edge of the chunk;
-        // even though we don't own them we'll need to draw part of them.
-        let end_x = origin.pos().x + spec.chunk_resolution[0];
-        let end_y = origin.pos().y + spec.chunk_resolution[1];
-        // Chunks don't share cells in the z-direction,
-        // but do in the x- and y-directions.
-        let end_z = origin.pos().z + spec.chunk_resolution[2] - 1;
-        for cell_z in origin.pos().z..(end_z + 1) {
-            for cell_y in origin.pos().y..(end_y + 1) {
-                for cell_x in origin.pos().x..(end_x + 1) {
-                    let cell_pos = CellPos {
-                        root: origin.pos().root,
-                        x: cell_x,
-                        y: cell_y,
-                        z: cell_z,
-                    };
-                    let mut cell = globe.gen.cell_at(cell_pos);
-                    // Temp hax?
-                    let mut rng = rand::thread_rng();
-                    cell.shade = 1.0 - 0.5 * rng.next_f32();
-                    cells.push(cell);
-                }
-            }
+        // If this chunk has been modified before, then load
+        // it as it was rather than generating it afresh.
+        if let Some(saved_chunk) = globe.take_saved_chunk(origin) {
+            trace!(self.log, "Loading saved chunk"; "origin" => format!("{:?}", origin));
+            globe.add_chunk(saved_chunk);
+            return;
         }
+
+        let spec = globe.spec();
+        let cells = generate_cells(globe.gen(), origin);
         globe.add_chunk(Chunk::new(
             origin,
             cells,
@@ -119,19 +481,177 @@ impl ChunkSystem {
     }
 }
 
+// Everything in a freshly generated chunk.
+fn generate_cells(gen: &WorldGen, origin: ChunkOrigin) -> Vec<Cell> {
+    let spec = gen.spec();
+    let mut cells: Vec<Cell> = vec![
+        Cell {
+            material: material::AIR,
+            shade: 1.0,
+            light: 0,
+            flow: 0,
+        };
+        spec.cells_per_chunk()
+    ];
+    gen.fill_chunk(origin, &mut cells);
+
+    // Temp hax? Give each cell a slightly different shade so
+    // they're easy to tell apart. Cells are in the same order
+    // `fill_chunk` visits them.
+    let mut cell_index = 0;
+    let end_x = origin.pos().x + spec.chunk_resolution[0];
+    let end_y = origin.pos().y + spec.chunk_resolution[1];
+    let end_z = origin.pos().z + spec.chunk_resolution[2] - 1;
+    for cell_z in origin.pos().z..(end_z + 1) {
+        for cell_y in origin.pos().y..(end_y + 1) {
+            for cell_x in origin.pos().x..(end_x + 1) {
+                let cell_pos = CellPos {
+                    root: origin.pos().root,
+                    x: cell_x,
+                    y: cell_y,
+                    z: cell_z,
+                };
+                let pos_in_owning_root = PosInOwningRoot::new(cell_pos, spec.root_resolution);
+                cells[cell_index].shade = cell_shade(spec.seed, pos_in_owning_root);
+                cell_index += 1;
+            }
+        }
+    }
+    cells
+}
+
+// A shade for the cell at `pos` that looks random, but is always the
+// same for the same globe seed; on every run, on every client, and in
+// every chunk that has a copy of the cell.
+fn cell_shade(seed: u32, pos: PosInOwningRoot) -> f32 {
+    let pos: CellPos = pos.into();
+    let mut hash = seed as u64;
+    for &value in &[pos.root.index as u64, pos.x as u64, pos.y as u64, pos.z as u64] {
+        hash = mix_bits(hash ^ value);
+    }
+    // The top 24 bits fit exactly in an `f32`.
+    let unit = (hash >> 40) as f32 / (1u64 << 24) as f32;
+    1.0 - 0.5 * unit
+}
+
+// The finalizer from SplitMix64; flipping any bit of the
+// input flips about half of the bits of the output.
+fn mix_bits(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+/// Position of the cell in the middle of a chunk, in real space.
+pub fn chunk_center(spec: &Spec, origin: ChunkOrigin) -> Pt3 {
+    let middle_pos = CellPos {
+        root: origin.pos().root,
+        x: origin.pos().x + spec.chunk_resolution[0] / 2,
+        y: origin.pos().y + spec.chunk_resolution[1] / 2,
+        z: origin.pos().z + spec.chunk_resolution[2] / 2,
+    };
+    spec.cell_center_center(middle_pos)
+}
+
+// The origin of the bottom chunk in the column of
+// chunks that owns the cell at `pos`.
+fn column_containing(spec: &Spec, pos: CellPos) -> ChunkOrigin {
+    let pos_in_owning_root = PosInOwningRoot::new(CellPos { z: 0, .. pos }, spec.root_resolution);
+    origin_of_chunk_owning(pos_in_owning_root, spec.root_resolution, spec.chunk_resolution)
+}
+
+// Columns of chunks (see `column_containing`) that share or border
+// any of the cells around the edge of `column`, even across roots.
+fn columns_next_to(spec: &Spec, column: ChunkOrigin) -> HashSet<ChunkOrigin> {
+    let origin = *column.pos();
+    let end_x = origin.x + spec.chunk_resolution[0];
+    let end_y = origin.y + spec.chunk_resolution[1];
+    let mut columns = HashSet::new();
+    for y in origin.y..(end_y + 1) {
+        for x in origin.x..(end_x + 1) {
+            let is_on_edge = x == origin.x || x == end_x || y == origin.y || y == end_y;
+            if !is_on_edge {
+                continue;
+            }
+            let pos = CellPos { x: x, y: y, .. origin };
+            columns.insert(column_containing(spec, pos));
+            for neighbor_pos in Neighbors::new(pos, spec.root_resolution) {
+                // Only look sideways.
+                if neighbor_pos.z != pos.z {
+                    continue;
+                }
+                columns.insert(column_containing(spec, neighbor_pos));
+            }
+        }
+    }
+    columns.remove(&column);
+    columns
+}
+
 impl specs::System<TimeDelta> for ChunkSystem {
     fn run(&mut self, arg: specs::RunArg, _dt: TimeDelta) {
         use specs::Join;
-        let (mut globes,) = arg.fetch(|w| {
-            (w.write::<Globe>(),)
-        });
+        arg.fetch(|w| {
+            let mut globes = w.write::<Globe>();
+            let cell_dwellers = w.read::<CellDweller>();
+            let chunk_anchors = w.read::<ChunkAnchor>();
+            let entities = w.entities();
+
+            self.add_generated_chunks(&mut globes);
 
-        for globe in (&mut globes).iter() {
-            // TEMP; just factoring the dumb "build everything" logic out into
-            // a system before making it do interesting things.
-            if !self.have_built_chunks {
-                self.build_all_chunks(globe);
+            // Find everything that wants chunks loaded around it.
+            let mut anchors: Vec<(specs::Entity, CellPos)> = Vec::new();
+            for cd in (&cell_dwellers).iter() {
+                if let Some(globe_entity) = cd.globe_entity {
+                    anchors.push((globe_entity, cd.pos()));
+                }
             }
-        }
+            for anchor in (&chunk_anchors).iter() {
+                if let Some(globe_entity) = anchor.globe_entity {
+                    anchors.push((globe_entity, anchor.pos));
+                }
+            }
+
+            // Nothing to do unless one of the anchors has moved into
+            // a different chunk (or come or gone) since last time.
+            let anchor_chunks: Vec<(specs::Entity, ChunkOrigin)> = anchors.iter()
+                .filter_map(|&(globe_entity, pos)| {
+                    globes.get(globe_entity).map(|globe| {
+                        (globe_entity, globe.origin_of_chunk_in_same_root_containing(pos))
+                    })
+                })
+                .collect();
+            if anchor_chunks == self.last_anchor_chunks {
+                return;
+            }
+
+            for (globe, globe_entity) in (&mut globes, &entities).iter() {
+                let anchor_positions: Vec<CellPos> = anchors.iter()
+                    .filter(|&&(anchor_globe_entity, _)| anchor_globe_entity == globe_entity)
+                    .map(|&(_, pos)| pos)
+                    .collect();
+
+                match self.worker_pool.clone() {
+                    Some(worker_pool) => self.queue_chunks_near(
+                        &worker_pool,
+                        globe,
+                        globe_entity,
+                        &anchor_positions,
+                    ),
+                    None => self.load_chunks_near(globe, &anchor_positions),
+                }
+
+                let removed_chunks = self.unload_chunks_far_from(globe, &anchor_positions);
+                for chunk in removed_chunks {
+                    // Take the chunk's view down with it.
+                    if let Some(view_entity) = chunk.view_entity {
+                        w.delete_later(view_entity);
+                    }
+                }
+            }
+
+            self.last_anchor_chunks = anchor_chunks;
+        });
     }
 }
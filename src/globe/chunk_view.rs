@@ -1,10 +1,12 @@
 use specs;
 
-use globe::ChunkOrigin;
+use globe::{ ChunkOrigin, MeshStats };
 
 pub struct ChunkView {
     pub globe_entity: specs::Entity,
     pub origin: ChunkOrigin,
+    /// How big the chunk's mesh was last time we built it.
+    pub mesh_stats: MeshStats,
 }
 
 impl ChunkView {
@@ -15,6 +17,7 @@ impl ChunkView {
         ChunkView {
             origin: origin,
             globe_entity: globe_entity,
+            mesh_stats: MeshStats::default(),
         }
     }
 }
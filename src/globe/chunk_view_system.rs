@@ -1,18 +1,112 @@
+use std::collections::HashMap;
 use std::ops::{ Deref, DerefMut };
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, mpsc };
 
 use specs;
 use slog::Logger;
 
 use types::*;
-use globe::{ Globe, View, ChunkView };
+use globe::{ Globe, View, ChunkView, ChunkOrigin, MaterialRegistry, Meshing, MeshStats };
+use globe::chunk_system::chunk_center;
+use globe::lighting;
 use ::render::{ Visual, ProtoMesh, Vertex };
+use ::cell_dweller::ClientPlayer;
+use ::Spatial;
+use worker_pool::{ WorkerPool, Job, Ticket };
+
+// Uploading meshes to the video card isn't free, so don't
+// try to take on too many finished ones in a single tick.
+const MAX_MESHES_ADDED_PER_TICK: usize = 4;
+
+// Don't queue up more meshes than this at a time; chunks closer
+// to the player might need meshing by the time they'd get started.
+const MAX_PENDING_MESHES: usize = 16;
+
+// Stop retrying a chunk's mesh if building it goes wrong this many
+// times in a row. It'll get another go when the chunk next changes.
+const MAX_MESH_ATTEMPTS: usize = 3;
 
 // For now, just creates up to 1 chunk view per tick,
 // until we have created views for all chunks.
+//
+// Give it a `WorkerPool` with `set_worker_pool` to build
+// meshes in the background instead, closest to the player first.
 pub struct ChunkViewSystem {
     log: Logger,
+    meshing: Meshing,
     seconds_between_geometry_creation: TimeDelta,
     seconds_since_last_geometry_creation: TimeDelta,
+    worker_pool: Option<Arc<WorkerPool>>,
+    // Meshes being built in the background, keyed by
+    // the entity of the `ChunkView` they're for.
+    pending_meshes: HashMap<specs::Entity, PendingMesh>,
+    next_job_id: u64,
+    mesh_sender: mpsc::Sender<BuiltMesh>,
+    mesh_receiver: mpsc::Receiver<BuiltMesh>,
+    // Finished meshes we haven't got around to using yet.
+    ready_meshes: Vec<BuiltMesh>,
+    // How many times in a row building each view's mesh
+    // in the background has panicked.
+    failed_meshes: HashMap<specs::Entity, usize>,
+}
+
+struct PendingMesh {
+    ticket: Ticket,
+    // Only results from the most recent job for
+    // each view are wanted; see `BuiltMesh`.
+    job_id: u64,
+    // From the nearest player, when we asked for it.
+    distance: f64,
+}
+
+// Geometry for a chunk, built by a `BuildMeshJob`.
+struct BuiltMesh {
+    view_entity: specs::Entity,
+    job_id: u64,
+    // `None` if building the mesh panicked.
+    geometry: Option<(Vec<Vertex>, Vec<u32>, MeshStats)>,
+}
+
+struct BuildMeshJob {
+    view_entity: specs::Entity,
+    job_id: u64,
+    origin: ChunkOrigin,
+    // Copies of just the chunks we need to look at,
+    // as they were when the job was queued.
+    snapshot: Globe,
+    materials: MaterialRegistry,
+    meshing: Meshing,
+    log: Logger,
+    sender: mpsc::Sender<BuiltMesh>,
+}
+
+impl Job for BuildMeshJob {
+    fn run(self: Box<Self>) {
+        // Tell `ChunkViewSystem` if this goes wrong, rather than
+        // leaving it waiting for a mesh that will never turn up.
+        let geometry = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut globe_view = View::new(self.snapshot.spec(), &self.log);
+            globe_view.set_meshing(self.meshing);
+            let mut vertex_data: Vec<Vertex> = Vec::new();
+            let mut index_data: Vec<u32> = Vec::new();
+            let stats = globe_view.make_chunk_geometry(
+                &self.snapshot,
+                &self.materials,
+                self.origin,
+                &mut vertex_data,
+                &mut index_data,
+            );
+            (vertex_data, index_data, stats)
+        })).ok();
+        // Nobody might be listening any more if the
+        // game is shutting down; that's fine.
+        let _ = self.sender.send(BuiltMesh {
+            view_entity: self.view_entity,
+            job_id: self.job_id,
+            geometry: geometry,
+        });
+    }
 }
 
 impl ChunkViewSystem {
@@ -20,23 +114,45 @@ impl ChunkViewSystem {
         parent_log: &Logger,
         seconds_between_geometry_creation: TimeDelta,
     ) -> ChunkViewSystem {
+        let (mesh_sender, mesh_receiver) = mpsc::channel();
         ChunkViewSystem {
             log: parent_log.new(o!()),
+            meshing: Meshing::default(),
             seconds_between_geometry_creation: seconds_between_geometry_creation,
             seconds_since_last_geometry_creation: 0.0,
+            worker_pool: None,
+            pending_meshes: HashMap::new(),
+            next_job_id: 0,
+            mesh_sender: mesh_sender,
+            mesh_receiver: mesh_receiver,
+            ready_meshes: Vec::new(),
+            failed_meshes: HashMap::new(),
         }
     }
 
+    /// Build chunk meshes on `worker_pool`'s threads, rather than
+    /// on the simulation thread. Meshes are no longer throttled
+    /// by `seconds_between_geometry_creation`.
+    pub fn set_worker_pool(&mut self, worker_pool: Arc<WorkerPool>) {
+        self.worker_pool = Some(worker_pool);
+    }
+
+    /// Choose how to build chunk meshes; see `Meshing`.
+    pub fn set_meshing(&mut self, meshing: Meshing) {
+        self.meshing = meshing;
+    }
+
     fn build_chunk_geometry<
         A: Deref<Target = specs::Allocator>,
         Gd: DerefMut<Target = specs::MaskedStorage<Globe>>,
         Vd: DerefMut<Target = specs::MaskedStorage<Visual>>,
-        Cd: Deref<Target = specs::MaskedStorage<ChunkView>>,
+        Cd: DerefMut<Target = specs::MaskedStorage<ChunkView>>,
     >(
         &mut self,
         mut globes: specs::Storage<Globe, A, Gd>,
         mut visuals: specs::Storage<Visual, A, Vd>,
-        chunk_views: specs::Storage<ChunkView, A, Cd>,
+        mut chunk_views: specs::Storage<ChunkView, A, Cd>,
+        materials: &MaterialRegistry,
     ) {
         // Throttle rate of geometry creation.
         // We don't want to spend too much doing this.
@@ -46,7 +162,8 @@ impl ChunkViewSystem {
         }
 
         use specs::Join;
-        for (visual, chunk_view) in (&mut visuals, &chunk_views).iter() {
+        let mut built_any = false;
+        for (visual, chunk_view) in (&mut visuals, &mut chunk_views).iter() {
             // TODO: find the closest mesh to the player that needs
             // to be generated (i.e. absent or dirty).
             //
@@ -74,27 +191,39 @@ impl ChunkViewSystem {
             let spec = globe.spec();
             {
                 // Ew, can I please have non-lexical borrow scopes?
-                let chunk = &mut globe.chunks_mut().get(&chunk_view.origin)
-                    .expect("Don't know how to deal with chunk not loaded yet. Why do we have a view for it anyway?");
+                let chunk = match globe.chunks_mut().get(&chunk_view.origin) {
+                    Some(chunk) => chunk,
+                    None => {
+                        // The chunk has been unloaded; `ChunkSystem` will have
+                        // asked for this view to be deleted, but that might not
+                        // have happened yet.
+                        continue;
+                    },
+                };
                 if !chunk.is_view_dirty {
                     continue;
                 }
             }
 
+            // Light levels around the chunk might have changed, too.
+            lighting::light_chunk(globe, materials, chunk_view.origin);
+
             // Make a proto-mesh for the chunk.
             trace!(self.log, "Making chunk proto-mesh"; "origin" => format!("{:?}", chunk_view.origin));
             // TEMP: just use the existing globe `View` struct
             // to get this done. TODO: move into `ChunkView`.
-            let globe_view = View::new(
+            let mut globe_view = View::new(
                 spec,
                 &self.log,
             );
+            globe_view.set_meshing(self.meshing);
             // Build geometry for this chunk into vertex
             // and index buffers.
             let mut vertex_data: Vec<Vertex> = Vec::new();
             let mut index_data: Vec<u32> = Vec::new();
-            globe_view.make_chunk_geometry(
+            chunk_view.mesh_stats = globe_view.make_chunk_geometry(
                 globe,
+                materials,
                 chunk_view.origin,
                 &mut vertex_data,
                 &mut index_data,
@@ -129,8 +258,208 @@ impl ChunkViewSystem {
 
             // Do at most 1 per frame; probably far less.
             self.seconds_since_last_geometry_creation = 0.0;
+            built_any = true;
+            break;
+        }
+
+        if built_any {
+            let mut total_stats = MeshStats::default();
+            for chunk_view in (&chunk_views).iter() {
+                total_stats += chunk_view.mesh_stats;
+            }
+            debug!(self.log, "Chunk meshes"; "vertices" => total_stats.vertices, "triangles" => total_stats.triangles);
+        }
+    }
+}
+
+impl ChunkViewSystem {
+    fn build_chunk_geometry_in_background<
+        A: Deref<Target = specs::Allocator>,
+        Gd: DerefMut<Target = specs::MaskedStorage<Globe>>,
+        Vd: DerefMut<Target = specs::MaskedStorage<Visual>>,
+        Cd: DerefMut<Target = specs::MaskedStorage<ChunkView>>,
+    >(
+        &mut self,
+        worker_pool: &WorkerPool,
+        mut globes: specs::Storage<Globe, A, Gd>,
+        mut visuals: specs::Storage<Visual, A, Vd>,
+        mut chunk_views: specs::Storage<ChunkView, A, Cd>,
+        materials: &MaterialRegistry,
+        player_positions: &[Pt3],
+    ) {
+        use specs::Join;
+        use globe::globe::GlobeGuts;
+
+        // Use finished meshes, closest first.
+        self.ready_meshes.extend(self.mesh_receiver.try_iter());
+        {
+            // Throw away anything we've asked for again since;
+            // there'll be another result along soon.
+            let pending_meshes = &self.pending_meshes;
+            self.ready_meshes.retain(|mesh| {
+                pending_meshes.get(&mesh.view_entity)
+                    .map(|pending_mesh| pending_mesh.job_id == mesh.job_id)
+                    .unwrap_or(false)
+            });
+            // Furthest first, so we can pop the closest off the end.
+            self.ready_meshes.sort_by(|a, b| {
+                let a_distance = pending_meshes[&a.view_entity].distance;
+                let b_distance = pending_meshes[&b.view_entity].distance;
+                b_distance.partial_cmp(&a_distance).unwrap_or(::std::cmp::Ordering::Equal)
+            });
+        }
+        for _ in 0..MAX_MESHES_ADDED_PER_TICK {
+            let mesh = match self.ready_meshes.pop() {
+                Some(mesh) => mesh,
+                None => break,
+            };
+            self.pending_meshes.remove(&mesh.view_entity);
+
+            let (vertex_data, index_data, stats) = match mesh.geometry {
+                Some(geometry) => geometry,
+                None => {
+                    self.mesh_building_failed(mesh.view_entity, &mut globes, &chunk_views);
+                    continue;
+                },
+            };
+            self.failed_meshes.remove(&mesh.view_entity);
+
+            // The chunk might have been unloaded since, taking its view with it.
+            let (visual, chunk_view) = match (visuals.get_mut(mesh.view_entity), chunk_views.get_mut(mesh.view_entity)) {
+                (Some(visual), Some(chunk_view)) => (visual, chunk_view),
+                _ => continue,
+            };
+            chunk_view.mesh_stats = stats;
+            // See `build_chunk_geometry` about empty meshes.
+            if vertex_data.len() == 0 || index_data.len() == 0 {
+                continue;
+            }
+            visual.proto_mesh = ProtoMesh::new(vertex_data, index_data).into();
+        }
+
+        // Find the chunks that need new meshes: anything whose view has
+        // changed since we last took a snapshot of it, closest first.
+        let mut dirty_views: Vec<(specs::Entity, specs::Entity, ChunkOrigin, f64)> = Vec::new();
+        for chunk_view in (&chunk_views).iter() {
+            let globe = match globes.get(chunk_view.globe_entity) {
+                Some(globe) => globe,
+                None => {
+                    warn!(self.log, "The globe associated with this ChunkView is not alive! Can't proceed!");
+                    continue;
+                },
+            };
+            let chunk = match globe.chunks().get(&chunk_view.origin) {
+                Some(chunk) => chunk,
+                // Being unloaded; see `build_chunk_geometry`.
+                None => continue,
+            };
+            let view_entity = match chunk.view_entity {
+                Some(view_entity) => view_entity,
+                None => continue,
+            };
+            if !chunk.is_view_dirty {
+                continue;
+            }
+            // TODO: use the globe's actual position
+            // when globes have a `Spatial`.
+            use na::Norm;
+            let center = chunk_center(&globe.spec(), chunk_view.origin);
+            let distance = player_positions.iter()
+                .map(|player_pos| (center - *player_pos).norm())
+                .fold(::std::f64::INFINITY, f64::min);
+            // With nobody to be near, just do them in whatever order.
+            let distance = if distance.is_finite() { distance } else { 0.0 };
+            dirty_views.push((view_entity, chunk_view.globe_entity, chunk_view.origin, distance));
+        }
+        dirty_views.sort_by(|a, b| a.3.partial_cmp(&b.3).unwrap_or(::std::cmp::Ordering::Equal));
+
+        for (view_entity, globe_entity, origin, distance) in dirty_views {
+            let is_already_pending = self.pending_meshes.contains_key(&view_entity);
+            if !is_already_pending && self.pending_meshes.len() >= MAX_PENDING_MESHES {
+                break;
+            }
+            let globe = globes.get_mut(globe_entity).expect("Just found this globe");
+
+            // Light it here rather than on the worker, so the
+            // globe remembers the light levels for next time.
+            lighting::light_chunk(globe, materials, origin);
+            let snapshot = globe.snapshot_for_view(origin);
+            // Anything that changes after this point
+            // will need another new mesh.
+            globe.chunks_mut().get_mut(&origin)
+                .expect("Just found this chunk")
+                .mark_view_as_clean();
+
+            if let Some(old_pending_mesh) = self.pending_meshes.get(&view_entity) {
+                // If it's already started, its result will be ignored.
+                worker_pool.cancel(old_pending_mesh.ticket);
+            }
+            let job_id = self.next_job_id;
+            self.next_job_id += 1;
+            let job = BuildMeshJob {
+                view_entity: view_entity,
+                job_id: job_id,
+                origin: origin,
+                snapshot: snapshot,
+                materials: materials.clone(),
+                meshing: self.meshing,
+                log: self.log.clone(),
+                sender: self.mesh_sender.clone(),
+            };
+            let ticket = worker_pool.submit(distance, Box::new(job));
+            self.pending_meshes.insert(view_entity, PendingMesh {
+                ticket: ticket,
+                job_id: job_id,
+                distance: distance,
+            });
+        }
+    }
+
+    // Building a chunk's mesh in the background panicked. We marked its
+    // view as clean when we queued it, so mark it dirty again to have
+    // another go, unless it keeps happening.
+    fn mesh_building_failed<
+        A: Deref<Target = specs::Allocator>,
+        Gd: DerefMut<Target = specs::MaskedStorage<Globe>>,
+        Cd: DerefMut<Target = specs::MaskedStorage<ChunkView>>,
+    >(
+        &mut self,
+        view_entity: specs::Entity,
+        globes: &mut specs::Storage<Globe, A, Gd>,
+        chunk_views: &specs::Storage<ChunkView, A, Cd>,
+    ) {
+        use globe::globe::GlobeGuts;
+
+        let attempts = {
+            let attempts = self.failed_meshes.entry(view_entity).or_insert(0);
+            *attempts += 1;
+            *attempts
+        };
+        if attempts >= MAX_MESH_ATTEMPTS {
+            error!(self.log, "Failed to build chunk mesh too many times; giving up until it changes"; "attempts" => attempts);
+            self.failed_meshes.remove(&view_entity);
             return;
         }
+        warn!(self.log, "Failed to build chunk mesh; trying again"; "attempts" => attempts);
+
+        // The chunk might have been unloaded since, taking its view with it.
+        let chunk_view = match chunk_views.get(view_entity) {
+            Some(chunk_view) => chunk_view,
+            None => {
+                self.failed_meshes.remove(&view_entity);
+                return;
+            },
+        };
+        let globe = match globes.get_mut(chunk_view.globe_entity) {
+            Some(globe) => globe,
+            None => {
+                warn!(self.log, "The globe associated with this ChunkView is not alive! Can't proceed!");
+                return;
+            },
+        };
+        if let Some(chunk) = globe.chunks_mut().get_mut(&chunk_view.origin) {
+            chunk.mark_view_as_dirty();
+        }
     }
 }
 
@@ -139,7 +468,7 @@ impl specs::System<TimeDelta> for ChunkViewSystem {
         self.seconds_since_last_geometry_creation += dt;
 
         use specs::Join;
-        let (globes, visuals, chunk_views) = arg.fetch(|w| {
+        let (globes, visuals, chunk_views, materials, player_positions) = arg.fetch(|w| {
             let mut globes = w.write::<Globe>();
             let entities = w.entities();
             for (globe, globe_entity) in (&mut globes, &entities).iter() {
@@ -152,15 +481,35 @@ impl specs::System<TimeDelta> for ChunkViewSystem {
                 // be selective about what views to have.
                 globe.ensure_chunk_view_entities(w, globe_entity);
             }
-            (globes, w.write::<Visual>(), w.write::<ChunkView>())
+
+            let client_players = w.read::<ClientPlayer>();
+            let spatials = w.read::<Spatial>();
+            let player_positions: Vec<Pt3> = (&client_players, &spatials).iter()
+                .map(|(_, spatial)| *spatial.transform.translation.as_point())
+                .collect();
+
+            (globes, w.write::<Visual>(), w.write::<ChunkView>(), w.read_resource::<MaterialRegistry>(), player_positions)
         });
 
+        if let Some(worker_pool) = self.worker_pool.clone() {
+            self.build_chunk_geometry_in_background(
+                &worker_pool,
+                globes,
+                visuals,
+                chunk_views,
+                &materials,
+                &player_positions,
+            );
+            return;
+        }
+
         // Build geometry for some chunks; throttled
         // so we don't spend too much time doing this each frame.
         self.build_chunk_geometry(
             globes,
             visuals,
             chunk_views,
+            &materials,
         );
     }
 }
@@ -1,39 +1,122 @@
 use noise;
 
+use types::*;
 use super::spec::Spec;
-use super::CellPos;
-use super::chunk::{ Cell, Material };
-
-// TODO: turn this into a component that we can slap onto a Globe
-// or other globe-oid (distant point?).
+use super::{ CellPos, ChunkOrigin };
+use super::chunk::Cell;
+use super::material;
 
 /// Globe content generator. Stores all the state for generating
 /// the terrain and any other parts of the globe that are derived
 /// from its seed.
 ///
-/// Will eventually do some basic caching, etc., but is pretty dumb
-/// right now.
+/// `Globe` holds one of these, and asks it for the content of every
+/// chunk that hasn't been loaded before. Implement this yourself if
+/// you want your own terrain; `SimpleGen` and `FlatGen` are provided
+/// as examples, and for worlds that don't need anything fancier.
 ///
 /// The plan is for this to eventually be used with multiple
 /// implementations of globes, e.g., a full voxmap based globe,
 /// a distant blob in the sky, to a shiny dot in the distance.
-pub struct Gen {
+pub trait WorldGen: Send + Sync {
+    /// The spec of the globe this generates content for.
+    fn spec(&self) -> Spec;
+
+    /// Generate the content of a single cell.
+    ///
+    /// Note that `shade` is not part of the natural content of a globe,
+    /// so implementations can leave it as whatever they like.
+    fn cell_at(&self, cell_pos: CellPos) -> Cell;
+
+    /// Distance from the center of the globe to the surface of the land
+    /// at the given point on the unit sphere, ignoring anything like caves
+    /// or overhangs; this is all that's used to draw the globe from far
+    /// away. See `globe::lod`.
+    ///
+    /// The default implementation is a smooth ball at sea level;
+    /// override it if your terrain is any more interesting than that.
+    fn land_height_at(&self, _pt_on_unit_sphere: Pt3) -> f64 {
+        self.spec().ocean_radius
+    }
+
+    /// Generate the content of every cell in the chunk at `origin`,
+    /// in the same order that `Chunk` stores them: sorted by `(z, y, x)`,
+    /// and including cells on the far edges of the chunk in the x- and
+    /// y-directions.
+    ///
+    /// The default implementation just calls `cell_at` for every cell;
+    /// override it if you can do better by generating a whole chunk at once.
+    ///
+    /// # Panics
+    ///
+    /// Implementations may panic if `cells` is not exactly the length
+    /// of a chunk's cell data.
+    fn fill_chunk(&self, origin: ChunkOrigin, cells: &mut [Cell]) {
+        let spec = self.spec();
+        assert_eq!(spec.cells_per_chunk(), cells.len());
+
+        let mut cell_index = 0;
+        // Include cells _on_ the far edge of the chunk;
+        // even though we don't own them we'll need to draw part of them.
+        let end_x = origin.pos().x + spec.chunk_resolution[0];
+        let end_y = origin.pos().y + spec.chunk_resolution[1];
+        // Chunks don't share cells in the z-direction,
+        // but do in the x- and y-directions.
+        let end_z = origin.pos().z + spec.chunk_resolution[2] - 1;
+        for cell_z in origin.pos().z..(end_z + 1) {
+            for cell_y in origin.pos().y..(end_y + 1) {
+                for cell_x in origin.pos().x..(end_x + 1) {
+                    let cell_pos = CellPos {
+                        root: origin.pos().root,
+                        x: cell_x,
+                        y: cell_y,
+                        z: cell_z,
+                    };
+                    cells[cell_index] = self.cell_at(cell_pos);
+                    cell_index += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Rolling hills of dirt sticking out of an ocean, shaped by
+/// a single layer of fractal noise.
+pub struct SimpleGen {
     spec: Spec,
     // Permutation table for noise
     pt: noise::Seed,
 }
 
-impl Gen {
-    pub fn new(spec: Spec) -> Gen {
+impl SimpleGen {
+    pub fn new(spec: Spec) -> SimpleGen {
         assert!(spec.is_valid(), "Invalid globe spec!");
         let pt = noise::Seed::new(spec.seed);
-        Gen {
+        SimpleGen {
             spec: spec,
             pt: pt,
         }
     }
 
-    pub fn cell_at(&self, cell_pos: CellPos) -> Cell {
+    /// Distance from the center of the globe to the surface of the land
+    /// in the column containing `cell_pos`. Ignores `cell_pos.z`.
+    pub fn land_height(&self, cell_pos: CellPos) -> f64 {
+        let land_pt3 = self.spec.cell_center_on_unit_sphere(cell_pos);
+        self.land_height_at(land_pt3)
+    }
+}
+
+impl WorldGen for SimpleGen {
+    fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    fn cell_at(&self, cell_pos: CellPos) -> Cell {
+        let land_height = self.land_height(cell_pos);
+        cell_at_height(&self.spec, cell_pos, land_height)
+    }
+
+    fn land_height_at(&self, land_pt3: Pt3) -> f64 {
         // TODO: get parameters from spec
         //
         // TODO: store this function... when you figure
@@ -43,48 +126,75 @@ impl Gen {
             noise::open_simplex3::<f64>, 6
         ).wavelength(1.0);
 
-        // Calculate height for this cell from world spec.
-        // To do this, project the cell onto a unit sphere
-        // and sample 3D simplex noise to get a height value.
-        //
-        // TODO: split out a proper world generator
-        // that layers in lots of different kinds of noise etc.
-        let land_pt3 = self.spec.cell_center_on_unit_sphere(cell_pos);
-        let cell_pt3 = self.spec.cell_center_center(cell_pos);
-
-        // More temp; TODO: make a different Gen for flat earth.
+        // Calculate height for this point from world spec
+        // by sampling 3D simplex noise on the unit sphere.
         // Vary a little bit around 1.0.
-        let delta = if self.spec.flat {
-            self.spec.ocean_radius * 0.1
-        } else {
+        let delta =
             terrain_noise.apply(&self.pt, land_pt3.as_ref())
             * self.spec.ocean_radius
-            * 0.3
-        };
-        let land_height = self.spec.ocean_radius + delta;
-        // TEMP: ...
-        use na::Norm;
-        let cell_height = cell_pt3.as_vector().norm();
-        let material = if cell_height < land_height {
-            Material::Dirt
-        } else if cell_height < self.spec.ocean_radius {
-            Material::Water
-        } else {
-            Material::Air
-        };
-        Cell {
-            material: material,
-            // `Globe` fills this in; it's not really a property
-            // of the naturally generated world, and it's not
-            // deterministic from the world seed, so we don't
-            // want to pollute `Gen` with it.
-            //
-            // TODO: probably remove this? We're just using
-            // temporarily to create some texture across
-            // cells to make them easy to tell apart and look
-            // kinda nice, but this probably isn't a great
-            // long-term solution...
-            shade: 1.0,
+            * 0.3;
+        self.spec.ocean_radius + delta
+    }
+}
+
+/// Dirt up to a constant height above sea level, everywhere.
+///
+/// Mostly useful for testing, where you want to know exactly where
+/// the ground is without having to go looking for it.
+pub struct FlatGen {
+    spec: Spec,
+}
+
+impl FlatGen {
+    pub fn new(spec: Spec) -> FlatGen {
+        assert!(spec.is_valid(), "Invalid globe spec!");
+        FlatGen {
+            spec: spec,
         }
     }
 }
+
+impl WorldGen for FlatGen {
+    fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    fn cell_at(&self, cell_pos: CellPos) -> Cell {
+        let land_height = self.land_height_at(self.spec.cell_center_on_unit_sphere(cell_pos));
+        cell_at_height(&self.spec, cell_pos, land_height)
+    }
+
+    fn land_height_at(&self, _pt_on_unit_sphere: Pt3) -> f64 {
+        self.spec.ocean_radius * 1.1
+    }
+}
+
+// Dirt below `land_height`, water below the ocean radius,
+// and air everywhere else.
+fn cell_at_height(spec: &Spec, cell_pos: CellPos, land_height: f64) -> Cell {
+    use na::Norm;
+    let cell_pt3 = spec.cell_center_center(cell_pos);
+    let cell_height = cell_pt3.as_vector().norm();
+    let material = if cell_height < land_height {
+        material::DIRT
+    } else if cell_height < spec.ocean_radius {
+        material::WATER
+    } else {
+        material::AIR
+    };
+    Cell {
+        material: material,
+        // `ChunkSystem` fills this in; it's not really a property
+        // of the naturally generated world, so we don't want to
+        // pollute `WorldGen` with it.
+        //
+        // TODO: probably remove this? We're just using
+        // temporarily to create some texture across
+        // cells to make them easy to tell apart and look
+        // kinda nice, but this probably isn't a great
+        // long-term solution...
+        shade: 1.0,
+        light: 0,
+        flow: 0,
+    }
+}
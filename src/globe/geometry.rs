@@ -7,7 +7,8 @@ use slog::Logger;
 use super::spec::Spec;
 use super::{Globe, CellPos, Cursor, ChunkOrigin};
 use super::globe::GlobeGuts;
-use super::chunk::{ Chunk, Material };
+use super::chunk::Chunk;
+use super::MaterialRegistry;
 use super::cell_shape;
 
 // `Geometry` doesn't store a reference to a `Globe`,
@@ -32,16 +33,21 @@ impl Geometry {
         }
     }
 
-    pub fn build_collision_mesh(&self, globe: &Globe, origin: ChunkOrigin) -> TriMesh<na::Point3<f32>> {
+    pub fn build_collision_mesh(
+        &self,
+        globe: &Globe,
+        materials: &MaterialRegistry,
+        origin: ChunkOrigin,
+    ) -> TriMesh<na::Point3<f32>> {
         use std::sync::Arc;
 
-        let (vertices, indices) = self.make_geometry(globe, origin);
+        let (vertices, indices) = self.make_geometry(globe, materials, origin);
 
         return TriMesh::new(Arc::new(vertices), Arc::new(indices), None, None);
     }
 
     // Make vertices and list of indices into that array for triangle faces.
-    fn make_geometry(&self, globe: &Globe, origin: ChunkOrigin)
+    fn make_geometry(&self, globe: &Globe, materials: &MaterialRegistry, origin: ChunkOrigin)
         -> (Vec<na::Point3<f32>>, Vec<na::Point3<usize>>)
     {
         debug!(self.log, "Making chunk geometry for globe"; "chunks" => globe.chunks().len());
@@ -53,7 +59,7 @@ impl Geometry {
             for chunk in globe.chunks() {
                 // TODO: factor out
                 self.make_chunk_geometry(
-                    globe, origin,
+                    globe, materials, origin,
                     &mut vertex_data,
                     &mut index_data,
                 );
@@ -84,6 +90,7 @@ impl Geometry {
     pub fn make_chunk_geometry(
         &self,
         globe: &Globe,
+        materials: &MaterialRegistry,
         origin: ChunkOrigin,
         vertex_data: &mut Vec<na::Point3<f32>>,
         index_data: &mut Vec<na::Point3<usize>>
@@ -110,14 +117,15 @@ impl Geometry {
 
                     cursor.set_pos(cell_pos);
 
-                    if self.cull_cell(&cursor) {
+                    if self.cull_cell(&cursor, materials) {
                        continue;
                     }
 
-                    // Don't make geometry for air
+                    // Don't make geometry for air, or anything
+                    // else we can move through.
                     {
                         let cell = cursor.cell().expect("Tried to build geometry for a chunk that isn't loaded.");
-                        if cell.material == Material::Air {
+                        if !materials.is_solid(cell.material) {
                             continue;
                         }
                     }
@@ -213,7 +221,7 @@ impl Geometry {
         }
     }
 
-    fn cull_cell(&self, cursor: &Cursor) -> bool {
+    fn cull_cell(&self, cursor: &Cursor, materials: &MaterialRegistry) -> bool {
         use super::Neighbors;
 
         let resolution = cursor.globe().spec().root_resolution;
@@ -221,13 +229,13 @@ impl Geometry {
         let cell_pos = cursor.pos();
         let mut neighbor_cursor = cursor.clone();
 
-        // If none of the neighboring cells contain air,
+        // If we can't see through any of the neighboring cells,
         // then we won't render the cell at all.
         let neighbors = Neighbors::new(cell_pos, resolution);
         for neighbor_pos in neighbors {
             neighbor_cursor.set_pos(neighbor_pos);
             if let Some(neighbor) = neighbor_cursor.cell() {
-                if neighbor.material == Material::Air {
+                if !materials.is_opaque(neighbor.material) {
                     // This cell can be seen; we can't cull it.
                     return false;
                 }
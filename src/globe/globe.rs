@@ -1,33 +1,38 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
+use std::io;
+use std::sync::Arc;
+use std::io::{ Read, Write };
 
 use specs;
 
 use slog::Logger;
 
 use super::{ origin_of_chunk_owning, origin_of_chunk_in_same_root_containing };
-use super::Root;
 use super::{ CellPos, PosInOwningRoot, ChunkOrigin };
 use super::Neighbors;
-use super::chunk::{ Chunk, Cell, Material };
+use super::chunk::{ Chunk, Cell };
+use super::material::{ MaterialId, MaterialRegistry };
 use super::spec::Spec;
-use super::gen::Gen;
+use super::gen::{ WorldGen, SimpleGen, FlatGen };
+use super::biome::BiomeGen;
+use super::save;
 use ::Spatial;
 
-// TODO: lift to module level.
-const ROOT_QUADS: u8 = 5;
-
-// TODO: how many to build high?
-// TODO: remove me
-const Z_CHUNKS: i64 = 5;
-
-// TODO: split out a WorldGen type that handles all the procedural
-// generation, because none of that really needs to be tangled
-// with the realised Globe.
+/// A planet made of hexagonal cells, split into `Chunk`s.
+///
+/// Only loaded chunks are looked at by the simulation, but a chunk that
+/// has been modified can't be generated again, so unloading one keeps it
+/// in memory rather than throwing it away. Nothing is ever written to
+/// disk unless you call `save_to`; if the game stops without doing that,
+/// every change is lost. Memory grows with the number of chunks that
+/// have been modified, loaded or not.
 pub struct Globe {
     spec: Spec,
-    // TODO: temporarily making this public because I'm planning to
-    // rip it out of `Globe` anyway.
-    pub gen: Gen,
+    // Generates the content of chunks that have never been
+    // loaded (or never been modified) before.
+    //
+    // Shared so that chunks can be generated on other threads.
+    gen: Arc<Box<WorldGen>>,
     // Map chunk origins to chunks.
     //
     // TODO: figure out what structure to store these in.
@@ -37,6 +42,20 @@ pub struct Globe {
     // pseudo-chunks for rendering planets at a distance.
     // But maybe you can put that off?
     chunks: HashMap<ChunkOrigin, Chunk>,
+    // Modified chunks that aren't loaded: either unloaded since
+    // they were modified, or read by `load_from_with_gen` and not
+    // loaded yet. We can't just generate these again, so we hang
+    // on to them until they're loaded again. Saving the globe
+    // writes them out, but doesn't let go of them.
+    //
+    // TODO: write these out to disk instead of keeping them in
+    // memory forever; that needs somewhere to put them, and a way
+    // to read single chunks back out of a save.
+    saved_chunks: HashMap<ChunkOrigin, Chunk>,
+    // Map the origin of each chunk to the origins of the loaded
+    // chunks that keep copies of some of its cells, so we know
+    // where to copy cells to when they change.
+    dependents: HashMap<ChunkOrigin, Vec<ChunkOrigin>>,
     log: Logger,
 }
 
@@ -58,90 +77,246 @@ impl<'a> GlobeGuts<'a> for Globe {
 }
 
 impl Globe {
+    /// Make a globe whose terrain is generated by `SimpleGen`.
     pub fn new(spec: Spec, parent_log: &Logger) -> Globe {
+        Globe::new_with_gen(Box::new(SimpleGen::new(spec)), parent_log)
+    }
+
+    /// Make a globe whose terrain is generated by `gen`.
+    /// The globe takes its spec from the generator.
+    pub fn new_with_gen(gen: Box<WorldGen>, parent_log: &Logger) -> Globe {
+        let spec = gen.spec();
+        assert!(spec.is_valid(), "Invalid globe spec!");
         let globe = Globe {
             spec: spec,
-            gen: Gen::new(spec),
+            gen: Arc::new(gen),
             chunks: HashMap::new(),
+            saved_chunks: HashMap::new(),
+            dependents: HashMap::new(),
             log: parent_log.new(o!()),
         };
         globe
     }
 
-    pub fn new_example(parent_log: &Logger) -> Globe {
-        Globe::new(
-            Spec {
-                seed: 13,
-                floor_radius: 0.91, // TODO: make it ~Earth
-                // NOTE: Don't let ocean radius be a neat multiple of block
-                // height above floor radius, or we'll end up with
-                // z-fighting in evaluating what blocks are water/air.
-                ocean_radius: 1.13,
-                block_height: 0.02,
-                root_resolution: [32, 64],
-                chunk_resolution: [16, 16, 4],
-                flat: false,
+    /// Load a globe previously written by `save_to`.
+    ///
+    /// The save file only records the globe's spec, not how its
+    /// terrain was generated, so `make_gen` is given the spec and
+    /// must return the same kind of generator the globe was
+    /// originally made with. Otherwise every chunk that wasn't
+    /// saved will come back with different terrain.
+    ///
+    /// Modified chunks read from the save are held until `ChunkSystem`
+    /// asks for them; everything else will be generated as usual.
+    ///
+    /// Fails if any saved cell is made of something that isn't
+    /// in `materials`.
+    pub fn load_from_with_gen<R, F>(
+        reader: &mut R,
+        make_gen: F,
+        materials: &MaterialRegistry,
+        parent_log: &Logger,
+    ) -> io::Result<Globe>
+        where R: Read, F: FnOnce(Spec) -> Box<WorldGen>
+    {
+        let spec = save::read_header(reader)?;
+        let mut globe = Globe::new_with_gen(make_gen(spec), parent_log);
+        let chunk_count = save::read_u32(reader)?;
+        for _ in 0..chunk_count {
+            let chunk = save::read_chunk(reader, &spec, materials)?;
+            globe.saved_chunks.insert(chunk.origin, chunk);
+        }
+        debug!(globe.log, "Loaded globe"; "modified_chunks" => chunk_count);
+        Ok(globe)
+    }
+
+    /// Write this globe's spec and all modified chunks
+    /// (loaded or not) to `writer`.
+    ///
+    /// Chunks that have never been modified aren't written;
+    /// they can be generated again from the spec.
+    pub fn save_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        save::write_header(writer, &self.spec)?;
+        let modified_chunks = self.modified_chunks();
+        save::write_u32(writer, modified_chunks.len() as u32)?;
+        for chunk in &modified_chunks {
+            save::write_chunk(writer, chunk)?;
+        }
+        debug!(self.log, "Saved globe"; "modified_chunks" => modified_chunks.len());
+        Ok(())
+    }
+
+    /// All chunks that have been modified since they were generated,
+    /// whether they're loaded or not.
+    pub fn modified_chunks(&self) -> Vec<&Chunk> {
+        self.chunks.values()
+            .filter(|chunk| chunk.is_modified())
+            .chain(self.saved_chunks.values())
+            .collect()
+    }
+
+    /// Replace our copy of a chunk with one from somewhere else;
+    /// e.g. a server that has the authoritative copy of this globe.
+    ///
+    /// Does nothing if we already have that version of the chunk or
+    /// a newer one. If the chunk isn't loaded, then it's held until
+    /// `ChunkSystem` asks for it, just like modified chunks read from
+    /// a save file.
+    pub fn receive_chunk(&mut self, chunk: Chunk) {
+        let origin = chunk.origin;
+        match self.chunks.get_mut(&origin) {
+            Some(loaded_chunk) => {
+                if loaded_chunk.version >= chunk.version {
+                    return;
+                }
+                loaded_chunk.cells = chunk.cells;
+                loaded_chunk.version = chunk.version;
+                // The copies of other chunks' cells that came with it
+                // might be older or newer than ours; copy them again.
+                for neighbor in &mut loaded_chunk.authoritative_neighbors {
+                    neighbor.last_known_version = 0;
+                }
+                loaded_chunk.mark_view_as_dirty();
             },
-            parent_log,
-        )
+            None => {
+                let is_newer = self.saved_chunks.get(&origin)
+                    .map_or(true, |saved_chunk| saved_chunk.version < chunk.version);
+                if is_newer {
+                    self.saved_chunks.insert(origin, chunk);
+                }
+                return;
+            },
+        }
+
+        // Only this chunk and the ones that keep copies of its cells
+        // can be out of date now; leave everything else alone.
+        self.maybe_copy_authoritative_cells(origin);
+        let dependent_origins = self.dependents.get(&origin)
+            .cloned()
+            .unwrap_or_else(Vec::new);
+        for dependent_origin in dependent_origins {
+            self.maybe_copy_authoritative_cells(dependent_origin);
+        }
+        self.mark_views_of_chunks_adjacent_to_chunk_as_dirty(origin);
+    }
+
+    /// Take the saved copy of a chunk that was previously modified,
+    /// if there is one, so that it can be loaded instead of being
+    /// generated from scratch.
+    pub fn take_saved_chunk(&mut self, origin: ChunkOrigin) -> Option<Chunk> {
+        self.saved_chunks.remove(&origin)
+    }
+
+    pub fn new_example(parent_log: &Logger) -> Globe {
+        let spec = Spec {
+            seed: 13,
+            floor_radius: 0.91, // TODO: make it ~Earth
+            // NOTE: Don't let ocean radius be a neat multiple of block
+            // height above floor radius, or we'll end up with
+            // z-fighting in evaluating what blocks are water/air.
+            ocean_radius: 1.13,
+            block_height: 0.02,
+            root_resolution: [32, 64],
+            chunk_resolution: [16, 16, 4],
+        };
+        Globe::new_with_gen(Box::new(BiomeGen::new(spec)), parent_log)
     }
 
     pub fn new_small_flat(parent_log: &Logger) -> Globe {
-        Globe::new(
-            Spec {
-                seed: 13,
-                floor_radius: 0.91,
-                ocean_radius: 1.13,
-                block_height: 0.02,
-                root_resolution: [8, 16],
-                chunk_resolution: [4, 4, 4],
-                flat: true,
-            },
-            parent_log,
-        )
+        let spec = Spec {
+            seed: 13,
+            floor_radius: 0.91,
+            ocean_radius: 1.13,
+            block_height: 0.02,
+            root_resolution: [8, 16],
+            chunk_resolution: [4, 4, 4],
+        };
+        Globe::new_with_gen(Box::new(FlatGen::new(spec)), parent_log)
     }
 
     pub fn spec(&self) -> Spec {
         self.spec
     }
 
-    // TODO: there's no way this should be public.
-    // Replace with a better interface for mutating cell content
-    // that automatically ensures that all neighbouring chunks
-    // get updated, etc.
-    pub fn copy_all_authoritative_cells(&mut self) {
-        // Calculate how many chunks to a root in each direction in (x, y).
-        let chunks_per_root = [
-            self.spec.root_resolution[0] / self.spec.chunk_resolution[0],
-            self.spec.root_resolution[1] / self.spec.chunk_resolution[1],
-        ];
+    /// The generator used for chunks that haven't been modified.
+    pub fn gen(&self) -> &WorldGen {
+        &**self.gen
+    }
 
-        // Copy cells over from chunks that own cells to those that
-        // contain the same cells but don't own them.
-        // TODO: we won't be able to assume that this is always
-        // possible for long, because often most of the chunks
-        // won't be loaded. This needs to be hooked up in a more
-        // subtle way. :)
-        for root_index in 0..ROOT_QUADS {
-            let root = Root { index: root_index };
-            for z in 0..Z_CHUNKS {
-                for y in 0..chunks_per_root[1] {
-                    for x in 0..chunks_per_root[0] {
-                        let origin = ChunkOrigin::new(
-                            CellPos {
-                                root: root,
-                                x: x * self.spec.chunk_resolution[0],
-                                y: y * self.spec.chunk_resolution[1],
-                                z: z * self.spec.chunk_resolution[2],
-                            },
-                            self.spec.root_resolution,
-                            self.spec.chunk_resolution,
-                        );
-                        self.maybe_copy_authoritative_cells(origin);
+    /// Like `gen`, but for passing to other threads;
+    /// e.g. to generate chunks in the background.
+    pub fn shared_gen(&self) -> Arc<Box<WorldGen>> {
+        self.gen.clone()
+    }
+
+    /// A globe with copies of just the loaded chunks that building
+    /// geometry for the chunk at `origin` looks at: the chunk itself,
+    /// and any containing neighbours of its cells or of the cells
+    /// above and below them.
+    ///
+    /// Useful for building geometry on another thread,
+    /// while this globe carries on changing.
+    pub fn snapshot_for_view(&self, origin: ChunkOrigin) -> Globe {
+        // Work out which chunks a `Cursor` might visit from each
+        // cell in the chunk; see `View::cull_cell`, and the cells
+        // around each vertex that `View::vertex_shading` looks at.
+        let mut origins: HashSet<ChunkOrigin> = HashSet::new();
+        origins.insert(origin);
+        let end_x = origin.pos().x + self.spec.chunk_resolution[0];
+        let end_y = origin.pos().y + self.spec.chunk_resolution[1];
+        let end_z = origin.pos().z + self.spec.chunk_resolution[2] - 1;
+        for cell_z in origin.pos().z..(end_z + 1) {
+            for cell_y in origin.pos().y..(end_y + 1) {
+                for cell_x in origin.pos().x..(end_x + 1) {
+                    let cell_pos = CellPos {
+                        root: origin.pos().root,
+                        x: cell_x,
+                        y: cell_y,
+                        z: cell_z,
+                    };
+                    for layer_z in (cell_z - 1)..(cell_z + 2) {
+                        if layer_z < 0 {
+                            continue;
+                        }
+                        let layer_pos = cell_pos.set_z(layer_z);
+                        for neighbor_pos in Neighbors::new(layer_pos, self.spec.root_resolution) {
+                            origins.insert(self.origin_of_chunk_in_same_root_containing(neighbor_pos));
+                        }
                     }
                 }
             }
         }
+
+        let chunks = origins.into_iter()
+            .filter_map(|origin| self.chunks.get(&origin))
+            .map(|chunk| (chunk.origin, chunk.clone()))
+            .collect();
+        Globe {
+            spec: self.spec,
+            gen: self.gen.clone(),
+            chunks: chunks,
+            saved_chunks: HashMap::new(),
+            dependents: HashMap::new(),
+            log: self.log.new(o!()),
+        }
+    }
+
+    /// Bring every loaded chunk up to date with the cells it shares
+    /// with its neighbours.
+    ///
+    /// You don't need to call this after `set_cell`; it's only needed
+    /// after loading new chunks, which might be out of date with
+    /// (or have newer data than) the chunks around them.
+    pub fn copy_all_authoritative_cells(&mut self) {
+        // Copy cells over from chunks that own cells to those that
+        // contain the same cells but don't own them.
+        //
+        // Only loaded chunks can be brought up to date; any chunks
+        // that aren't loaded yet will catch up when they're loaded.
+        let origins: Vec<ChunkOrigin> = self.chunks.keys().cloned().collect();
+        for origin in origins {
+            self.maybe_copy_authoritative_cells(origin);
+        }
     }
 
     fn maybe_copy_authoritative_cells(&mut self, target_chunk_origin: ChunkOrigin) {
@@ -230,7 +405,7 @@ impl Globe {
     pub fn find_lowest_cell_containing(
         &self,
         column: CellPos,
-        material: Material
+        material: MaterialId
     ) -> Option<CellPos> {
         // Translate into owning root, then start at bedrock.
         let mut pos = PosInOwningRoot::new(column, self.spec.root_resolution);
@@ -287,56 +462,199 @@ impl Globe {
         }
     }
 
-    /// Most `Chunks`s will have an associated `ChunkView`. Indicate that the
-    /// chunk (or something else affecting its visibility) has been modified
-    /// since the view was last updated.
-    pub fn mark_chunk_views_affected_by_cell_as_dirty(&mut self, pos: CellPos) {
-        // Translate into owning root.
-        // TODO: wrapper types so we don't have to do
-        // this sort of thing defensively!
-        // TODO: is it even necessary at all here? All we really care about
-        // is consistency in which chunk we look at for the centre cell,
-        // and the one.....
-        // TODO: really, just rewrite this whole function. It doesn't really work.
-        let pos_in_owning_root = PosInOwningRoot::new(pos, self.spec.root_resolution);
-
-        // TODO: this (Vec) is super slow! Replace with a less brain-dead solution.
-        // Just committing this one now to patch over a kinda-regression in that
-        // the existing bug of not doing this at all just become a lot more
-        // obvious now that I'm doing a better job of culling cells.
-        let mut dirty_cells: Vec<PosInOwningRoot> = Vec::new();
-        dirty_cells.push(pos_in_owning_root);
-        dirty_cells.extend(
-            Neighbors::new(pos_in_owning_root.into(), self.spec.root_resolution)
-                .map(|neighbor_pos| PosInOwningRoot::new(neighbor_pos, self.spec.root_resolution))
-        );
-        // Gah, filthy hacks. This is to get around not having a way to query for
-        // "all chunks containing this cell".
-        let mut cells_in_dirty_chunks: Vec<PosInOwningRoot> = Vec::new();
-        for dirty_cell in dirty_cells {
-            cells_in_dirty_chunks.extend(
-                Neighbors::new(dirty_cell.into(), self.spec.root_resolution)
-                    .map(|neighbor_pos| PosInOwningRoot::new(neighbor_pos, self.spec.root_resolution))
+    /// Replace the content of the cell at `pos`.
+    ///
+    /// This takes care of everything else that needs to happen when
+    /// a cell changes: the version of the chunk that owns it is bumped,
+    /// the new content is copied into any other loaded chunks that
+    /// keep a copy of the cell, and the views of any chunks whose
+    /// visible cells might have changed are marked as dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk that owns `pos` isn't loaded.
+    pub fn set_cell(&mut self, pos: PosInOwningRoot, cell: Cell) {
+        self.set_cells(&[(pos, cell)]);
+    }
+
+    /// Like `set_cell`, but for lots of cells at once.
+    ///
+    /// The version of each chunk that owns any of the cells is only
+    /// bumped once, no matter how many of its cells changed, so anyone
+    /// keeping track of changes (e.g. a server sending chunks to clients)
+    /// only sees a single change. If the same cell appears more than once,
+    /// the last one wins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk that owns any of the cells isn't loaded.
+    pub fn set_cells(&mut self, changes: &[(PosInOwningRoot, Cell)]) {
+        let resolution = self.spec.root_resolution;
+
+        // Group the changes by which chunk owns them,
+        // keeping them in the order they were given.
+        let mut owner_origins: Vec<ChunkOrigin> = Vec::new();
+        let mut changes_by_owner: HashMap<ChunkOrigin, Vec<(PosInOwningRoot, Cell)>> = HashMap::new();
+        for &(pos, cell) in changes {
+            let owner_origin = self.origin_of_chunk_owning(pos);
+            changes_by_owner.entry(owner_origin)
+                .or_insert_with(|| {
+                    owner_origins.push(owner_origin);
+                    Vec::new()
+                })
+                .push((pos, cell));
+        }
+
+        for owner_origin in owner_origins {
+            let owner_changes = &changes_by_owner[&owner_origin];
+            let old_version = {
+                let owner = self.chunks.get_mut(&owner_origin)
+                    .expect("Uh oh, I don't know how to handle chunks that aren't loaded yet.");
+                for &(pos, cell) in owner_changes {
+                    *owner.cell_mut(pos.into()) = cell;
+                }
+                owner.version += 1;
+                owner.version - 1
+            };
+
+            // Copy the new content into any other chunks that have these cells.
+            let dependent_origins = self.dependents.get(&owner_origin)
+                .cloned()
+                .unwrap_or_else(Vec::new);
+            let mut stale_origins: Vec<ChunkOrigin> = Vec::new();
+            for dependent_origin in dependent_origins {
+                let dependent = match self.chunks.get_mut(&dependent_origin) {
+                    // `dependents` only lists loaded chunks, but
+                    // there's no harm in being defensive.
+                    None => continue,
+                    Some(dependent) => dependent,
+                };
+                let copies: Vec<(CellPos, Cell)> = {
+                    let neighbor = match dependent.authoritative_neighbors.iter_mut()
+                        .find(|neighbor| neighbor.origin == owner_origin)
+                    {
+                        None => continue,
+                        Some(neighbor) => neighbor,
+                    };
+                    if neighbor.last_known_version != old_version {
+                        // This chunk was already behind for some other reason;
+                        // copying just these cells won't bring it up to date.
+                        stale_origins.push(dependent_origin);
+                        continue;
+                    }
+                    neighbor.last_known_version = old_version + 1;
+                    let mut copies: Vec<(CellPos, Cell)> = Vec::new();
+                    for shared_pos in &neighbor.shared_cells {
+                        let shared_pos_in_owning_root = PosInOwningRoot::new(*shared_pos, resolution);
+                        // Later changes to the same cell win.
+                        let change = owner_changes.iter().rev()
+                            .find(|&&(pos, _)| pos == shared_pos_in_owning_root);
+                        if let Some(&(_, cell)) = change {
+                            copies.push((*shared_pos, cell));
+                        }
+                    }
+                    copies
+                };
+                for (copy_pos, cell) in copies {
+                    *dependent.cell_mut(copy_pos) = cell;
+                }
+            }
+            for stale_origin in stale_origins {
+                self.maybe_copy_authoritative_cells(stale_origin);
+            }
+        }
+
+        // Changing these cells might change how they look, and also
+        // hide or reveal faces of the cells around them.
+        let mut affected_cells: HashSet<PosInOwningRoot> = HashSet::new();
+        for &(pos, _) in changes {
+            affected_cells.insert(pos);
+            affected_cells.extend(
+                Neighbors::new(pos.into(), resolution)
+                    .map(|neighbor_pos| PosInOwningRoot::new(neighbor_pos, resolution))
             );
         }
-        for dirty_pos in cells_in_dirty_chunks {
-            let chunk_origin = self.origin_of_chunk_owning(dirty_pos);
-            // It's fine for the chunk to not be loaded.
-            if let Some(chunk) = self.chunks.get_mut(&chunk_origin) {
-                chunk.mark_view_as_dirty();
+        for affected_pos in affected_cells {
+            let affected_origins = self.origins_of_loaded_chunks_containing(affected_pos);
+            for origin in affected_origins {
+                if let Some(chunk) = self.chunks.get_mut(&origin) {
+                    chunk.mark_view_as_dirty();
+                }
             }
         }
     }
 
-    // TODO: this all lacks subtlety; we only actually need to update the
-    // destination chunk's data if the dirty cell was on the edge of
-    // the chunk. This needs some thought on a good interface for mutating
-    // chunks that doesn't allow oopsing this...
-    pub fn increment_chunk_version_for_cell(&mut self, pos: PosInOwningRoot) {
-        let chunk_origin = self.origin_of_chunk_owning(pos.into());
-        let chunk = self.chunks.get_mut(&chunk_origin)
-            .expect("Uh oh, I don't know how to handle chunks that aren't loaded yet.");
-        chunk.version += 1;
+    // The chunk that owns `pos`, and any other chunks that keep
+    // a copy of it; only those that are loaded.
+    fn origins_of_loaded_chunks_containing(&self, pos: PosInOwningRoot) -> Vec<ChunkOrigin> {
+        let resolution = self.spec.root_resolution;
+        let owner_origin = self.origin_of_chunk_owning(pos);
+        let mut origins: Vec<ChunkOrigin> = Vec::new();
+        if self.chunks.contains_key(&owner_origin) {
+            origins.push(owner_origin);
+        }
+        let dependent_origins = match self.dependents.get(&owner_origin) {
+            None => return origins,
+            Some(dependent_origins) => dependent_origins,
+        };
+        for dependent_origin in dependent_origins {
+            let dependent = match self.chunks.get(dependent_origin) {
+                None => continue,
+                Some(dependent) => dependent,
+            };
+            let has_copy = dependent.authoritative_neighbors.iter()
+                .filter(|neighbor| neighbor.origin == owner_origin)
+                .flat_map(|neighbor| neighbor.shared_cells.iter())
+                .any(|shared_pos| PosInOwningRoot::new(*shared_pos, resolution) == pos);
+            if has_copy {
+                origins.push(*dependent_origin);
+            }
+        }
+        origins
+    }
+
+    /// Mark the views of all loaded chunks adjacent to the chunk at the given
+    /// origin as dirty.
+    ///
+    /// Which cells we cull at the edges of a chunk depends on what's in
+    /// the chunks next to it, so when a chunk is loaded its neighbours
+    /// might need to show some cells they were previously hiding.
+    pub fn mark_views_of_chunks_adjacent_to_chunk_as_dirty(&mut self, origin: ChunkOrigin) {
+        // Chunks this one copies cells from.
+        let mut adjacent_origins: Vec<ChunkOrigin> = match self.chunks.get(&origin) {
+            Some(chunk) => chunk.authoritative_neighbors.iter()
+                .map(|neighbor| neighbor.origin)
+                .collect(),
+            None => Vec::new(),
+        };
+        // Chunks that copy cells from this one.
+        if let Some(dependent_origins) = self.dependents.get(&origin) {
+            adjacent_origins.extend(dependent_origins.iter().cloned());
+        }
+        // Chunks immediately above or below this one. These don't share
+        // any cells with it, so we have to work them out ourselves.
+        let chunk_depth = self.spec.chunk_resolution[2];
+        let pos = *origin.pos();
+        let mut z_neighbors = vec![pos.z + chunk_depth];
+        if pos.z >= chunk_depth {
+            z_neighbors.push(pos.z - chunk_depth);
+        }
+        for z in z_neighbors {
+            adjacent_origins.push(ChunkOrigin::new(
+                CellPos { z: z, .. pos },
+                self.spec.root_resolution,
+                self.spec.chunk_resolution,
+            ));
+        }
+
+        for adjacent_origin in adjacent_origins {
+            if adjacent_origin == origin {
+                continue;
+            }
+            if let Some(adjacent_chunk) = self.chunks.get_mut(&adjacent_origin) {
+                adjacent_chunk.mark_view_as_dirty();
+            }
+        }
     }
 
     /// Add the given chunk to the globe.
@@ -353,19 +671,60 @@ impl Globe {
         // the same resolution.
 
         let chunk_origin = chunk.origin;
+        let owner_origins: Vec<ChunkOrigin> = chunk.authoritative_neighbors.iter()
+            .map(|neighbor| neighbor.origin)
+            .collect();
         if self.chunks.insert(chunk_origin, chunk).is_some() {
             panic!("There was already a chunk loaded at the same origin!");
         }
+        for owner_origin in owner_origins {
+            self.dependents.entry(owner_origin)
+                .or_insert_with(Vec::new)
+                .push(chunk_origin);
+        }
     }
 
     /// Remove the chunk at the given chunk origin. Returns the removed chunk.
     ///
+    /// If the chunk has been modified, then a copy of it is kept in memory
+    /// until it's loaded again; see `take_saved_chunk`. This does not write
+    /// anything to disk; use `save_to` for that.
+    ///
     /// # Panics
     ///
     /// Panics if there was no chunk loaded at the given chunk origin.
     pub fn remove_chunk(&mut self, chunk_origin: ChunkOrigin) -> Chunk {
-        self.chunks.remove(&chunk_origin)
-            .expect("Attempted to remove a chunk that was not loaded")
+        let chunk = self.chunks.remove(&chunk_origin)
+            .expect("Attempted to remove a chunk that was not loaded");
+
+        for neighbor in &chunk.authoritative_neighbors {
+            let is_now_empty = match self.dependents.get_mut(&neighbor.origin) {
+                None => false,
+                Some(dependent_origins) => {
+                    dependent_origins.retain(|origin| *origin != chunk_origin);
+                    dependent_origins.is_empty()
+                },
+            };
+            if is_now_empty {
+                self.dependents.remove(&neighbor.origin);
+            }
+        }
+
+        // We can't generate a modified chunk again from scratch,
+        // so hang on to a copy of it.
+        if chunk.is_modified() {
+            let mut saved_chunk = Chunk::new(
+                chunk.origin,
+                chunk.cells.clone(),
+                self.spec.root_resolution,
+                self.spec.chunk_resolution,
+            );
+            saved_chunk.version = chunk.version;
+            self.saved_chunks.insert(chunk_origin, saved_chunk);
+            debug!(self.log, "Keeping modified chunk in memory"; "unloaded_modified_chunks" => self.saved_chunks.len());
+        }
+
+        chunk
     }
 }
 
@@ -382,16 +741,6 @@ impl<'a> Globe {
         chunk.cell(pos.into())
     }
 
-    pub fn authoritative_cell_mut(
-        &'a mut self,
-        pos: PosInOwningRoot,
-    ) -> &'a mut Cell {
-        let chunk_origin = self.origin_of_chunk_owning(pos);
-        let chunk = self.chunks.get_mut(&chunk_origin)
-            .expect("Uh oh, I don't know how to handle chunks that aren't loaded yet.");
-        chunk.cell_mut(pos.into())
-    }
-
     pub fn maybe_non_authoritative_cell(
         &'a self,
         pos: CellPos,
@@ -401,6 +750,16 @@ impl<'a> Globe {
             .expect("Uh oh, I don't know how to handle chunks that aren't loaded yet.");
         chunk.cell(pos)
     }
+
+    /// Like `maybe_non_authoritative_cell`, but returns `None`
+    /// instead of panicking if the relevant chunk isn't loaded.
+    pub fn loaded_cell(
+        &'a self,
+        pos: CellPos,
+    ) -> Option<&'a Cell> {
+        let chunk_origin = self.origin_of_chunk_in_same_root_containing(pos);
+        self.chunks.get(&chunk_origin).map(|chunk| chunk.cell(pos))
+    }
 }
 
 impl specs::Component for Globe {
@@ -0,0 +1,388 @@
+//! How brightly lit each cell is, for drawing.
+//!
+//! Light comes from two places: the sky, which lights every
+//! cell that isn't opaque straight down from the top of the loaded
+//! part of the globe until it hits something opaque; and materials
+//! that give off light of their own. Both spread out from there to
+//! neighbouring cells that aren't opaque, getting one level dimmer
+//! with every step.
+//!
+//! Light levels are stored in each `Cell`, but they're not part of
+//! the content of the globe; they're worked out one chunk at a time
+//! from whatever is around it, and never saved or sent anywhere.
+
+use std::collections::VecDeque;
+
+use super::{ Globe, CellPos, ChunkOrigin, Cursor, Neighbors };
+use super::MaterialRegistry;
+
+/// Full daylight.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Work out light levels for every cell in the chunk at `origin`,
+/// including those it shares with its neighbours.
+///
+/// Uses the light levels already stored in any loaded neighbouring
+/// chunks, so light spreads across chunk boundaries. If light along
+/// this chunk's boundary changes, then the views of all its neighbours
+/// (including those above and below) are marked as dirty, so that
+/// whatever draws them will relight them in turn.
+///
+/// Does nothing if the chunk isn't loaded.
+pub fn light_chunk(globe: &mut Globe, materials: &MaterialRegistry, origin: ChunkOrigin) {
+    use super::globe::GlobeGuts;
+
+    let light = match chunk_light(globe, materials, origin) {
+        Some(light) => light,
+        None => return,
+    };
+    let cells = ChunkCells::new(origin, globe.spec().chunk_resolution);
+    let mut is_edge_light_changed = false;
+    {
+        // Inner scope to fight borrowck.
+        let chunk = globe.chunks_mut().get_mut(&origin)
+            .expect("Just found this chunk");
+        for ((cell, cell_light), cell_pos) in chunk.cells.iter_mut().zip(light).zip(cells.iter()) {
+            if cell.light != cell_light && cells.is_on_boundary(cell_pos) {
+                is_edge_light_changed = true;
+            }
+            cell.light = cell_light;
+        }
+    }
+    if is_edge_light_changed {
+        globe.mark_views_of_chunks_adjacent_to_chunk_as_dirty(origin);
+    }
+}
+
+// Light levels for the chunk at `origin`, in the same order
+// as its cells, or `None` if the chunk isn't loaded.
+fn chunk_light(globe: &Globe, materials: &MaterialRegistry, origin: ChunkOrigin) -> Option<Vec<u8>> {
+    use super::globe::GlobeGuts;
+
+    let cell_count = match globe.chunks().get(&origin) {
+        Some(chunk) => chunk.cells.len(),
+        None => return None,
+    };
+    let spec = globe.spec();
+    let cells = ChunkCells::new(origin, spec.chunk_resolution);
+    let mut light: Vec<u8> = vec![0; cell_count];
+    let mut cursor = Cursor::new(globe, *origin.pos());
+    // Cells whose light might need to spread to their neighbours.
+    let mut to_spread: VecDeque<CellPos> = VecDeque::new();
+
+    // Let the sky in from above, down each column until
+    // it hits something opaque.
+    for cell_y in cells.start.y..(cells.end.y + 1) {
+        for cell_x in cells.start.x..(cells.end.x + 1) {
+            let top_pos = CellPos {
+                root: origin.pos().root,
+                x: cell_x,
+                y: cell_y,
+                z: cells.end.z,
+            };
+            if !is_open_to_sky_above(&mut cursor, materials, top_pos) {
+                continue;
+            }
+            for cell_z in (cells.start.z..(cells.end.z + 1)).rev() {
+                let cell_pos = top_pos.set_z(cell_z);
+                cursor.set_pos(cell_pos);
+                let cell = cursor.cell().expect("Chunk should be loaded");
+                if materials.is_opaque(cell.material) {
+                    break;
+                }
+                light[cells.index(cell_pos)] = MAX_LIGHT;
+                to_spread.push_back(cell_pos);
+            }
+        }
+    }
+
+    // Anything that glows, and anything lit just outside the chunk,
+    // lights the cells next to it.
+    for cell_pos in cells.iter() {
+        cursor.set_pos(cell_pos);
+        let material = cursor.cell().expect("Chunk should be loaded").material;
+        let index = cells.index(cell_pos);
+        let emitted = materials.light(material);
+        if emitted > light[index] {
+            light[index] = emitted;
+            to_spread.push_back(cell_pos);
+        }
+
+        if !cells.is_on_boundary(cell_pos) || materials.is_opaque(material) {
+            continue;
+        }
+        let mut brightest_neighbor = 0;
+        for neighbor_pos in Neighbors::new(cell_pos, spec.root_resolution) {
+            if cells.contains(neighbor_pos) {
+                continue;
+            }
+            cursor.set_pos(neighbor_pos);
+            if let Some(neighbor) = cursor.cell() {
+                brightest_neighbor = brightest_neighbor.max(neighbor.light);
+            }
+        }
+        let from_outside = brightest_neighbor.saturating_sub(1);
+        if from_outside > light[index] {
+            light[index] = from_outside;
+            to_spread.push_back(cell_pos);
+        }
+    }
+
+    // Spread the light out, one level dimmer with every step.
+    while let Some(cell_pos) = to_spread.pop_front() {
+        let spread_light = light[cells.index(cell_pos)].saturating_sub(1);
+        if spread_light == 0 {
+            continue;
+        }
+        for neighbor_pos in Neighbors::new(cell_pos, spec.root_resolution) {
+            if !cells.contains(neighbor_pos) {
+                continue;
+            }
+            let neighbor_index = cells.index(neighbor_pos);
+            if light[neighbor_index] >= spread_light {
+                continue;
+            }
+            cursor.set_pos(neighbor_pos);
+            let neighbor = cursor.cell().expect("Chunk should be loaded");
+            if materials.is_opaque(neighbor.material) {
+                continue;
+            }
+            light[neighbor_index] = spread_light;
+            to_spread.push_back(neighbor_pos);
+        }
+    }
+
+    Some(light)
+}
+
+// Whether there's nothing opaque anywhere above `pos`.
+//
+// We don't know what's in chunks that aren't loaded, so assume
+// they're open sky; they'll usually be above the surface.
+fn is_open_to_sky_above(cursor: &mut Cursor, materials: &MaterialRegistry, pos: CellPos) -> bool {
+    let mut above_pos = pos;
+    loop {
+        above_pos.z += 1;
+        cursor.set_pos(above_pos);
+        match cursor.cell() {
+            Some(cell) => if materials.is_opaque(cell.material) {
+                return false;
+            },
+            None => return true,
+        }
+    }
+}
+
+/// Brightness to draw something at, from 0 to 1, for the given
+/// light level. Nothing is ever completely black, so you can still
+/// make out the shape of caves.
+pub fn brightness(light: f32) -> f32 {
+    const MIN_BRIGHTNESS: f32 = 0.2;
+    MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * light / MAX_LIGHT as f32
+}
+
+// The cells in a chunk, including those it shares with its
+// neighbours, in the same order the chunk stores them.
+struct ChunkCells {
+    start: CellPos,
+    end: CellPos,
+}
+
+impl ChunkCells {
+    fn new(origin: ChunkOrigin, chunk_resolution: [i64; 3]) -> ChunkCells {
+        let start = *origin.pos();
+        // Chunks don't share cells in the z-direction,
+        // but do in the x- and y-directions.
+        let end = CellPos {
+            root: start.root,
+            x: start.x + chunk_resolution[0],
+            y: start.y + chunk_resolution[1],
+            z: start.z + chunk_resolution[2] - 1,
+        };
+        ChunkCells {
+            start: start,
+            end: end,
+        }
+    }
+
+    fn contains(&self, pos: CellPos) -> bool {
+        pos.root == self.start.root &&
+        pos.x >= self.start.x && pos.x <= self.end.x &&
+        pos.y >= self.start.y && pos.y <= self.end.y &&
+        pos.z >= self.start.z && pos.z <= self.end.z
+    }
+
+    fn is_on_boundary(&self, pos: CellPos) -> bool {
+        pos.x == self.start.x || pos.x == self.end.x ||
+        pos.y == self.start.y || pos.y == self.end.y ||
+        pos.z == self.start.z || pos.z == self.end.z
+    }
+
+    fn index(&self, pos: CellPos) -> usize {
+        let x_count = self.end.x - self.start.x + 1;
+        let y_count = self.end.y - self.start.y + 1;
+        (
+            (pos.z - self.start.z) * x_count * y_count +
+            (pos.y - self.start.y) * x_count +
+            (pos.x - self.start.x)
+        ) as usize
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item = CellPos> + 'a> {
+        Box::new((self.start.z..(self.end.z + 1)).flat_map(move |z| {
+            (self.start.y..(self.end.y + 1)).flat_map(move |y| {
+                (self.start.x..(self.end.x + 1)).map(move |x| {
+                    CellPos {
+                        root: self.start.root,
+                        x: x,
+                        y: y,
+                        z: z,
+                    }
+                })
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use slog;
+
+    use super::*;
+    use globe::{ Globe, ChunkSystem, CellPos, ChunkOrigin, PosInOwningRoot };
+    use globe::globe::GlobeGuts;
+    use globe::material;
+
+    #[test]
+    fn sky_lights_down_to_the_ground() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let mut globe = Globe::new_small_flat(&log);
+        let spec = globe.spec();
+        let materials = MaterialRegistry::new();
+        let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+        // Away from the edges of chunks and roots.
+        let column = CellPos::default().set_x(2).set_y(2);
+        chunk_sys.load_chunks_near(&mut globe, &[column.set_z(16)]);
+        let air_pos = globe.find_lowest_cell_containing(column, material::AIR)
+            .expect("Should have loaded the surface");
+        let ground_pos = air_pos.set_z(air_pos.z - 1);
+        let origins: Vec<ChunkOrigin> = globe.chunks().keys().cloned().collect();
+        for origin in origins {
+            light_chunk(&mut globe, &materials, origin);
+        }
+
+        let cell_at = |globe: &Globe, pos: CellPos| {
+            *globe.authoritative_cell(PosInOwningRoot::new(pos, spec.root_resolution))
+        };
+        assert_eq!(MAX_LIGHT, cell_at(&globe, air_pos).light);
+        assert_eq!(0, cell_at(&globe, ground_pos).light);
+
+        // Dig a hole, and put something that glows at the bottom of it.
+        let mut lamp = materials.get(material::ROCK).clone();
+        lamp.name = "lamp".to_string();
+        lamp.light = 10;
+        let mut materials = materials;
+        let lamp_id = materials.register(lamp);
+        let hole_pos = ground_pos.set_z(ground_pos.z - 3);
+        for z in (hole_pos.z + 1)..(ground_pos.z + 1) {
+            let mut cell = cell_at(&globe, hole_pos.set_z(z));
+            cell.material = material::AIR;
+            globe.set_cell(PosInOwningRoot::new(hole_pos.set_z(z), spec.root_resolution), cell);
+        }
+        // Cover it up again so no sky gets in.
+        let mut cell = cell_at(&globe, ground_pos);
+        cell.material = material::DIRT;
+        globe.set_cell(PosInOwningRoot::new(ground_pos, spec.root_resolution), cell);
+        let mut cell = cell_at(&globe, hole_pos);
+        cell.material = lamp_id;
+        globe.set_cell(PosInOwningRoot::new(hole_pos, spec.root_resolution), cell);
+
+        let origins: Vec<ChunkOrigin> = globe.chunks().keys().cloned().collect();
+        for origin in origins {
+            light_chunk(&mut globe, &materials, origin);
+        }
+        assert_eq!(10, cell_at(&globe, hole_pos).light);
+        assert_eq!(9, cell_at(&globe, hole_pos.set_z(hole_pos.z + 1)).light);
+        assert_eq!(8, cell_at(&globe, hole_pos.set_z(hole_pos.z + 2)).light);
+        // Rock is opaque, so this is as far as it goes.
+        assert_eq!(0, cell_at(&globe, ground_pos).light);
+    }
+
+    #[test]
+    fn light_spreads_down_into_chunk_below() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let mut globe = Globe::new_small_flat(&log);
+        let spec = globe.spec();
+        let mut materials = MaterialRegistry::new();
+        let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+        // Away from the edges of chunks and roots.
+        let column = CellPos::default().set_x(2).set_y(2);
+        chunk_sys.load_chunks_near(&mut globe, &[column.set_z(16)]);
+        let air_pos = globe.find_lowest_cell_containing(column, material::AIR)
+            .expect("Should have loaded the surface");
+        let ground_pos = air_pos.set_z(air_pos.z - 1);
+        // The ground should be right at the bottom of a chunk,
+        // so the shaft below it is in the chunk below.
+        assert_eq!(0, ground_pos.z % spec.chunk_resolution[2]);
+        let lower_origin = ChunkOrigin::new(
+            ground_pos.set_z(ground_pos.z - 1),
+            spec.root_resolution,
+            spec.chunk_resolution,
+        );
+
+        let cell_at = |globe: &Globe, pos: CellPos| {
+            *globe.authoritative_cell(PosInOwningRoot::new(pos, spec.root_resolution))
+        };
+        // Relight chunks like `ChunkViewSystem` would,
+        // until none of them need it any more.
+        let relight_dirty_chunks = |globe: &mut Globe, materials: &MaterialRegistry| {
+            for _ in 0..100 {
+                let dirty_origins: Vec<ChunkOrigin> = globe.chunks().iter()
+                    .filter(|&(_, chunk)| chunk.is_view_dirty)
+                    .map(|(origin, _)| *origin)
+                    .collect();
+                if dirty_origins.is_empty() {
+                    return;
+                }
+                for origin in dirty_origins {
+                    light_chunk(globe, materials, origin);
+                    globe.chunks_mut().get_mut(&origin)
+                        .expect("Just found this chunk")
+                        .mark_view_as_clean();
+                }
+            }
+            panic!("Light never settled");
+        };
+        relight_dirty_chunks(&mut globe, &materials);
+
+        // Dig a shaft down into the chunk below, and put something
+        // that glows at the top of it, in the chunk above.
+        let mut lamp = materials.get(material::ROCK).clone();
+        lamp.name = "lamp".to_string();
+        lamp.light = 10;
+        let lamp_id = materials.register(lamp);
+        let shaft_bottom_pos = ground_pos.set_z(ground_pos.z - 3);
+        for z in shaft_bottom_pos.z..ground_pos.z {
+            let mut cell = cell_at(&globe, ground_pos.set_z(z));
+            cell.material = material::AIR;
+            globe.set_cell(PosInOwningRoot::new(ground_pos.set_z(z), spec.root_resolution), cell);
+        }
+        let mut cell = cell_at(&globe, ground_pos);
+        cell.material = lamp_id;
+        globe.set_cell(PosInOwningRoot::new(ground_pos, spec.root_resolution), cell);
+
+        // Light the chunk below first, before the chunk above
+        // knows about the lamp, so it can only find out later.
+        light_chunk(&mut globe, &materials, lower_origin);
+        globe.chunks_mut().get_mut(&lower_origin)
+            .expect("Should have loaded the chunk below the surface")
+            .mark_view_as_clean();
+        assert_eq!(0, cell_at(&globe, ground_pos.set_z(ground_pos.z - 1)).light);
+        relight_dirty_chunks(&mut globe, &materials);
+
+        assert_eq!(10, cell_at(&globe, ground_pos).light);
+        assert_eq!(9, cell_at(&globe, ground_pos.set_z(ground_pos.z - 1)).light);
+        assert_eq!(8, cell_at(&globe, ground_pos.set_z(ground_pos.z - 2)).light);
+        assert_eq!(7, cell_at(&globe, shaft_bottom_pos).light);
+    }
+}
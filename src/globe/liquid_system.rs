@@ -0,0 +1,493 @@
+use std::collections::{ HashMap, HashSet, VecDeque };
+
+use specs;
+use slog::Logger;
+
+use types::*;
+use super::{ Globe, CellPos, PosInOwningRoot, ChunkOrigin, Neighbors, AboveAndBelow };
+use super::chunk::Cell;
+use super::material;
+use super::MaterialRegistry;
+
+/// How far liquid spreads sideways from a source before it
+/// stops. Falling doesn't count; liquid can fall as far as it likes.
+pub const MAX_FLOW: u8 = 7;
+
+/// Makes liquids flow into the air around them.
+///
+/// Every liquid cell has a `flow` level; sources (like the ocean,
+/// as it's generated) have a level of 0, and liquid that has flowed
+/// sideways from a cell is one level higher than the cell it came
+/// from, up to `MAX_FLOW`. Liquid falls straight down into any air
+/// below it before it spreads sideways. Anything that isn't a source
+/// dries up once there's nothing left feeding it, i.e. no liquid
+/// above it and nothing beside it with a lower level.
+///
+/// We don't look at every liquid cell every time; only those that
+/// might be able to flow somewhere. Any loaded chunk that has changed
+/// since we last saw it (according to its version) is checked for
+/// liquid next to air, and whenever we change a cell we check the
+/// cells around it again. All changes go through `Globe::set_cells`,
+/// so they redraw chunk views like any other, and bump the version of
+/// each chunk they touch; but only once per step, so that a server
+/// sends each chunk that liquid is flowing through at most once
+/// per step, rather than once for every cell that changed.
+///
+/// TODO: liquids don't push `CellDweller`s around, or wash
+/// away anything in their path.
+pub struct LiquidSystem {
+    log: Logger,
+    // How often liquids get to flow one step further.
+    pub seconds_between_flows: TimeDelta,
+    seconds_until_next_flow: TimeDelta,
+    // Most cells to look at each time, so a burst of
+    // activity can't hold up the simulation.
+    pub max_cells_per_flow: usize,
+    // Keyed by globe entity.
+    liquids: HashMap<specs::Entity, Liquids>,
+}
+
+impl LiquidSystem {
+    pub fn new(
+        parent_log: &Logger,
+        seconds_between_flows: TimeDelta,
+        max_cells_per_flow: usize,
+    ) -> LiquidSystem {
+        LiquidSystem {
+            log: parent_log.new(o!()),
+            seconds_between_flows: seconds_between_flows,
+            seconds_until_next_flow: seconds_between_flows,
+            max_cells_per_flow: max_cells_per_flow,
+            liquids: HashMap::new(),
+        }
+    }
+}
+
+impl specs::System<TimeDelta> for LiquidSystem {
+    fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
+        use specs::Join;
+        let (entities, mut globes, materials) = arg.fetch(|w|
+            (w.entities(), w.write::<Globe>(), w.read_resource::<MaterialRegistry>())
+        );
+
+        // Count down until liquids are allowed to flow next.
+        self.seconds_until_next_flow -= dt;
+        if self.seconds_until_next_flow > 0.0 {
+            return;
+        }
+        self.seconds_until_next_flow = self.seconds_between_flows;
+
+        // Forget about any globes that have gone away.
+        let mut live_globes: HashSet<specs::Entity> = HashSet::new();
+        for (entity, globe) in (&entities, &mut globes).iter() {
+            live_globes.insert(entity);
+            let liquids = self.liquids.entry(entity).or_insert_with(Liquids::new);
+            liquids.find_changed_chunks(globe, &materials);
+            let changed_cells = liquids.flow(globe, &materials, self.max_cells_per_flow);
+            if changed_cells > 0 {
+                trace!(self.log, "Liquids flowed"; "changed_cells" => changed_cells, "active_cells" => liquids.active_count());
+            }
+        }
+        self.liquids.retain(|entity, _| live_globes.contains(entity));
+    }
+}
+
+// Which liquid cells in a single globe might be able to flow.
+struct Liquids {
+    // The version of each loaded chunk when we last
+    // checked it for liquid that might be able to flow.
+    known_versions: HashMap<ChunkOrigin, u64>,
+    // Cells to look at, oldest first, and the same again
+    // for checking whether a cell is already queued.
+    active: VecDeque<PosInOwningRoot>,
+    is_active: HashSet<PosInOwningRoot>,
+}
+
+impl Liquids {
+    fn new() -> Liquids {
+        Liquids {
+            known_versions: HashMap::new(),
+            active: VecDeque::new(),
+            is_active: HashSet::new(),
+        }
+    }
+
+    fn active_count(&self) -> usize {
+        self.active.len()
+    }
+
+    fn activate(&mut self, pos: PosInOwningRoot) {
+        if self.is_active.insert(pos) {
+            self.active.push_back(pos);
+        }
+    }
+
+    // Look for liquid that might be able to flow in any
+    // chunks that are new or have changed since last time.
+    fn find_changed_chunks(&mut self, globe: &Globe, materials: &MaterialRegistry) {
+        use super::globe::GlobeGuts;
+
+        // Forget about chunks that have been unloaded; their neighbours
+        // might have changed by the time they're loaded again.
+        let chunks = globe.chunks();
+        self.known_versions.retain(|origin, _| chunks.contains_key(origin));
+
+        let changed_origins: Vec<ChunkOrigin> = chunks.iter()
+            .filter(|&(origin, chunk)| self.known_versions.get(origin) != Some(&chunk.version))
+            .map(|(origin, _)| *origin)
+            .collect();
+        for origin in changed_origins {
+            self.known_versions.insert(origin, chunks[&origin].version);
+            self.find_liquid_in_chunk(globe, materials, origin);
+        }
+    }
+
+    // Activate every liquid cell in or around the chunk at `origin`
+    // that has air beside or below it, and anything that's flowing
+    // (rather than a source), in case it needs to dry up.
+    fn find_liquid_in_chunk(&mut self, globe: &Globe, materials: &MaterialRegistry, origin: ChunkOrigin) {
+        use super::globe::GlobeGuts;
+
+        let chunk = match globe.chunks().get(&origin) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let spec = globe.spec();
+        let start = *origin.pos();
+        // Include cells on the far edges of the chunk,
+        // which are owned by its neighbours; see `Chunk`.
+        let end_x = start.x + spec.chunk_resolution[0];
+        let end_y = start.y + spec.chunk_resolution[1];
+        let end_z = start.z + spec.chunk_resolution[2] - 1;
+        for cell_z in start.z..(end_z + 1) {
+            for cell_y in start.y..(end_y + 1) {
+                for cell_x in start.x..(end_x + 1) {
+                    let cell_pos = CellPos {
+                        root: start.root,
+                        x: cell_x,
+                        y: cell_y,
+                        z: cell_z,
+                    };
+                    let cell = chunk.cell(cell_pos);
+                    if materials.is_liquid(cell.material) {
+                        if cell.flow > 0 {
+                            self.activate(PosInOwningRoot::new(cell_pos, spec.root_resolution));
+                        }
+                        continue;
+                    }
+                    if cell.material != material::AIR {
+                        continue;
+                    }
+                    // Liquid can flow into this air from above or beside it,
+                    // but not from below; there's air above every ocean.
+                    for neighbor_pos in Neighbors::new(cell_pos, spec.root_resolution) {
+                        if neighbor_pos.z < cell_pos.z {
+                            continue;
+                        }
+                        let neighbor_pos = PosInOwningRoot::new(neighbor_pos, spec.root_resolution);
+                        let is_liquid = cell_at(globe, neighbor_pos)
+                            .map_or(false, |neighbor| materials.is_liquid(neighbor.material));
+                        if is_liquid {
+                            self.activate(neighbor_pos);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Let up to `max_cells` active cells flow one step.
+    // Cells activated along the way have to wait until next time,
+    // so that liquid spreads at a steady pace.
+    //
+    // Returns how many cells were changed.
+    fn flow(&mut self, globe: &mut Globe, materials: &MaterialRegistry, max_cells: usize) -> usize {
+        // Hold on to all the changes until the end, so they only
+        // bump each chunk's version once; later cells still see
+        // what earlier ones did.
+        let mut changes = PendingChanges::new();
+        let cell_count = max_cells.min(self.active.len());
+        for _ in 0..cell_count {
+            let pos = self.active.pop_front().expect("Just checked length");
+            self.is_active.remove(&pos);
+            for (change_pos, new_cell) in next_changes(globe, &changes, materials, pos) {
+                changes.insert(change_pos, new_cell);
+            }
+        }
+        let changed_cells = changes.order.len();
+        if changed_cells > 0 {
+            self.set_cells(globe, changes.into_vec());
+        }
+        changed_cells
+    }
+
+    // Change some cells, and look at everything around them again next time.
+    fn set_cells(&mut self, globe: &mut Globe, changes: Vec<(PosInOwningRoot, Cell)>) {
+        use super::globe::GlobeGuts;
+
+        // Don't go looking through the owning chunks all over again
+        // just because we changed them ourselves; but if someone else
+        // changed them since we last looked, we still need to.
+        let mut up_to_date_origins: Vec<ChunkOrigin> = Vec::new();
+        for &(pos, _) in &changes {
+            let owner_origin = globe.origin_of_chunk_owning(pos);
+            let old_version = globe.chunks().get(&owner_origin).map(|chunk| chunk.version);
+            let is_up_to_date = old_version.is_some() && self.known_versions.get(&owner_origin) == old_version.as_ref();
+            if is_up_to_date && !up_to_date_origins.contains(&owner_origin) {
+                up_to_date_origins.push(owner_origin);
+            }
+        }
+        globe.set_cells(&changes);
+        for owner_origin in up_to_date_origins {
+            let new_version = globe.chunks()[&owner_origin].version;
+            self.known_versions.insert(owner_origin, new_version);
+        }
+
+        let resolution = globe.spec().root_resolution;
+        for (pos, _) in changes {
+            self.activate(pos);
+            for neighbor_pos in Neighbors::new(pos.into(), resolution) {
+                self.activate(PosInOwningRoot::new(neighbor_pos, resolution));
+            }
+        }
+    }
+}
+
+// Changes to cells that haven't been made to the globe yet,
+// in the order they were first made.
+struct PendingChanges {
+    cells: HashMap<PosInOwningRoot, Cell>,
+    order: Vec<PosInOwningRoot>,
+}
+
+impl PendingChanges {
+    fn new() -> PendingChanges {
+        PendingChanges {
+            cells: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, pos: PosInOwningRoot, cell: Cell) {
+        if self.cells.insert(pos, cell).is_none() {
+            self.order.push(pos);
+        }
+    }
+
+    fn into_vec(self) -> Vec<(PosInOwningRoot, Cell)> {
+        let cells = self.cells;
+        self.order.into_iter()
+            .map(|pos| (pos, cells[&pos]))
+            .collect()
+    }
+}
+
+// What needs to change for liquid at `pos` to flow one step,
+// taking into account any changes that haven't been made yet;
+// nothing if it isn't liquid, or can't go anywhere.
+fn next_changes(
+    globe: &Globe,
+    pending: &PendingChanges,
+    materials: &MaterialRegistry,
+    pos: PosInOwningRoot,
+) -> Vec<(PosInOwningRoot, Cell)> {
+    let resolution = globe.spec().root_resolution;
+    let current_cell_at = |pos: PosInOwningRoot| {
+        pending.cells.get(&pos).cloned().or_else(|| cell_at(globe, pos))
+    };
+    let cell = match current_cell_at(pos) {
+        Some(cell) => cell,
+        // Its chunk has been unloaded since it was activated.
+        None => return Vec::new(),
+    };
+    if !materials.is_liquid(cell.material) {
+        return Vec::new();
+    }
+    let cell_pos: CellPos = pos.into();
+    let is_same_liquid = |other: &Cell| other.material == cell.material;
+
+    // Look above and below first; falling takes priority.
+    let mut above: Option<Cell> = None;
+    let mut below: Option<(PosInOwningRoot, Cell)> = None;
+    for vertical_cell_pos in AboveAndBelow::new(cell_pos) {
+        let vertical_pos = PosInOwningRoot::new(vertical_cell_pos, resolution);
+        let vertical_cell = match current_cell_at(vertical_pos) {
+            Some(vertical_cell) => vertical_cell,
+            None => continue,
+        };
+        if vertical_cell_pos.z > cell_pos.z {
+            above = Some(vertical_cell);
+        } else {
+            below = Some((vertical_pos, vertical_cell));
+        }
+    }
+    let beside: Vec<(PosInOwningRoot, Cell)> = Neighbors::new(cell_pos, resolution)
+        .filter(|neighbor_pos| neighbor_pos.z == cell_pos.z)
+        .map(|neighbor_pos| PosInOwningRoot::new(neighbor_pos, resolution))
+        .filter_map(|neighbor_pos| current_cell_at(neighbor_pos).map(|neighbor| (neighbor_pos, neighbor)))
+        .collect();
+
+    // Dry up if nothing is feeding this cell any more.
+    if cell.flow > 0 {
+        let is_fed_from_above = above.map_or(false, |above| is_same_liquid(&above));
+        let is_fed_from_beside = beside.iter()
+            .any(|&(_, neighbor)| is_same_liquid(&neighbor) && neighbor.flow < cell.flow);
+        if !is_fed_from_above && !is_fed_from_beside {
+            let mut dry_cell = cell;
+            dry_cell.material = material::AIR;
+            dry_cell.flow = 0;
+            return vec![(pos, dry_cell)];
+        }
+    }
+
+    // Fall if we can. Falling liquid keeps the same level,
+    // except that it can't become a source.
+    if let Some((below_pos, below_cell)) = below {
+        let fall_flow = cell.flow.max(1);
+        if below_cell.material == material::AIR {
+            return vec![(below_pos, liquid_cell(below_cell, cell.material, fall_flow))];
+        }
+        if is_same_liquid(&below_cell) && below_cell.flow > fall_flow {
+            return vec![(below_pos, liquid_cell(below_cell, cell.material, fall_flow))];
+        }
+        if !is_same_liquid(&below_cell) && !materials.is_solid(below_cell.material) {
+            // Don't spread out across anything we could sink into.
+            // TODO: let liquids sink through each other?
+            return Vec::new();
+        }
+    }
+
+    // Otherwise spread out sideways.
+    let spread_flow = cell.flow + 1;
+    if spread_flow > MAX_FLOW {
+        return Vec::new();
+    }
+    beside.into_iter()
+        .filter(|&(_, neighbor)| {
+            neighbor.material == material::AIR ||
+            (is_same_liquid(&neighbor) && neighbor.flow > spread_flow)
+        })
+        .map(|(neighbor_pos, neighbor)| (neighbor_pos, liquid_cell(neighbor, cell.material, spread_flow)))
+        .collect()
+}
+
+// `cell`, filled with `liquid` at the given level.
+fn liquid_cell(cell: Cell, liquid: material::MaterialId, flow: u8) -> Cell {
+    let mut new_cell = cell;
+    new_cell.material = liquid;
+    new_cell.flow = flow;
+    new_cell
+}
+
+// The cell at `pos`, or `None` if the chunk that owns it isn't loaded.
+fn cell_at(globe: &Globe, pos: PosInOwningRoot) -> Option<Cell> {
+    use super::globe::GlobeGuts;
+    let origin = globe.origin_of_chunk_owning(pos);
+    globe.chunks().get(&origin).map(|chunk| *chunk.cell(pos.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use slog;
+
+    use super::*;
+    use globe::{ Globe, ChunkSystem };
+
+    #[test]
+    fn water_spreads_out_and_dries_up() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let mut globe = Globe::new_small_flat(&log);
+        let spec = globe.spec();
+        let materials = MaterialRegistry::new();
+        let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+        // Away from the edges of chunks and roots.
+        let column = CellPos::default().set_x(2).set_y(2);
+        chunk_sys.load_chunks_near(&mut globe, &[column.set_z(16)]);
+        let source_pos = globe.find_lowest_cell_containing(column, material::AIR)
+            .expect("Should have loaded the surface");
+        let source_pos = PosInOwningRoot::new(source_pos, spec.root_resolution);
+        let mut liquids = Liquids::new();
+        let flow_until_still = |liquids: &mut Liquids, globe: &mut Globe| {
+            for _ in 0..1000 {
+                liquids.find_changed_chunks(globe, &materials);
+                if liquids.flow(globe, &materials, 100) == 0 && liquids.active_count() == 0 {
+                    return;
+                }
+            }
+            panic!("Liquid never stopped flowing");
+        };
+        // Nothing on the flat globe should go anywhere.
+        flow_until_still(&mut liquids, &mut globe);
+
+        // Pour some water on the ground.
+        let mut source_cell = *globe.authoritative_cell(source_pos);
+        source_cell.material = material::WATER;
+        globe.set_cell(source_pos, source_cell);
+        flow_until_still(&mut liquids, &mut globe);
+
+        let source_cell_pos: CellPos = source_pos.into();
+        let next_pos = PosInOwningRoot::new(source_cell_pos.set_x(source_cell_pos.x + 1), spec.root_resolution);
+        let next_but_one_pos = PosInOwningRoot::new(source_cell_pos.set_x(source_cell_pos.x + 2), spec.root_resolution);
+        let above_pos = PosInOwningRoot::new(source_cell_pos.set_z(source_cell_pos.z + 1), spec.root_resolution);
+        let next_cell = *globe.authoritative_cell(next_pos);
+        assert_eq!(material::WATER, next_cell.material);
+        assert_eq!(1, next_cell.flow);
+        let next_but_one_cell = *globe.authoritative_cell(next_but_one_pos);
+        assert_eq!(material::WATER, next_but_one_cell.material);
+        assert_eq!(2, next_but_one_cell.flow);
+        assert_eq!(material::AIR, globe.authoritative_cell(above_pos).material);
+
+        // Take the source away again, and the rest should dry up.
+        source_cell.material = material::AIR;
+        globe.set_cell(source_pos, source_cell);
+        flow_until_still(&mut liquids, &mut globe);
+        assert_eq!(material::AIR, globe.authoritative_cell(next_pos).material);
+        assert_eq!(material::AIR, globe.authoritative_cell(next_but_one_pos).material);
+    }
+
+    #[test]
+    fn water_falls_before_spreading() {
+        use globe::globe::GlobeGuts;
+
+        let log = slog::Logger::root(slog::Discard, o!());
+        let mut globe = Globe::new_small_flat(&log);
+        let spec = globe.spec();
+        let materials = MaterialRegistry::new();
+        let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+        // Away from the edges of chunks and roots.
+        let column = CellPos::default().set_x(2).set_y(2);
+        chunk_sys.load_chunks_near(&mut globe, &[column.set_z(16)]);
+        let landing_pos = globe.find_lowest_cell_containing(column, material::AIR)
+            .expect("Should have loaded the surface");
+        let landing_pos = PosInOwningRoot::new(landing_pos, spec.root_resolution);
+        let mut liquids = Liquids::new();
+        liquids.find_changed_chunks(&globe, &materials);
+        liquids.flow(&mut globe, &materials, 100);
+
+        // Pour some water in the air, with a gap below it.
+        let landing_cell_pos: CellPos = landing_pos.into();
+        let source_cell_pos = landing_cell_pos.set_z(landing_cell_pos.z + 1);
+        let source_pos = PosInOwningRoot::new(source_cell_pos, spec.root_resolution);
+        let mut source_cell = *globe.authoritative_cell(source_pos);
+        source_cell.material = material::WATER;
+        globe.set_cell(source_pos, source_cell);
+        let beside_source_pos = PosInOwningRoot::new(source_cell_pos.set_x(source_cell_pos.x + 1), spec.root_resolution);
+        let beside_landing_pos = PosInOwningRoot::new(landing_cell_pos.set_x(landing_cell_pos.x + 1), spec.root_resolution);
+
+        // It should fall first...
+        liquids.find_changed_chunks(&globe, &materials);
+        liquids.flow(&mut globe, &materials, 100);
+        assert_eq!(material::WATER, globe.authoritative_cell(landing_pos).material);
+        assert_eq!(material::AIR, globe.authoritative_cell(beside_source_pos).material);
+        assert_eq!(material::AIR, globe.authoritative_cell(beside_landing_pos).material);
+
+        // ...and only then spread out, changing lots of cells in the
+        // same chunk, but only bumping its version once.
+        let origin = globe.origin_of_chunk_owning(landing_pos);
+        let old_version = globe.chunks()[&origin].version;
+        liquids.find_changed_chunks(&globe, &materials);
+        assert!(liquids.flow(&mut globe, &materials, 100) > 1);
+        assert_eq!(material::WATER, globe.authoritative_cell(beside_landing_pos).material);
+        assert_eq!(old_version + 1, globe.chunks()[&origin].version);
+    }
+}
@@ -0,0 +1,199 @@
+//! Coarse meshes of a whole globe's surface, for drawing it from far
+//! enough away that loading its chunks would be a waste of time.
+//!
+//! These are built straight from the globe's `WorldGen`, using only
+//! `WorldGen::land_height_at`; they know nothing about individual
+//! cells, and don't reflect any changes made to the globe since
+//! it was generated.
+//!
+//! Each level of detail subdivides every triangle of the icosahedron
+//! at the previous level into four; level 0 is the icosahedron itself.
+
+use std::collections::HashMap;
+
+use types::*;
+use super::WorldGen;
+use super::MaterialRegistry;
+use super::material;
+use super::icosahedron::{ FACES, VERTICES };
+use ::render::Vertex;
+
+/// Finest level of detail we'll ever build.
+/// Level `n` has `20 * 4^n` triangles.
+pub const MAX_LOD_LEVEL: usize = 5;
+
+// Don't draw a coarse mesh at all if the camera is closer to the
+// surface than this; chunks around the player will be loaded, and
+// they're what we want to be looking at.
+const MIN_LOD_ALTITUDE: f64 = 0.3;
+
+// Pick the coarsest level whose triangles' edges are no longer than
+// this, measured as an angle (in radians) from the camera. Anything
+// smaller than a couple of pixels is wasted effort.
+const MAX_EDGE_ANGLE: f64 = 0.02;
+
+// Length of an edge of an icosahedron with a circumscribed
+// sphere of radius 1.
+const ICOSAHEDRON_EDGE_LENGTH: f64 = 1.0514622242382672;
+
+/// Which level of detail to draw a globe at when the camera
+/// is `distance` from its centre, if at all.
+pub fn lod_level_for_distance(ocean_radius: f64, distance: f64) -> Option<usize> {
+    let altitude = distance - ocean_radius;
+    if altitude < MIN_LOD_ALTITUDE {
+        return None;
+    }
+    let mut edge_length = ICOSAHEDRON_EDGE_LENGTH * ocean_radius;
+    for level in 0..MAX_LOD_LEVEL {
+        if edge_length / altitude <= MAX_EDGE_ANGLE {
+            return Some(level);
+        }
+        edge_length /= 2.0;
+    }
+    Some(MAX_LOD_LEVEL)
+}
+
+/// Build a mesh of the surface of the globe generated by `gen`,
+/// at the given level of detail.
+///
+/// Land is coloured like grass, and anything below sea level is
+/// drawn as the surface of the ocean. The whole thing is sunk one
+/// block below the real surface, so that wherever chunks are
+/// also loaded and drawn they'll cover it up.
+pub fn make_lod_geometry(
+    gen: &WorldGen,
+    materials: &MaterialRegistry,
+    level: usize,
+    vertex_data: &mut Vec<Vertex>,
+    index_data: &mut Vec<u32>,
+) {
+    let spec = gen.spec();
+    let (points, triangles) = subdivided_icosahedron(level);
+
+    let first_vertex_index = vertex_data.len() as u32;
+    // TODO: ask the generator what the surface is actually
+    // made of, rather than assuming everything is grass.
+    let land_color = materials.get(material::GRASS).color;
+    let ocean_color = materials.get(material::WATER).color;
+    for point in &points {
+        let land_height = gen.land_height_at(*point);
+        let (height, color) = if land_height < spec.ocean_radius {
+            (spec.ocean_radius, ocean_color)
+        } else {
+            (land_height, land_color)
+        };
+        let pos = point.to_vector() * (height - spec.block_height);
+        // Light it as if it were flat; the hills are too
+        // far away for anyone to notice.
+        vertex_data.push(Vertex::new_with_normal(
+            [pos.x as f32, pos.y as f32, pos.z as f32],
+            color,
+            [point.x as f32, point.y as f32, point.z as f32],
+        ));
+    }
+
+    for triangle in &triangles {
+        for &point_index in triangle {
+            index_data.push(first_vertex_index + point_index as u32);
+        }
+    }
+}
+
+// Points on the unit sphere, and the triangles between them,
+// of an icosahedron with every face split `level` times.
+fn subdivided_icosahedron(level: usize) -> (Vec<Pt3>, Vec<[usize; 3]>) {
+    let mut points: Vec<Pt3> = VERTICES.iter().map(|vertex| vertex.into()).collect();
+    let mut triangles: Vec<[usize; 3]> = FACES.to_vec();
+
+    for _ in 0..level {
+        // Neighbouring triangles share the point in the middle of
+        // their common edge; remember them so we only add each once.
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, points: &mut Vec<Pt3>| -> usize {
+            let key = if a < b { (a, b) } else { (b, a) };
+            *midpoints.entry(key).or_insert_with(|| {
+                use na::Norm;
+                let middle = (points[a].to_vector() + points[b].to_vector()).normalize();
+                points.push(*middle.as_point());
+                points.len() - 1
+            })
+        };
+
+        let mut new_triangles: Vec<[usize; 3]> = Vec::with_capacity(triangles.len() * 4);
+        for triangle in &triangles {
+            let ab = midpoint(triangle[0], triangle[1], &mut points);
+            let bc = midpoint(triangle[1], triangle[2], &mut points);
+            let ca = midpoint(triangle[2], triangle[0], &mut points);
+            // Keep the same winding as the original triangle.
+            new_triangles.push([triangle[0], ab, ca]);
+            new_triangles.push([ab, triangle[1], bc]);
+            new_triangles.push([ca, bc, triangle[2]]);
+            new_triangles.push([ab, bc, ca]);
+        }
+        triangles = new_triangles;
+    }
+
+    (points, triangles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use globe::{ Spec, WorldGen, FlatGen };
+
+    fn small_flat_spec() -> Spec {
+        Spec {
+            seed: 13,
+            floor_radius: 0.91,
+            ocean_radius: 1.13,
+            block_height: 0.02,
+            root_resolution: [8, 16],
+            chunk_resolution: [4, 4, 4],
+        }
+    }
+
+    #[test]
+    fn subdivision_shares_vertices() {
+        for level in 0..(MAX_LOD_LEVEL + 1) {
+            let (points, triangles) = subdivided_icosahedron(level);
+            let four_to_the_level = 1 << (2 * level);
+            assert_eq!(20 * four_to_the_level, triangles.len());
+            // Euler characteristic of a sphere: V - E + F = 2,
+            // and every edge is shared by two triangles.
+            assert_eq!(10 * four_to_the_level + 2, points.len());
+        }
+    }
+
+    #[test]
+    fn lod_mesh_follows_land_height() {
+        let spec = small_flat_spec();
+        let gen = FlatGen::new(spec);
+        let materials = MaterialRegistry::new();
+        let mut vertex_data = Vec::new();
+        let mut index_data = Vec::new();
+        make_lod_geometry(&gen, &materials, 2, &mut vertex_data, &mut index_data);
+
+        assert_eq!(20 * 16 * 3, index_data.len());
+        let expected_radius = gen.land_height_at(Pt3::new(0.0, 0.0, 1.0)) - spec.block_height;
+        for vertex in &vertex_data {
+            let pos = vertex.a_pos;
+            let radius = ((pos[0] * pos[0] + pos[1] * pos[1] + pos[2] * pos[2]) as f64).sqrt();
+            assert!((radius - expected_radius).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn more_detail_up_close() {
+        let ocean_radius = 1.13;
+        // Right down on the surface we'll have chunks instead.
+        assert_eq!(None, lod_level_for_distance(ocean_radius, ocean_radius + 0.1));
+        let mut previous_level = MAX_LOD_LEVEL;
+        for &distance in &[1.5, 3.0, 10.0, 100.0, 10000.0] {
+            let level = lod_level_for_distance(ocean_radius, distance)
+                .expect("Should draw the globe from this far away");
+            assert!(level <= previous_level);
+            previous_level = level;
+        }
+        assert_eq!(Some(0), lod_level_for_distance(ocean_radius, 10000.0));
+    }
+}
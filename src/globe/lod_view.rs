@@ -0,0 +1,33 @@
+use specs;
+
+use ::render::MeshHandle;
+use super::lod::MAX_LOD_LEVEL;
+
+/// Draws a whole globe as a single coarse mesh, at whatever level
+/// of detail suits how far away the camera is; see `globe::lod`.
+///
+/// Give one of these a `Visual` and a `Spatial`, and `LodViewSystem`
+/// will keep the visual's mesh up to date. Up close, when you'll be
+/// looking at chunks instead, it isn't drawn at all.
+pub struct LodView {
+    pub globe_entity: specs::Entity,
+    // The level of detail currently in the `Visual`, if any.
+    pub level: Option<usize>,
+    // Meshes already sent to the video card for each level
+    // of detail, so we can switch back to them for free.
+    pub mesh_handles: Vec<Option<MeshHandle>>,
+}
+
+impl LodView {
+    pub fn new(globe_entity: specs::Entity) -> LodView {
+        LodView {
+            globe_entity: globe_entity,
+            level: None,
+            mesh_handles: vec![None; MAX_LOD_LEVEL + 1],
+        }
+    }
+}
+
+impl specs::Component for LodView {
+    type Storage = specs::HashMapStorage<LodView>;
+}
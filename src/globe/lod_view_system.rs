@@ -0,0 +1,99 @@
+use specs;
+use slog::Logger;
+
+use types::*;
+use globe::{ Globe, LodView, MaterialRegistry };
+use globe::lod::{ lod_level_for_distance, make_lod_geometry };
+use ::render::{ Visual, ProtoMesh, Vertex };
+
+/// Swaps the mesh of every `LodView` for one with more or less
+/// detail as the camera moves closer or further away.
+///
+/// Needs a `Camera` resource, so only add this if you're drawing things.
+pub struct LodViewSystem {
+    log: Logger,
+}
+
+impl LodViewSystem {
+    pub fn new(parent_log: &Logger) -> LodViewSystem {
+        LodViewSystem {
+            log: parent_log.new(o!()),
+        }
+    }
+
+    fn update_lod_views(&mut self, w: &specs::World) {
+        use specs::Join;
+
+        let globes = w.read::<Globe>();
+        let mut lod_views = w.write::<LodView>();
+        let mut visuals = w.write::<Visual>();
+        let materials = w.read_resource::<MaterialRegistry>();
+        let camera = w.read_resource::<Camera>();
+
+        for (lod_view, visual) in (&mut lod_views, &mut visuals).iter() {
+            // Remember the mesh for the current level once it's
+            // been realized, so we never have to build it again.
+            if let Some(level) = lod_view.level {
+                if visual.proto_mesh.is_none() && visual.mesh_handle().is_some() {
+                    lod_view.mesh_handles[level] = visual.mesh_handle();
+                }
+            }
+
+            // Get the associated globe, complaining loudly if we fail.
+            let globe = match globes.get(lod_view.globe_entity) {
+                Some(globe) => globe,
+                None => {
+                    warn!(self.log, "The globe associated with this LodView is not alive! Can't proceed!");
+                    continue;
+                },
+            };
+
+            // TODO: use the globe's actual position
+            // when globes have a `Spatial`.
+            use na::Norm;
+            let camera_pos = Vec3::new(camera.position[0], camera.position[1], camera.position[2]);
+            let level = lod_level_for_distance(globe.spec().ocean_radius, camera_pos.norm());
+            if level == lod_view.level {
+                continue;
+            }
+            lod_view.level = level;
+
+            let level = match level {
+                Some(level) => level,
+                None => {
+                    // Close enough that we're looking at chunks instead.
+                    visual.clear_mesh_handle();
+                    visual.proto_mesh = None;
+                    continue;
+                },
+            };
+
+            if let Some(mesh_handle) = lod_view.mesh_handles[level] {
+                visual.set_mesh_handle(mesh_handle);
+                visual.proto_mesh = None;
+                continue;
+            }
+
+            debug!(self.log, "Making globe LOD proto-mesh"; "level" => level);
+            let mut vertex_data: Vec<Vertex> = Vec::new();
+            let mut index_data: Vec<u32> = Vec::new();
+            make_lod_geometry(
+                globe.gen(),
+                &materials,
+                level,
+                &mut vertex_data,
+                &mut index_data,
+            );
+            // Don't let realizing this replace the mesh we've
+            // kept for some other level; make a new one instead.
+            visual.clear_mesh_handle();
+            visual.proto_mesh = ProtoMesh::new(vertex_data, index_data).into();
+        }
+    }
+}
+
+impl specs::System<TimeDelta> for LodViewSystem {
+    fn run(&mut self, arg: specs::RunArg, _dt: TimeDelta) {
+        arg.fetch(|w| self.update_lod_views(w));
+    }
+}
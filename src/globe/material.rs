@@ -0,0 +1,231 @@
+/// Compact identifier for a kind of material, stored in every `Cell`.
+///
+/// Look up what it means in the `MaterialRegistry`. The built-in
+/// materials below are always registered with these IDs; anything
+/// else is whatever the game registered it as.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Hash)]
+pub struct MaterialId(pub u8);
+
+pub const AIR: MaterialId = MaterialId(0);
+pub const DIRT: MaterialId = MaterialId(1);
+pub const WATER: MaterialId = MaterialId(2);
+pub const GRASS: MaterialId = MaterialId(3);
+pub const SAND: MaterialId = MaterialId(4);
+pub const ROCK: MaterialId = MaterialId(5);
+pub const SNOW: MaterialId = MaterialId(6);
+
+/// Everything the engine needs to know about a kind of material.
+#[derive(Clone, Debug)]
+pub struct MaterialProperties {
+    pub name: String,
+    /// You can stand on it, and can't walk through it.
+    pub solid: bool,
+    /// You can't see through it. Only opaque cells are drawn,
+    /// and only if they're next to a cell that isn't opaque.
+    pub opaque: bool,
+    /// It flows, and you can wade through it.
+    pub liquid: bool,
+    /// A `CellDweller` can pick it up.
+    pub minable: bool,
+    /// Base colour (RGB) for drawing cells of this material.
+    pub color: [f32; 3],
+    /// How hard it is to move across this material, where
+    /// 1.0 is normal; not used by anything yet.
+    pub friction: f32,
+    /// How brightly it lights up the cells around it, from 0 (not
+    /// at all) to `lighting::MAX_LIGHT`.
+    pub light: u8,
+}
+
+/// Maps `MaterialId`s to their `MaterialProperties`.
+///
+/// Add one of these to the world as a resource; systems that need
+/// to know how cells behave (movement, mining, drawing, etc.) look
+/// it up rather than hard-coding particular materials.
+#[derive(Clone)]
+pub struct MaterialRegistry {
+    materials: Vec<MaterialProperties>,
+}
+
+impl MaterialRegistry {
+    /// Make a registry containing just the built-in materials.
+    pub fn new() -> MaterialRegistry {
+        let mut registry = MaterialRegistry {
+            materials: Vec::new(),
+        };
+        // Order matters here; these need to line up with
+        // the constants at the top of this module.
+        registry.register(MaterialProperties {
+            name: "air".to_string(),
+            solid: false,
+            opaque: false,
+            liquid: false,
+            minable: false,
+            color: [ 0.0, 0.0, 0.0 ],
+            friction: 1.0,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "dirt".to_string(),
+            solid: true,
+            opaque: true,
+            liquid: false,
+            minable: true,
+            // Muddy brown
+            color: [ 0.3, 0.2, 0.05 ],
+            friction: 1.0,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "water".to_string(),
+            solid: false,
+            // TODO: we can't draw anything translucent yet,
+            // so pretend we can't see through water for now.
+            opaque: true,
+            liquid: true,
+            minable: false,
+            // Ocean blue
+            color: [ 0.0, 0.1, 0.7 ],
+            friction: 1.0,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "grass".to_string(),
+            solid: true,
+            opaque: true,
+            liquid: false,
+            minable: true,
+            // Grassy green
+            color: [ 0.0, 0.4, 0.0 ],
+            friction: 1.0,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "sand".to_string(),
+            solid: true,
+            opaque: true,
+            liquid: false,
+            minable: true,
+            color: [ 0.7, 0.65, 0.35 ],
+            friction: 1.2,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "rock".to_string(),
+            solid: true,
+            opaque: true,
+            liquid: false,
+            minable: true,
+            color: [ 0.35, 0.35, 0.35 ],
+            friction: 1.0,
+            light: 0,
+        });
+        registry.register(MaterialProperties {
+            name: "snow".to_string(),
+            solid: true,
+            opaque: true,
+            liquid: false,
+            minable: true,
+            color: [ 0.9, 0.9, 0.95 ],
+            friction: 0.8,
+            light: 0,
+        });
+        registry
+    }
+
+    /// Add a new kind of material, and return the ID cells
+    /// should use to refer to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no room left for another material.
+    pub fn register(&mut self, properties: MaterialProperties) -> MaterialId {
+        let index = self.materials.len();
+        assert!(index <= u8::max_value() as usize, "Too many materials registered");
+        self.materials.push(properties);
+        MaterialId(index as u8)
+    }
+
+    /// Look up the properties of a material.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was never registered.
+    pub fn get(&self, id: MaterialId) -> &MaterialProperties {
+        self.materials.get(id.0 as usize)
+            .expect("Tried to look up a material that was never registered")
+    }
+
+    /// Whether `id` has been registered, and so can be looked up.
+    ///
+    /// Use this to check IDs that came from somewhere else,
+    /// e.g. a save file, before trusting them.
+    pub fn contains(&self, id: MaterialId) -> bool {
+        (id.0 as usize) < self.materials.len()
+    }
+
+    /// Find the ID of the material with the given name, if any.
+    pub fn find(&self, name: &str) -> Option<MaterialId> {
+        self.materials.iter()
+            .position(|properties| properties.name == name)
+            .map(|index| MaterialId(index as u8))
+    }
+
+    pub fn is_solid(&self, id: MaterialId) -> bool {
+        self.get(id).solid
+    }
+
+    pub fn is_opaque(&self, id: MaterialId) -> bool {
+        self.get(id).opaque
+    }
+
+    pub fn is_liquid(&self, id: MaterialId) -> bool {
+        self.get(id).liquid
+    }
+
+    pub fn is_minable(&self, id: MaterialId) -> bool {
+        self.get(id).minable
+    }
+
+    pub fn light(&self, id: MaterialId) -> u8 {
+        self.get(id).light
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> MaterialRegistry {
+        MaterialRegistry::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn built_in_materials_match_constants() {
+        let registry = MaterialRegistry::new();
+        assert_eq!(Some(AIR), registry.find("air"));
+        assert_eq!(Some(DIRT), registry.find("dirt"));
+        assert_eq!(Some(WATER), registry.find("water"));
+        assert_eq!(Some(GRASS), registry.find("grass"));
+        assert_eq!(Some(SAND), registry.find("sand"));
+        assert_eq!(Some(ROCK), registry.find("rock"));
+        assert_eq!(Some(SNOW), registry.find("snow"));
+        assert!(!registry.is_solid(AIR));
+        assert!(registry.is_liquid(WATER));
+        assert!(registry.is_minable(DIRT));
+    }
+
+    #[test]
+    fn register_custom_material() {
+        let mut registry = MaterialRegistry::new();
+        let mut lava = registry.get(WATER).clone();
+        lava.name = "lava".to_string();
+        lava.color = [ 0.9, 0.3, 0.0 ];
+        let lava_id = registry.register(lava);
+        assert_eq!(MaterialId(SNOW.0 + 1), lava_id);
+        assert_eq!(Some(lava_id), registry.find("lava"));
+        assert!(registry.is_liquid(lava_id));
+    }
+}
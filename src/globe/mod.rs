@@ -4,10 +4,12 @@ mod globe;
 pub mod icosahedron;
 mod spec;
 pub mod chunk;
+pub mod material;
 mod root;
 pub mod cell_shape;
 mod view;
 mod gen;
+mod biome;
 mod geometry;
 mod cell_pos;
 mod neighbors;
@@ -15,7 +17,15 @@ mod dir;
 mod chunk_view;
 mod chunk_view_system;
 mod chunk_system;
+mod chunk_anchor;
+mod liquid_system;
+pub mod lod;
+pub mod lighting;
+mod lod_view;
+mod lod_view_system;
+pub mod save;
 mod cursor;
+mod raycast;
 
 #[cfg(test)]
 mod tests;
@@ -33,7 +43,15 @@ pub use self::dir::*;
 pub use self::chunk_view::*;
 pub use self::chunk_view_system::*;
 pub use self::chunk_system::ChunkSystem;
+pub use self::chunk_anchor::ChunkAnchor;
+pub use self::liquid_system::{ LiquidSystem, MAX_FLOW };
+pub use self::lod_view::LodView;
+pub use self::lod_view_system::LodViewSystem;
 pub use self::cursor::Cursor;
+pub use self::raycast::{ RaycastHit, CellFace };
+pub use self::gen::{ WorldGen, SimpleGen, FlatGen };
+pub use self::biome::{ Biome, BiomeGen, Layer };
+pub use self::material::{ MaterialId, MaterialProperties, MaterialRegistry };
 
 pub type IntCoord = i64;
 
@@ -169,6 +187,100 @@ pub fn project(root: Root, mut pt_in_root_quad: Pt2) -> Pt3 {
     *pos_on_icosahedron.as_vector().normalize().as_point()
 }
 
+/// Find the root quad and position within it that `project`
+/// would map to the given point on the unit sphere.
+///
+/// This is the inverse of `project`; see there for how the
+/// triangles of each root quad are laid out.
+///
+/// Points exactly on the boundary between two roots may be
+/// returned in terms of either root.
+pub fn unproject(pt_on_unit_sphere: Pt3) -> (Root, Pt2) {
+    use na::{ Cross, Dot };
+    use self::icosahedron::{ FACES, VERTICES };
+
+    let p = pt_on_unit_sphere.to_vector();
+
+    // In case of floating point trouble right on the edge of
+    // a triangle, remember the triangle we were closest to being in.
+    let mut best: Option<(Root, Pt2)> = None;
+    let mut best_outside = ::std::f64::INFINITY;
+
+    for root_index in 0..5 {
+        let root = Root::new(root_index);
+        let faces = [
+            FACES[root_index as usize * 4],
+            FACES[root_index as usize * 4 + 1],
+            FACES[root_index as usize * 4 + 3],
+        ];
+        // Same names as in `project`.
+        let a: Pt3 = (&VERTICES[faces[0][0]]).into();
+        let b: Pt3 = (&VERTICES[faces[0][1]]).into();
+        let c: Pt3 = (&VERTICES[faces[1][1]]).into();
+        let d: Pt3 = (&VERTICES[faces[1][0]]).into();
+        let e: Pt3 = (&VERTICES[faces[2][1]]).into();
+        let f: Pt3 = (&VERTICES[faces[2][0]]).into();
+
+        // Origin and axes of each triangle, in the same order as in `project`.
+        let triangles = [
+            (a, b - a, c - a),
+            (d, c - d, b - d),
+            (c, d - c, e - c),
+            (f, e - f, d - f),
+        ];
+        for (triangle_index, &(origin, u_axis, v_axis)) in triangles.iter().enumerate() {
+            // Find where the ray from the center of the globe through
+            // the point hits the plane of this triangle.
+            let normal = u_axis.cross(&v_axis);
+            let p_dot_normal = p.dot(&normal);
+            if p_dot_normal.abs() < 1e-12 {
+                continue;
+            }
+            let t = origin.to_vector().dot(&normal) / p_dot_normal;
+            if t <= 0.0 {
+                // It's on the other side of the globe.
+                continue;
+            }
+            let w = p * t - origin.to_vector();
+
+            // Solve `w = u * u_axis + v * v_axis`.
+            let uu = u_axis.dot(&u_axis);
+            let uv = u_axis.dot(&v_axis);
+            let vv = v_axis.dot(&v_axis);
+            let wu = w.dot(&u_axis);
+            let wv = w.dot(&v_axis);
+            let det = uu * vv - uv * uv;
+            let u = (wu * vv - wv * uv) / det;
+            let v = (wv * uu - wu * uv) / det;
+
+            // Map back to root quad coordinates, with y-values
+            // between 0 and 2, as in `project`.
+            let (x, y) = match triangle_index {
+                0 => (u, v),
+                1 => (1.0 - u, 1.0 - v),
+                2 => (u, 1.0 + v),
+                _ => (1.0 - u, 2.0 - v),
+            };
+            let pt_in_root_quad = Pt2::new(x, y / 2.0);
+
+            // How far outside the triangle we are; zero if we're inside.
+            let outside =
+                (-u).max(0.0) +
+                (-v).max(0.0) +
+                (u + v - 1.0).max(0.0);
+            if outside == 0.0 {
+                return (root, pt_in_root_quad);
+            }
+            if outside < best_outside {
+                best_outside = outside;
+                best = Some((root, pt_in_root_quad));
+            }
+        }
+    }
+
+    best.expect("Every point should be in or near some triangle")
+}
+
 /// Calculate the origin of a chunk that contains the given `pos`,
 /// with the guarantee that the chunk will be in the same root even
 /// if `pos` is on the edge of that root.
@@ -157,11 +157,11 @@ impl Iterator for FastIntraRootNeighbors {
     }
 }
 
-// Iterator over cell positions immediately above and below
-// a given cell.
-//
-// Does not yield the (invalid) position below if the center cell is at `z == 0`.
-struct AboveAndBelow {
+/// Iterator over cell positions immediately above and below
+/// a given cell, in that order.
+///
+/// Does not yield the (invalid) position below if the center cell is at `z == 0`.
+pub struct AboveAndBelow {
     origin: CellPos,
     yielded_above: bool,
     yielded_below: bool,
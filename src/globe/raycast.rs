@@ -0,0 +1,216 @@
+use types::*;
+use super::{ Globe, CellPos, Neighbors, Dir };
+use super::chunk::Cell;
+use super::spec::Spec;
+use movement::adjacent_pos_in_dir;
+
+/// Which part of a cell a ray entered it through.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CellFace {
+    Top,
+    Bottom,
+    /// One of the sides; the direction from the center of the cell
+    /// toward that side, in terms of the root of the hit `CellPos`.
+    Side(Dir),
+    /// The ray started inside the cell.
+    Inside,
+}
+
+/// The first cell a ray hit, as returned by `Globe::raycast`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RaycastHit {
+    pub pos: CellPos,
+    pub face: CellFace,
+    /// How far along the ray the cell was hit.
+    pub distance: f64,
+}
+
+impl Globe {
+    /// Find the first cell along a ray that matches the given predicate.
+    ///
+    /// Walks along the ray from `origin` in direction `dir` (which needn't
+    /// be normalized) for up to `max_distance`, looking at every cell the
+    /// ray passes through, including when it crosses between root quads
+    /// and layers, and when it only just clips the corner of a cell.
+    ///
+    /// Cells in chunks that aren't loaded are skipped over, as is any
+    /// part of the ray that passes below the floor of the globe.
+    pub fn raycast<F>(
+        &self,
+        origin: Pt3,
+        dir: Vec3,
+        max_distance: f64,
+        predicate: F,
+    ) -> Option<RaycastHit>
+        where F: Fn(&Cell) -> bool
+    {
+        use na::Norm;
+
+        let spec = self.spec();
+        let dir_length = dir.norm();
+        if dir_length == 0.0 {
+            return None;
+        }
+        let dir = dir / dir_length;
+
+        // Walk from cell to cell, each time finding whichever
+        // of its faces the ray leaves through first, and stepping
+        // into the cell on the other side of that face.
+        let mut maybe_pos = spec.cell_containing(origin);
+        let mut face = CellFace::Inside;
+        let mut distance = 0.0;
+        while distance <= max_distance {
+            let pos = match maybe_pos {
+                Some(pos) => pos,
+                None => {
+                    // We're below the floor; skip ahead to wherever
+                    // we come back up through it, if anywhere.
+                    distance = match leave_sphere(origin, dir, spec.floor_radius) {
+                        Some(exit_distance) => exit_distance.max(distance),
+                        None => return None,
+                    };
+                    maybe_pos = Some(column_containing(&spec, origin + dir * distance).set_z(0));
+                    face = CellFace::Bottom;
+                    continue;
+                },
+            };
+
+            let is_hit = self.loaded_cell(pos).map_or(false, |cell| predicate(cell));
+            if is_hit {
+                return Some(RaycastHit {
+                    pos: pos,
+                    face: face,
+                    distance: distance,
+                });
+            }
+
+            let (exit_distance, next_pos, next_face) = exit_cell(&spec, pos, origin, dir, distance);
+            distance = exit_distance;
+            maybe_pos = next_pos;
+            face = next_face;
+        }
+        None
+    }
+}
+
+// Work out how far along the ray (from `origin` in unit direction `dir`)
+// it leaves the cell at `pos`, given that it's in there at `distance`.
+// Returns that, along with the cell on the other side (or `None` if that's
+// below the floor of the globe) and which of its faces the ray enters through.
+fn exit_cell(
+    spec: &Spec,
+    pos: CellPos,
+    origin: Pt3,
+    dir: Vec3,
+    distance: f64,
+) -> (f64, Option<CellPos>, CellFace) {
+    use na::Dot;
+
+    let bottom_radius = spec.floor_radius + spec.block_height * pos.z as f64;
+    let top_radius = bottom_radius + spec.block_height;
+
+    // We're inside the sphere at the top of the cell,
+    // so we must leave through it eventually...
+    // (If the ray only just grazes it, then we're leaving right now.)
+    let mut best_distance = leave_sphere(origin, dir, top_radius)
+        .map_or(distance, |leave_distance| leave_distance.max(distance));
+    let mut best_pos = Some(pos.set_z(pos.z + 1));
+    let mut best_face = CellFace::Bottom;
+
+    // ...unless we go through the bottom first...
+    if let Some(enter_distance) = enter_sphere(origin, dir, bottom_radius) {
+        if enter_distance > distance && enter_distance < best_distance {
+            best_distance = enter_distance;
+            best_pos = if pos.z > 0 {
+                Some(pos.set_z(pos.z - 1))
+            } else {
+                None
+            };
+            best_face = CellFace::Top;
+        }
+    }
+
+    // ...or one of the sides. Cells are the regions closest to their
+    // centers, so the side between this cell and each neighbor lies
+    // on the plane through the center of the globe that's equidistant
+    // from both cells' centers.
+    let center = spec.cell_center_on_unit_sphere(pos).to_vector();
+    for neighbor_pos in Neighbors::new(pos, spec.root_resolution) {
+        if neighbor_pos.z != pos.z {
+            continue;
+        }
+        let toward_neighbor = spec.cell_center_on_unit_sphere(neighbor_pos).to_vector() - center;
+        let closing_rate = dir.dot(&toward_neighbor);
+        if closing_rate <= 0.0 {
+            // We're not heading toward this side; this
+            // includes the one we might have just come through.
+            continue;
+        }
+        let cross_distance = (-origin.to_vector().dot(&toward_neighbor) / closing_rate).max(distance);
+        if cross_distance < best_distance {
+            best_distance = cross_distance;
+            best_pos = Some(neighbor_pos);
+            best_face = CellFace::Side(side_facing(spec, neighbor_pos, pos));
+        }
+    }
+
+    (best_distance, best_pos, best_face)
+}
+
+// How far along the ray (from `origin` in unit direction `dir`)
+// it crosses the sphere of the given `radius` around the center of
+// the globe, on the way in or out; or `None` if it misses it entirely.
+fn sphere_crossings(origin: Pt3, dir: Vec3, radius: f64) -> Option<(f64, f64)> {
+    use na::{ Dot, Norm };
+
+    let half_b = origin.to_vector().dot(&dir);
+    let c = origin.to_vector().norm_squared() - radius * radius;
+    let discriminant = half_b * half_b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-half_b - root, -half_b + root))
+}
+
+fn enter_sphere(origin: Pt3, dir: Vec3, radius: f64) -> Option<f64> {
+    sphere_crossings(origin, dir, radius).map(|(enter, _)| enter)
+}
+
+fn leave_sphere(origin: Pt3, dir: Vec3, radius: f64) -> Option<f64> {
+    sphere_crossings(origin, dir, radius).map(|(_, leave)| leave)
+}
+
+// Find the column of cells that `pt` is in, even if it's
+// right on (or just below) the floor of the globe.
+fn column_containing(spec: &Spec, pt: Pt3) -> CellPos {
+    use na::Norm;
+
+    let middle_of_bottom_layer = spec.floor_radius + spec.block_height * 0.5;
+    let pt = *(pt.as_vector().normalize() * middle_of_bottom_layer).as_point();
+    spec.cell_containing(pt).expect("Point should be above the floor")
+}
+
+// Work out which side of the cell at `pos` faces its neighbor at
+// `neighbor_pos`. The positions we look at might be outside of
+// `pos`'s root, but `project` still puts them in roughly the right place,
+// which is all we need to tell which is closest.
+fn side_facing(spec: &Spec, pos: CellPos, neighbor_pos: CellPos) -> Dir {
+    use na::Norm;
+
+    let neighbor_center = spec.cell_center_on_unit_sphere(neighbor_pos);
+    let mut best_dir = Dir::default();
+    let mut best_distance_squared = ::std::f64::INFINITY;
+    for edge_index in 0..6 {
+        let dir = Dir::new(edge_index * 2);
+        let adjacent_pos = adjacent_pos_in_dir(pos, dir)
+            .expect("Even directions always point at an edge");
+        let distance_squared =
+            (spec.cell_center_on_unit_sphere(adjacent_pos) - neighbor_center).norm_squared();
+        if distance_squared < best_distance_squared {
+            best_dir = dir;
+            best_distance_squared = distance_squared;
+        }
+    }
+    best_dir
+}
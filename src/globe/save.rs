@@ -0,0 +1,270 @@
+//! Binary format for saving a `Globe` to disk, and loading it back again.
+//!
+//! Only chunks that have been modified since they were generated are
+//! stored; everything else can be generated again from the globe's `Spec`.
+//!
+//! All numbers are little-endian. The layout is:
+//!
+//! - Magic bytes `PKGLOBE\0`
+//! - Format version (`u32`)
+//! - The globe's `Spec`
+//! - Number of chunks (`u32`), followed by that many chunks, each of which is:
+//!     - `ChunkOrigin`: root index (`u8`), then x, y, and z (`i64`)
+//!     - Chunk version (`u64`)
+//!     - Number of cells (`u32`)
+//!     - Number of runs (`u32`), followed by that many runs of identical
+//!       cells, each of which is a run length (`u32`) and then the cell:
+//!       its `MaterialId` (`u8`), shade (`f32`), and flow (`u8`).
+//!
+//! Light levels aren't stored; they're worked out again
+//! whenever a loaded chunk is next drawn.
+
+use std::io;
+use std::io::{ Read, Write };
+
+use globe::{ CellPos, ChunkOrigin, Root };
+use super::spec::Spec;
+use super::chunk::{ Chunk, Cell };
+use super::material::{ MaterialId, MaterialRegistry };
+use io_util::invalid_data;
+
+const MAGIC: &'static [u8; 8] = b"PKGLOBE\0";
+
+// Bump this whenever the format changes.
+//
+// History:
+//
+// - 1: Initial version; cells store material, shade, and flow.
+const FORMAT_VERSION: u32 = 1;
+
+pub fn write_header<W: Write>(writer: &mut W, spec: &Spec) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, FORMAT_VERSION)?;
+    write_spec(writer, spec)
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<Spec> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a PlanetKit globe save file"));
+    }
+    let format_version = read_u32(reader)?;
+    if format_version != FORMAT_VERSION {
+        return Err(invalid_data("Unsupported globe save format version"));
+    }
+    let spec = read_spec(reader)?;
+    if !spec.is_valid() {
+        return Err(invalid_data("Saved globe has an invalid spec"));
+    }
+    Ok(spec)
+}
+
+pub fn write_spec<W: Write>(writer: &mut W, spec: &Spec) -> io::Result<()> {
+    write_u32(writer, spec.seed)?;
+    write_f64(writer, spec.floor_radius)?;
+    write_f64(writer, spec.ocean_radius)?;
+    write_f64(writer, spec.block_height)?;
+    write_i64(writer, spec.root_resolution[0])?;
+    write_i64(writer, spec.root_resolution[1])?;
+    write_i64(writer, spec.chunk_resolution[0])?;
+    write_i64(writer, spec.chunk_resolution[1])?;
+    write_i64(writer, spec.chunk_resolution[2])
+}
+
+pub fn read_spec<R: Read>(reader: &mut R) -> io::Result<Spec> {
+    Ok(Spec {
+        seed: read_u32(reader)?,
+        floor_radius: read_f64(reader)?,
+        ocean_radius: read_f64(reader)?,
+        block_height: read_f64(reader)?,
+        root_resolution: [read_i64(reader)?, read_i64(reader)?],
+        chunk_resolution: [read_i64(reader)?, read_i64(reader)?, read_i64(reader)?],
+    })
+}
+
+pub fn write_chunk<W: Write>(writer: &mut W, chunk: &Chunk) -> io::Result<()> {
+    let origin = chunk.origin.pos();
+    write_u8(writer, origin.root.index)?;
+    write_i64(writer, origin.x)?;
+    write_i64(writer, origin.y)?;
+    write_i64(writer, origin.z)?;
+    write_u64(writer, chunk.version)?;
+
+    // Most chunks are big bands of the same thing (air, dirt, water)
+    // so run-length encoding gets rid of most of the bulk.
+    let mut runs: Vec<(u32, Cell)> = Vec::new();
+    for cell in &chunk.cells {
+        if let Some(&mut (ref mut run_length, ref run_cell)) = runs.last_mut() {
+            if cells_are_identical(run_cell, cell) {
+                *run_length += 1;
+                continue;
+            }
+        }
+        runs.push((1, *cell));
+    }
+
+    write_u32(writer, chunk.cells.len() as u32)?;
+    write_u32(writer, runs.len() as u32)?;
+    for &(run_length, ref cell) in &runs {
+        write_u32(writer, run_length)?;
+        write_cell(writer, cell)?;
+    }
+    Ok(())
+}
+
+/// Read a chunk written by `write_chunk`.
+///
+/// Every cell's material must be in `materials`; the file (or network
+/// message) is rejected otherwise, rather than leaving IDs around that
+/// would panic when someone looks them up later.
+pub fn read_chunk<R: Read>(
+    reader: &mut R,
+    spec: &Spec,
+    materials: &MaterialRegistry,
+) -> io::Result<Chunk> {
+    let root_index = read_u8(reader)?;
+    let x = read_i64(reader)?;
+    let y = read_i64(reader)?;
+    let z = read_i64(reader)?;
+    let version = read_u64(reader)?;
+
+    // Check the origin before building a `ChunkOrigin`; that would panic
+    // rather than telling us the file is bad.
+    let is_valid_origin =
+        root_index < 5 &&
+        x >= 0 && x < spec.root_resolution[0] && x % spec.chunk_resolution[0] == 0 &&
+        y >= 0 && y < spec.root_resolution[1] && y % spec.chunk_resolution[1] == 0 &&
+        z >= 0 && z % spec.chunk_resolution[2] == 0;
+    if !is_valid_origin {
+        return Err(invalid_data("Saved chunk has an invalid origin"));
+    }
+    let origin = ChunkOrigin::new(
+        CellPos {
+            root: Root::new(root_index),
+            x: x,
+            y: y,
+            z: z,
+        },
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+
+    let cell_count = read_u32(reader)? as usize;
+    if cell_count != spec.cells_per_chunk() {
+        return Err(invalid_data("Saved chunk has the wrong number of cells"));
+    }
+    let run_count = read_u32(reader)?;
+    let mut cells: Vec<Cell> = Vec::with_capacity(cell_count);
+    for _ in 0..run_count {
+        let run_length = read_u32(reader)? as usize;
+        let cell = read_cell(reader, materials)?;
+        if cells.len() + run_length > cell_count {
+            return Err(invalid_data("Saved chunk has too many cells"));
+        }
+        for _ in 0..run_length {
+            cells.push(cell);
+        }
+    }
+    if cells.len() != cell_count {
+        return Err(invalid_data("Saved chunk has too few cells"));
+    }
+
+    let mut chunk = Chunk::new(
+        origin,
+        cells,
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk.version = version;
+    Ok(chunk)
+}
+
+fn cells_are_identical(a: &Cell, b: &Cell) -> bool {
+    // Compare shade bit-for-bit; we want whatever we read
+    // back to be exactly what we wrote.
+    a.material == b.material && a.shade.to_bits() == b.shade.to_bits() && a.flow == b.flow
+}
+
+fn write_cell<W: Write>(writer: &mut W, cell: &Cell) -> io::Result<()> {
+    write_u8(writer, cell.material.0)?;
+    write_u32(writer, cell.shade.to_bits())?;
+    write_u8(writer, cell.flow)
+}
+
+fn read_cell<R: Read>(reader: &mut R, materials: &MaterialRegistry) -> io::Result<Cell> {
+    let material = MaterialId(read_u8(reader)?);
+    if !materials.contains(material) {
+        return Err(invalid_data("Saved cell has an unknown material"));
+    }
+    let shade = f32::from_bits(read_u32(reader)?);
+    let flow = read_u8(reader)?;
+    Ok(Cell {
+        material: material,
+        shade: shade,
+        light: 0,
+        flow: flow,
+    })
+}
+
+pub fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+
+pub fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    let mut buf = [0u8; 4];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    writer.write_all(&buf)
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    let mut value = 0u32;
+    for (i, byte) in buf.iter().enumerate() {
+        value |= (*byte as u32) << (8 * i);
+    }
+    Ok(value)
+}
+
+pub fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    let mut buf = [0u8; 8];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    writer.write_all(&buf)
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().enumerate() {
+        value |= (*byte as u64) << (8 * i);
+    }
+    Ok(value)
+}
+
+pub fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    write_u64(writer, value as u64)
+}
+
+pub fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    read_u64(reader).map(|value| value as i64)
+}
+
+pub fn write_f64<W: Write>(writer: &mut W, value: f64) -> io::Result<()> {
+    write_u64(writer, value.to_bits())
+}
+
+pub fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    read_u64(reader).map(f64::from_bits)
+}
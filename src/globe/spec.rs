@@ -2,6 +2,7 @@ use types::*;
 
 use super::IntCoord;
 use super::CellPos;
+use super::Neighbors;
 
 // Contains the specifications (dimensions, seed, etc.)
 // needed to deterministically generate a `Globe`.
@@ -25,7 +26,6 @@ pub struct Spec {
     // world can have unbounded total depth.
     pub root_resolution: [IntCoord; 2],
     pub chunk_resolution: [IntCoord; 3],
-    pub flat: bool,
 }
 
 impl Spec {
@@ -59,6 +59,17 @@ impl Spec {
         ]
     }
 
+    /// Number of cells stored in each chunk, including those on its
+    /// far edges in the x- and y-directions that it shares with its
+    /// neighbours.
+    pub fn cells_per_chunk(&self) -> usize {
+        (
+            (self.chunk_resolution[0] + 1) *
+            (self.chunk_resolution[1] + 1) *
+            self.chunk_resolution[2]
+        ) as usize
+    }
+
     // Ignore the z-coordinate; just project to a unit sphere.
     // This is useful for, e.g., sampling noise to determine elevation
     // at a particular point on the surface, or other places where you're
@@ -73,6 +84,59 @@ impl Spec {
         super::project(cell_pos.root, pt_in_root_quad)
     }
 
+    /// Find the cell containing the given point in real space,
+    /// or `None` if the point is below the floor of the globe.
+    ///
+    /// Note that the returned position might be in terms of any root
+    /// that contains the cell, like any other `CellPos`.
+    pub fn cell_containing(&self, pt: Pt3) -> Option<CellPos> {
+        use na::Norm;
+
+        let radius = pt.as_vector().norm();
+        if radius < self.floor_radius {
+            return None;
+        }
+        let z = ((radius - self.floor_radius) / self.block_height).floor() as IntCoord;
+        let pt_on_unit_sphere = *pt.as_vector().normalize().as_point();
+
+        // Start from the nearest grid point in the root quad...
+        let (root, pt_in_root_quad) = super::unproject(pt_on_unit_sphere);
+        let x = (pt_in_root_quad[0] * self.root_resolution[0] as f64).round() as IntCoord;
+        let y = (pt_in_root_quad[1] * self.root_resolution[1] as f64).round() as IntCoord;
+        let mut best_pos = CellPos {
+            root: root,
+            x: x.max(0).min(self.root_resolution[0]),
+            y: y.max(0).min(self.root_resolution[1]),
+            z: z,
+        };
+        let mut best_distance_squared =
+            (self.cell_center_on_unit_sphere(best_pos) - pt_on_unit_sphere).norm_squared();
+
+        // ...and then walk toward whichever cell center is closest.
+        // Cells are the regions closest to their centers, so that's
+        // the one that contains the point. The grid isn't quite
+        // uniform once it's been projected onto the sphere, so the
+        // nearest grid point isn't always right.
+        loop {
+            let mut found_closer = false;
+            for neighbor_pos in Neighbors::new(best_pos, self.root_resolution) {
+                if neighbor_pos.z != z {
+                    continue;
+                }
+                let distance_squared =
+                    (self.cell_center_on_unit_sphere(neighbor_pos) - pt_on_unit_sphere).norm_squared();
+                if distance_squared < best_distance_squared {
+                    best_pos = neighbor_pos;
+                    best_distance_squared = distance_squared;
+                    found_closer = true;
+                }
+            }
+            if !found_closer {
+                return Some(best_pos);
+            }
+        }
+    }
+
     pub fn cell_center_center(&self, cell_pos: CellPos) -> Pt3 {
         let radius = self.floor_radius +
             self.block_height * (cell_pos.z as f64 + 0.5);
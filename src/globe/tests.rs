@@ -1,3 +1,650 @@
+use std::io;
+
+use slog;
+
+use super::*;
+use super::globe::GlobeGuts;
+
+fn discard_log() -> slog::Logger {
+    let drain = slog::Discard;
+    slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")))
+}
+
+#[test]
+fn save_and_load_modified_chunk() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    let modified_origin = ChunkOrigin::new(
+        CellPos::default(),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let unmodified_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk_sys.build_chunk(&mut globe, modified_origin);
+    chunk_sys.build_chunk(&mut globe, unmodified_origin);
+
+    // Dig a hole in the bottom of one chunk.
+    let hole_pos = PosInOwningRoot::new(
+        CellPos::default().set_x(1).set_y(1),
+        spec.root_resolution,
+    );
+    let mut hole_cell = *globe.authoritative_cell(hole_pos);
+    hole_cell.material = material::AIR;
+    globe.set_cell(hole_pos, hole_cell);
+
+    let mut bytes: Vec<u8> = Vec::new();
+    globe.save_to(&mut bytes).unwrap();
+    let materials = MaterialRegistry::new();
+    let mut loaded_globe = Globe::load_from_with_gen(
+        &mut io::Cursor::new(bytes),
+        |spec| Box::new(FlatGen::new(spec)),
+        &materials,
+        &log,
+    ).unwrap();
+
+    // Only the modified chunk should have been saved;
+    // the other one can just be generated again.
+    assert!(loaded_globe.take_saved_chunk(unmodified_origin).is_none());
+    let loaded_chunk = loaded_globe.take_saved_chunk(modified_origin)
+        .expect("Modified chunk should have been saved");
+    let original_chunk = globe.chunks().get(&modified_origin).unwrap();
+    assert_eq!(original_chunk.version, loaded_chunk.version);
+    assert_eq!(original_chunk.cells.len(), loaded_chunk.cells.len());
+    for (original_cell, loaded_cell) in original_chunk.cells.iter().zip(loaded_chunk.cells.iter()) {
+        assert!(original_cell.material == loaded_cell.material);
+        assert_eq!(original_cell.shade, loaded_cell.shade);
+    }
+    assert!(loaded_chunk.cell(hole_pos.into()).material == material::AIR);
+
+    // The chunk that wasn't saved should come back just as it was.
+    chunk_sys.build_chunk(&mut loaded_globe, unmodified_origin);
+    let original_chunk = globe.chunks().get(&unmodified_origin).unwrap();
+    let regenerated_chunk = loaded_globe.chunks().get(&unmodified_origin).unwrap();
+    for (original_cell, regenerated_cell) in original_chunk.cells.iter().zip(regenerated_chunk.cells.iter()) {
+        assert!(original_cell.material == regenerated_cell.material);
+    }
+}
+
+#[test]
+fn load_rejects_garbage() {
+    let log = discard_log();
+    let garbage = b"This is not a globe.".to_vec();
+    let materials = MaterialRegistry::new();
+    assert!(Globe::load_from_with_gen(
+        &mut io::Cursor::new(garbage),
+        |spec| Box::new(FlatGen::new(spec)),
+        &materials,
+        &log,
+    ).is_err());
+}
+
+#[test]
+fn load_rejects_unknown_materials() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let origin = ChunkOrigin::new(CellPos::default(), spec.root_resolution, spec.chunk_resolution);
+    ChunkSystem::new(&log, 1.0).build_chunk(&mut globe, origin);
+
+    // Make something out of a material nobody has heard of.
+    let materials = MaterialRegistry::new();
+    let unknown_material = MaterialId(200);
+    assert!(!materials.contains(unknown_material));
+    let pos = PosInOwningRoot::new(CellPos::default().set_x(1).set_y(1), spec.root_resolution);
+    let mut cell = *globe.authoritative_cell(pos);
+    cell.material = unknown_material;
+    globe.set_cell(pos, cell);
+
+    let mut bytes: Vec<u8> = Vec::new();
+    globe.save_to(&mut bytes).unwrap();
+    let result = Globe::load_from_with_gen(
+        &mut io::Cursor::new(bytes),
+        |spec| Box::new(FlatGen::new(spec)),
+        &materials,
+        &log,
+    );
+    assert_eq!(io::ErrorKind::InvalidData, result.err().expect("Should have been rejected").kind());
+}
+
+#[test]
+fn set_cell_updates_chunks_sharing_it() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    let west_origin = ChunkOrigin::new(
+        CellPos::default(),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let east_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let far_origin = ChunkOrigin::new(
+        CellPos::default().set_y(8),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk_sys.build_chunk(&mut globe, west_origin);
+    chunk_sys.build_chunk(&mut globe, east_origin);
+    chunk_sys.build_chunk(&mut globe, far_origin);
+    globe.copy_all_authoritative_cells();
+    for chunk in globe.chunks_mut().values_mut() {
+        chunk.mark_view_as_clean();
+    }
+
+    // This cell is on the edge between the two chunks,
+    // so they both have a copy of it.
+    let edge_pos = CellPos::default().set_x(4).set_y(1);
+    let edge_pos_in_owning_root = PosInOwningRoot::new(edge_pos, spec.root_resolution);
+    let mut edge_cell = *globe.authoritative_cell(edge_pos_in_owning_root);
+    edge_cell.material = material::AIR;
+    globe.set_cell(edge_pos_in_owning_root, edge_cell);
+
+    for origin in &[west_origin, east_origin] {
+        let chunk = globe.chunks().get(origin).unwrap();
+        assert!(chunk.cell(edge_pos).material == material::AIR);
+        assert!(chunk.is_view_dirty);
+    }
+    // Nowhere near the change.
+    assert!(!globe.chunks().get(&far_origin).unwrap().is_view_dirty);
+}
+
+#[test]
+fn receive_chunk_updates_chunks_sharing_it() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    let west_origin = ChunkOrigin::new(
+        CellPos::default(),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let east_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let far_origin = ChunkOrigin::new(
+        CellPos::default().set_y(8),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk_sys.build_chunk(&mut globe, west_origin);
+    chunk_sys.build_chunk(&mut globe, east_origin);
+    chunk_sys.build_chunk(&mut globe, far_origin);
+    globe.copy_all_authoritative_cells();
+    for chunk in globe.chunks_mut().values_mut() {
+        chunk.mark_view_as_clean();
+    }
+
+    // Someone else dug out a cell on the edge between the two chunks,
+    // so they both have a copy of it, and sent us the newer chunk.
+    let edge_pos = CellPos::default().set_x(4).set_y(1);
+    let edge_pos_in_owning_root = PosInOwningRoot::new(edge_pos, spec.root_resolution);
+    let owner_origin = globe.origin_of_chunk_owning(edge_pos_in_owning_root);
+    let mut received_chunk = {
+        let owner = globe.chunks().get(&owner_origin).unwrap();
+        let mut received_chunk = chunk::Chunk::new(
+            owner_origin,
+            owner.cells.clone(),
+            spec.root_resolution,
+            spec.chunk_resolution,
+        );
+        received_chunk.version = owner.version + 1;
+        received_chunk
+    };
+    received_chunk.cell_mut(edge_pos_in_owning_root.into()).material = material::AIR;
+    globe.receive_chunk(received_chunk);
+
+    for origin in &[west_origin, east_origin] {
+        let chunk = globe.chunks().get(origin).unwrap();
+        assert!(chunk.cell(edge_pos).material == material::AIR);
+        assert!(chunk.is_view_dirty);
+    }
+    // Nowhere near the change.
+    assert!(!globe.chunks().get(&far_origin).unwrap().is_view_dirty);
+}
+
+#[test]
+fn marks_views_of_chunks_next_to_chunk_as_dirty() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    let west_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4).set_y(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    // Shares cells with the west chunk, which owns some of them.
+    let north_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    // Shares cells with the west chunk, and owns some of them.
+    let east_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4).set_y(8),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    // Doesn't share any cells, but is right on top.
+    let above_origin = ChunkOrigin::new(
+        CellPos::default().set_x(4).set_y(4).set_z(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let far_origin = ChunkOrigin::new(
+        CellPos::default().set_y(12),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    for origin in &[west_origin, north_origin, east_origin, above_origin, far_origin] {
+        chunk_sys.build_chunk(&mut globe, *origin);
+    }
+    for chunk in globe.chunks_mut().values_mut() {
+        chunk.mark_view_as_clean();
+    }
+
+    globe.mark_views_of_chunks_adjacent_to_chunk_as_dirty(west_origin);
+
+    for origin in &[north_origin, east_origin, above_origin] {
+        assert!(globe.chunks().get(origin).unwrap().is_view_dirty);
+    }
+    assert!(!globe.chunks().get(&far_origin).unwrap().is_view_dirty);
+}
+
+#[test]
+fn load_chunks_near_finds_chunks_across_roots() {
+    use std::collections::HashSet;
+    use na::Norm;
+    use super::chunk_system::chunk_center;
+
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let load_radius = 0.6;
+    let mut chunk_sys = ChunkSystem::new(&log, load_radius);
+    // Near the north pole, so the nearby chunks are spread over every root.
+    let anchor_pos = CellPos::default().set_x(1).set_y(1).set_z(16);
+    chunk_sys.load_chunks_near(&mut globe, &[anchor_pos]);
+
+    // Check the whole globe, the slow way.
+    let anchor_pt = spec.cell_center_center(anchor_pos);
+    let chunks_per_root = spec.chunks_per_root_side();
+    let mut roots_with_chunks = HashSet::new();
+    for root_index in 0..5 {
+        for z in 0..16 {
+            for y in 0..chunks_per_root[1] {
+                for x in 0..chunks_per_root[0] {
+                    let origin = ChunkOrigin::new(
+                        CellPos {
+                            root: Root { index: root_index },
+                            x: x * spec.chunk_resolution[0],
+                            y: y * spec.chunk_resolution[1],
+                            z: z * spec.chunk_resolution[2],
+                        },
+                        spec.root_resolution,
+                        spec.chunk_resolution,
+                    );
+                    let is_near = (chunk_center(&spec, origin) - anchor_pt).norm() <= load_radius;
+                    assert_eq!(globe.chunks().contains_key(&origin), is_near);
+                    if is_near {
+                        roots_with_chunks.insert(root_index);
+                    }
+                }
+            }
+        }
+    }
+    assert_eq!(roots_with_chunks.len(), 5);
+}
+
+#[test]
+fn shared_vertices_make_the_same_triangles() {
+    use render::Vertex;
+
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    // Right where the surface of the land is.
+    let origin = ChunkOrigin::new(
+        CellPos::default().set_z(16),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk_sys.build_chunk(&mut globe, origin);
+    // Make every cell look the same, so there's lots to share.
+    for cell in &mut globe.chunks_mut().get_mut(&origin).unwrap().cells {
+        cell.shade = 0.0;
+    }
+
+    let materials = MaterialRegistry::new();
+    let build = |meshing: Meshing| {
+        let mut view = View::new(spec, &log);
+        view.set_meshing(meshing);
+        let mut vertex_data: Vec<Vertex> = Vec::new();
+        let mut index_data: Vec<u32> = Vec::new();
+        let stats = view.make_chunk_geometry(&globe, &materials, origin, &mut vertex_data, &mut index_data);
+        assert_eq!(vertex_data.len(), stats.vertices);
+        assert_eq!(index_data.len(), stats.triangles * 3);
+        let triangle_vertices: Vec<([f32; 4], [f32; 3])> = index_data.iter()
+            .map(|&i| (vertex_data[i as usize].a_pos, vertex_data[i as usize].a_color))
+            .collect();
+        (stats, triangle_vertices)
+    };
+    let (separate_stats, separate_triangles) = build(Meshing::SeparateFaces);
+    let (shared_stats, shared_triangles) = build(Meshing::SharedVertices);
+
+    assert!(separate_stats.triangles > 0);
+    assert_eq!(separate_stats.triangles, shared_stats.triangles);
+    assert!(shared_stats.vertices < separate_stats.vertices);
+    assert_eq!(separate_triangles, shared_triangles);
+}
+
+#[test]
+fn shades_are_the_same_everywhere() {
+    let log = discard_log();
+    let spec = Globe::new_small_flat(&log).spec();
+    // One layer of chunks right around the globe, so we
+    // get every kind of edge between chunks and roots.
+    let mut origins: Vec<ChunkOrigin> = Vec::new();
+    for root_index in 0..5u8 {
+        for x in 0..(spec.root_resolution[0] / spec.chunk_resolution[0]) {
+            for y in 0..(spec.root_resolution[1] / spec.chunk_resolution[1]) {
+                let pos = CellPos {
+                    root: Root::new(root_index),
+                    x: x * spec.chunk_resolution[0],
+                    y: y * spec.chunk_resolution[1],
+                    z: 16,
+                };
+                origins.push(ChunkOrigin::new(pos, spec.root_resolution, spec.chunk_resolution));
+            }
+        }
+    }
+    let build_globe = || {
+        let mut globe = Globe::new_small_flat(&log);
+        let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+        // Don't copy cells between chunks; we want
+        // to see what each one was generated with.
+        for origin in &origins {
+            chunk_sys.build_chunk(&mut globe, *origin);
+        }
+        globe
+    };
+    let globe = build_globe();
+    let other_globe = build_globe();
+
+    for origin in &origins {
+        let chunk = globe.chunks().get(origin).unwrap();
+        let other_chunk = other_globe.chunks().get(origin).unwrap();
+        let end_x = origin.pos().x + spec.chunk_resolution[0];
+        let end_y = origin.pos().y + spec.chunk_resolution[1];
+        let end_z = origin.pos().z + spec.chunk_resolution[2] - 1;
+        for z in origin.pos().z..(end_z + 1) {
+            for y in origin.pos().y..(end_y + 1) {
+                for x in origin.pos().x..(end_x + 1) {
+                    let pos = CellPos { root: origin.pos().root, x: x, y: y, z: z };
+                    let shade = chunk.cell(pos).shade;
+                    let owner_shade = globe.authoritative_cell(PosInOwningRoot::new(pos, spec.root_resolution)).shade;
+                    assert_eq!(owner_shade, shade);
+                    assert_eq!(other_chunk.cell(pos).shade, shade);
+                    assert!(shade > 0.5 && shade <= 1.0);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn snapshot_meshes_like_the_whole_globe() {
+    use render::Vertex;
+
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    let surface_pos = CellPos::default().set_z(16);
+    chunk_sys.load_chunks_near(&mut globe, &[surface_pos]);
+    let origin = ChunkOrigin::new(surface_pos, spec.root_resolution, spec.chunk_resolution);
+
+    let snapshot = globe.snapshot_for_view(origin);
+    // It shouldn't need anywhere near all of them.
+    assert!(snapshot.chunks().len() < globe.chunks().len());
+
+    let materials = MaterialRegistry::new();
+    let build = |globe: &Globe| {
+        let view = View::new(spec, &log);
+        let mut vertex_data: Vec<Vertex> = Vec::new();
+        let mut index_data: Vec<u32> = Vec::new();
+        view.make_chunk_geometry(globe, &materials, origin, &mut vertex_data, &mut index_data);
+        let triangle_vertices: Vec<([f32; 4], [f32; 3])> = index_data.iter()
+            .map(|&i| (vertex_data[i as usize].a_pos, vertex_data[i as usize].a_color))
+            .collect();
+        triangle_vertices
+    };
+    let whole_triangles = build(&globe);
+    assert!(whole_triangles.len() > 0);
+    assert_eq!(whole_triangles, build(&snapshot));
+}
+
+#[test]
+fn cell_containing_cell_centers() {
+    let spec = example_spec();
+    // Include some awkward ones on the edges of
+    // root quads, and the north and south poles.
+    let positions = [
+        CellPos::default().set_x(5).set_y(7).set_z(3),
+        CellPos::default().set_z(10),
+        CellPos::default().set_x(32).set_y(64).set_z(2),
+        CellPos::default().set_x(32).set_y(20),
+        CellPos::default().set_y(40).set_z(50),
+        CellPos::default().set_root(3).set_x(16).set_y(32).set_z(1),
+    ];
+    for pos in &positions {
+        let found_pos = spec.cell_containing(spec.cell_center_center(*pos))
+            .expect("Cell center should be above the floor of the globe");
+        assert_eq!(
+            PosInOwningRoot::new(*pos, spec.root_resolution),
+            PosInOwningRoot::new(found_pos, spec.root_resolution)
+        );
+    }
+
+    // Below the floor.
+    assert!(spec.cell_containing(::types::Pt3::new(0.0, 0.0, 0.5)).is_none());
+}
+
+#[test]
+fn raycast_down_onto_flat_ground() {
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    for chunk_z in &[12, 16] {
+        let origin = ChunkOrigin::new(
+            CellPos::default().set_z(*chunk_z),
+            spec.root_resolution,
+            spec.chunk_resolution,
+        );
+        chunk_sys.build_chunk(&mut globe, origin);
+    }
+
+    // Fire a ray from high above straight down toward the center
+    // of the globe. The top of the flat land is at z = 16.
+    let column = CellPos::default().set_x(2).set_y(2);
+    let origin = spec.cell_center_center(column.set_z(25));
+    let dir = -origin.to_vector();
+    let hit = globe.raycast(origin, dir, 1.0, |cell| cell.material != material::AIR)
+        .expect("Ray should have hit the ground");
+    assert_eq!(column.set_z(16), hit.pos);
+    assert_eq!(CellFace::Top, hit.face);
+
+    // Not far enough.
+    assert!(globe.raycast(origin, dir, 0.1, |cell| cell.material != material::AIR).is_none());
+}
+
+#[test]
+fn raycast_clips_corner_of_cell() {
+    use na::{ Cross, Norm };
+
+    let log = discard_log();
+    let mut globe = Globe::new_small_flat(&log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    // Put a block in the air, just above the flat land.
+    let target_pos = CellPos::default().set_x(3).set_y(5).set_z(17);
+    chunk_sys.load_chunks_near(&mut globe, &[target_pos]);
+    let target_pos_in_owning_root = PosInOwningRoot::new(target_pos, spec.root_resolution);
+    let mut cell = *globe.authoritative_cell(target_pos_in_owning_root);
+    cell.material = material::DIRT;
+    globe.set_cell(target_pos_in_owning_root, cell);
+
+    // Aim just inside one of its corners, at half its height,
+    // across the direction toward its center, so that the ray
+    // only passes through a tiny sliver of the cell.
+    let radius = spec.floor_radius + spec.block_height * (target_pos.z as f64 + 0.5);
+    let center = spec.cell_center_on_unit_sphere(target_pos).to_vector() * radius;
+    let corner = spec.cell_vertex_on_unit_sphere(target_pos, cell_shape::DIR_OFFSETS[1]).to_vector() * radius;
+    let corner_to_center = center - corner;
+    let clip_pt = (corner + corner_to_center * 0.1).to_point();
+    assert_eq!(
+        target_pos_in_owning_root,
+        PosInOwningRoot::new(
+            spec.cell_containing(clip_pt).expect("Should be above the floor"),
+            spec.root_resolution,
+        )
+    );
+    let dir = clip_pt.to_vector().cross(&corner_to_center).normalize();
+    let cell_radius = corner_to_center.norm();
+    let origin = clip_pt - dir * cell_radius * 2.0;
+
+    let hit = globe.raycast(origin, dir, cell_radius * 4.0, |cell| cell.material != material::AIR)
+        .expect("Ray should have clipped the block");
+    assert_eq!(target_pos_in_owning_root, PosInOwningRoot::new(hit.pos, spec.root_resolution));
+    match hit.face {
+        CellFace::Side(_) => (),
+        face => panic!("Ray should have come in through a side, not {:?}", face),
+    }
+    assert!(hit.distance > cell_radius && hit.distance < cell_radius * 2.0);
+}
+
+// Solid all the way up; lets us tell whether `Globe` actually
+// used the generator it was given.
+struct SolidGen {
+    spec: Spec,
+}
+
+impl WorldGen for SolidGen {
+    fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    fn cell_at(&self, _cell_pos: CellPos) -> chunk::Cell {
+        chunk::Cell {
+            material: material::DIRT,
+            shade: 1.0,
+            light: 0,
+            flow: 0,
+        }
+    }
+}
+
+#[test]
+fn custom_world_gen() {
+    let log = discard_log();
+    let spec = Globe::new_small_flat(&log).spec();
+    let mut globe = Globe::new_with_gen(Box::new(SolidGen { spec: spec }), &log);
+    let mut chunk_sys = ChunkSystem::new(&log, 1.0);
+    // Way up above the flat globe's land and ocean.
+    let origin = ChunkOrigin::new(
+        CellPos::default().set_z(spec.chunk_resolution[2] * 4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    chunk_sys.build_chunk(&mut globe, origin);
+    let chunk = globe.chunks().get(&origin).unwrap();
+    assert_eq!(spec.cells_per_chunk(), chunk.cells.len());
+    assert!(chunk.cells.iter().all(|cell| cell.material == material::DIRT));
+}
+
+fn example_spec() -> Spec {
+    Spec {
+        seed: 13,
+        floor_radius: 0.91,
+        ocean_radius: 1.13,
+        block_height: 0.02,
+        root_resolution: [32, 64],
+        chunk_resolution: [16, 16, 4],
+    }
+}
+
+#[test]
+fn biome_gen_is_deterministic() {
+    let spec = example_spec();
+    let origin = ChunkOrigin::new(
+        CellPos::default().set_x(16).set_y(32).set_z(4),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let blank = chunk::Cell {
+        material: material::AIR,
+        shade: 1.0,
+        light: 0,
+        flow: 0,
+    };
+    let mut cells_a = vec![blank; spec.cells_per_chunk()];
+    let mut cells_b = vec![blank; spec.cells_per_chunk()];
+    BiomeGen::new(spec).fill_chunk(origin, &mut cells_a);
+    BiomeGen::new(spec).fill_chunk(origin, &mut cells_b);
+    for (a, b) in cells_a.iter().zip(cells_b.iter()) {
+        assert_eq!(a.material, b.material);
+    }
+}
+
+#[test]
+fn biome_gen_fill_chunk_matches_cell_at() {
+    let spec = example_spec();
+    let gen = BiomeGen::new(spec);
+    let origin = ChunkOrigin::new(
+        CellPos::default().set_y(16).set_z(8),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let mut cells = vec![gen.cell_at(*origin.pos()); spec.cells_per_chunk()];
+    gen.fill_chunk(origin, &mut cells);
+    let chunk = chunk::Chunk::new(origin, cells, spec.root_resolution, spec.chunk_resolution);
+    for z in 8..12 {
+        for y in 16..33 {
+            for x in 0..17 {
+                let pos = CellPos::default().set_x(x).set_y(y).set_z(z);
+                assert_eq!(gen.cell_at(pos).material, chunk.cell(pos).material);
+            }
+        }
+    }
+}
+
+#[test]
+fn poles_are_frozen_or_ocean() {
+    let spec = example_spec();
+    let gen = BiomeGen::new(spec);
+    let north_pole = CellPos::default();
+    let south_pole = CellPos::default().set_x(32).set_y(64);
+    for pole in &[north_pole, south_pole] {
+        let biome = gen.biome_at(*pole);
+        assert!(biome == Biome::Polar || biome == Biome::Ocean, "{:?}", biome);
+    }
+}
+
 #[cfg(feature = "nightly")]
 pub mod benches {
     use test::Bencher;
@@ -54,11 +701,11 @@ pub mod benches {
             block_height: 0.02,
             root_resolution: ROOT_RESOLUTION,
             chunk_resolution: CHUNK_RESOLUTION,
-            flat: false,
         };
         let globe = Globe::new(spec, &log);
         let spec = globe.spec();
         let globe_view = View::new(spec, &log);
+        let materials = MaterialRegistry::new();
         let mut vertex_data: Vec<Vertex> = Vec::new();
         let mut index_data: Vec<u32> = Vec::new();
         // Copied from output of old version of test to make sure
@@ -73,6 +720,7 @@ pub mod benches {
             index_data.clear();
             globe_view.make_chunk_geometry(
                 &globe,
+                &materials,
                 middle_chunk_origin,
                 &mut vertex_data,
                 &mut index_data,
@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
 use slog::Logger;
 
+use types::*;
+use super::IntCoord;
 use super::spec::Spec;
 use super::{Globe, CellPos, Cursor, ChunkOrigin};
-use super::chunk::{ Material };
+use super::MaterialRegistry;
 use super::cell_shape;
+use super::lighting;
 use ::render;
 
 // TODO: between this and "draw" we now have some confusing names.
@@ -22,17 +28,60 @@ use ::render;
 // globe when it wants us to build geometry.
 pub struct View {
     spec: Spec,
+    meshing: Meshing,
     log: Logger,
 }
 
+/// How hard to try to keep chunk meshes small.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Meshing {
+    /// Give every face of every cell its own vertices.
+    SeparateFaces,
+    /// Reuse vertices wherever neighbouring faces in the same chunk
+    /// meet and would have the same colour; e.g. the tops of
+    /// adjacent cells of the same material and shade, or the
+    /// sides of neighbouring cells. Looks exactly the same as
+    /// `SeparateFaces`; how many vertices it saves depends on
+    /// how many neighbouring cells look exactly alike.
+    SharedVertices,
+}
+
+impl Default for Meshing {
+    fn default() -> Meshing {
+        Meshing::SharedVertices
+    }
+}
+
+/// How big a mesh is; see `View::make_chunk_geometry`.
+///
+/// Add these up to see how close a whole globe's worth of
+/// chunks is to what the video card can cope with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub triangles: usize,
+}
+
+impl AddAssign for MeshStats {
+    fn add_assign(&mut self, other: MeshStats) {
+        self.vertices += other.vertices;
+        self.triangles += other.triangles;
+    }
+}
+
 impl View {
     pub fn new(globe_spec: Spec, parent_log: &Logger) -> View {
         View {
             spec: globe_spec,
+            meshing: Meshing::default(),
             log: parent_log.new(o!()),
         }
     }
 
+    pub fn set_meshing(&mut self, meshing: Meshing) {
+        self.meshing = meshing;
+    }
+
     // TODO: don't take a reference to a chunk
     // in this method; to make geometry for this
     // chunk we'll eventually need to have data for adjacent chunks
@@ -49,16 +98,23 @@ impl View {
     // a general interface that can fetch as necessary,
     // commit to always caching as much as you
     // might ever need, or some combination.
+    //
+    // Returns how many vertices and triangles were added
+    // for this chunk.
     pub fn make_chunk_geometry(
         &self,
         globe: &Globe,
+        materials: &MaterialRegistry,
         origin: ChunkOrigin,
         vertex_data: &mut Vec<render::Vertex>,
         index_data: &mut Vec<u32>
-    ) {
+    ) -> MeshStats {
         trace!(self.log, "Building chunk geometry"; "origin" => format!("{:?}", origin));
 
+        let mut vertices = ChunkVertices::new(self.meshing, vertex_data, index_data);
         let mut cursor = Cursor::new(globe, *origin.pos());
+        // For looking at the cells around each vertex.
+        let mut light_cursor = Cursor::new(globe, *origin.pos());
 
         // Include cells _on_ the far edge of the chunk;
         // even though we don't own them we'll need to draw part of them.
@@ -80,7 +136,7 @@ impl View {
 
                     cursor.set_pos(cell_pos);
 
-                    if self.cull_cell(&cursor) {
+                    if self.cull_cell(&cursor, materials) {
                        continue;
                     }
 
@@ -88,18 +144,13 @@ impl View {
                         // Eww... can I please have non-lexical borrow scopes? :)
                         let cell = cursor.cell().expect("We shouldn't be trying to build geometry for a chunk that isn't loaded.");
 
-                        // TEMP color dirt as green, ocean as blue.
-                        // TEMP: Randomly mutate cell color to make it easier to see edges.
-                        let mut inner_cell_color = if cell.material == Material::Dirt {
-                            // Grassy green
-                            [ 0.0, 0.4, 0.0 ]
-                        } else if cell.material == Material::Water {
-                            // Ocean blue
-                            [ 0.0, 0.1, 0.7 ]
-                        } else {
-                            // Don't draw air or anything else we don't understand.
+                        // Don't draw air or anything else we can see straight through.
+                        let properties = materials.get(cell.material);
+                        if !properties.opaque {
                             continue;
-                        };
+                        }
+                        // TEMP: Randomly mutate cell color to make it easier to see edges.
+                        let mut inner_cell_color = properties.color;
                         for mut color_channel in &mut inner_cell_color {
                             *color_channel *= 1.0 - 0.5 * cell.shade;
                         }
@@ -107,10 +158,6 @@ impl View {
                         inner_cell_color
                     };
 
-                    // TODO: use functions that return just the bit they care
-                    // about and... maths. This is silly.
-                    let first_top_vertex_index = vertex_data.len() as u32;
-
                     // TODO: don't switch; split all this out into calls
                     // over different ranges of cells.
                     //
@@ -135,78 +182,150 @@ impl View {
                         cell_shape::FULL_HEX
                     };
 
-                    // Emit each top vertex of whatever shape we're using for this cell.
+                    // Emit each top vertex of whatever shape we're using for this cell,
+                    // lit by the cells above it.
                     let offsets = &cell_shape.top_outline_dir_offsets;
-                    for offset in offsets.iter() {
+                    let top_vertex_indexes: Vec<u32> = offsets.iter().map(|offset| {
                         let vertex_pt3 = self.spec.cell_top_vertex(cell_pos, *offset);
-                        vertex_data.push(render::Vertex::new([
-                            vertex_pt3[0] as f32,
-                            vertex_pt3[1] as f32,
-                            vertex_pt3[2] as f32,
-                        ], cell_color));
-                    }
+                        let shading = self.vertex_shading(
+                            &mut light_cursor,
+                            materials,
+                            cell_pos.set_z(cell_z + 1),
+                            *offset,
+                            true,
+                        );
+                        let normal = up_normal(vertex_pt3);
+                        vertices.add(cell_pos, *offset, cell_z + 1, vertex_pt3, shaded(cell_color, shading), normal)
+                    }).collect();
 
                     // Emit triangles for the top of the cell. All triangles
                     // will contain the first vertex, plus two others.
-                    for i in 1..(offsets.len() as u32 - 1) {
-                        index_data.extend_from_slice(&[
-                            first_top_vertex_index,
-                            first_top_vertex_index + i,
-                            first_top_vertex_index + i + 1,
-                        ]);
+                    for i in 1..(offsets.len() - 1) {
+                        vertices.add_triangle(
+                            top_vertex_indexes[0],
+                            top_vertex_indexes[i],
+                            top_vertex_indexes[i + 1],
+                        );
                     }
 
                     // Emit each top vertex of whatever shape we're using for this cell
                     // AGAIN for the top of the sides, so they can have a different colour.
+                    // The sides are lit by the cells beside them, rather than above.
                     // Darken the top of the sides slightly to fake lighting.
                     for mut color_channel in &mut cell_color {
                         *color_channel *= 0.9;
                     }
-                    let first_side_top_vertex_index = first_top_vertex_index
-                        + offsets.len() as u32;
-                    for offset in offsets.iter() {
+                    let side_shadings: Vec<f32> = offsets.iter().map(|offset| {
+                        self.vertex_shading(&mut light_cursor, materials, cell_pos, *offset, false)
+                    }).collect();
+                    // The sides face outwards from the middle of the cell,
+                    // and the same way at the top and bottom of each edge.
+                    let cell_center_pt3 = self.spec.cell_top_vertex(cell_pos, [0, 0]);
+                    let side_normals: Vec<[f32; 3]> = offsets.iter().map(|offset| {
                         let vertex_pt3 = self.spec.cell_top_vertex(cell_pos, *offset);
-                        vertex_data.push(render::Vertex::new([
-                            vertex_pt3[0] as f32,
-                            vertex_pt3[1] as f32,
-                            vertex_pt3[2] as f32,
-                        ], cell_color));
-                    }
+                        side_normal(cell_center_pt3, vertex_pt3)
+                    }).collect();
+                    let side_top_vertex_indexes: Vec<u32> = offsets.iter().enumerate().map(|(i, offset)| {
+                        let vertex_pt3 = self.spec.cell_top_vertex(cell_pos, *offset);
+                        let color = shaded(cell_color, side_shadings[i]);
+                        vertices.add(cell_pos, *offset, cell_z + 1, vertex_pt3, color, side_normals[i])
+                    }).collect();
 
                     // Emit each bottom vertex of whatever shape we're using for this cell.
                     // Darken the bottom of the sides substantially to fake lighting.
                     for mut color_channel in &mut cell_color {
                         *color_channel *= 0.5;
                     }
-                    let first_side_bottom_vertex_index = first_side_top_vertex_index
-                        + offsets.len() as u32;
-                    for offset in offsets.iter() {
+                    let side_bottom_vertex_indexes: Vec<u32> = offsets.iter().enumerate().map(|(i, offset)| {
                         let vertex_pt3 = self.spec.cell_bottom_vertex(cell_pos, *offset);
-                        vertex_data.push(render::Vertex::new([
-                            vertex_pt3[0] as f32,
-                            vertex_pt3[1] as f32,
-                            vertex_pt3[2] as f32,
-                        ], cell_color));
-                    }
+                        let color = shaded(cell_color, side_shadings[i]);
+                        vertices.add(cell_pos, *offset, cell_z, vertex_pt3, color, side_normals[i])
+                    }).collect();
 
                     // Emit triangles for the cell sides.
-                    for ab_i in 0..(offsets.len() as u32) {
-                        let cd_i = (ab_i + 1) % offsets.len() as u32;
-                        let a_i = first_side_top_vertex_index + ab_i;
-                        let b_i = first_side_bottom_vertex_index + ab_i;
-                        let c_i = first_side_bottom_vertex_index + cd_i;
-                        let d_i = first_side_top_vertex_index + cd_i;
-                        index_data.extend_from_slice(&[
-                            a_i, b_i, d_i,
-                            d_i, b_i, c_i,
-                        ]);
+                    for ab_i in 0..offsets.len() {
+                        let cd_i = (ab_i + 1) % offsets.len();
+                        let a_i = side_top_vertex_indexes[ab_i];
+                        let b_i = side_bottom_vertex_indexes[ab_i];
+                        let c_i = side_bottom_vertex_indexes[cd_i];
+                        let d_i = side_top_vertex_indexes[cd_i];
+                        vertices.add_triangle(a_i, b_i, d_i);
+                        vertices.add_triangle(d_i, b_i, c_i);
                     }
                 }
             }
         }
+
+        let stats = vertices.stats();
+        trace!(self.log, "Built chunk geometry"; "vertices" => stats.vertices, "triangles" => stats.triangles);
+        stats
+    }
+
+    // How brightly to light the vertex at `offset` from the centre of
+    // `layer_pos`, looking at the cells in that layer around the vertex:
+    // the more light they let through, the brighter it is, and the more
+    // of them are opaque, the darker it is (ambient occlusion).
+    //
+    // Include the cell at `layer_pos` itself if `include_center`; it's
+    // the cell above for the top of a cell, but for the sides of a cell
+    // it's the cell itself, which tells us nothing.
+    fn vertex_shading(
+        &self,
+        cursor: &mut Cursor,
+        materials: &MaterialRegistry,
+        layer_pos: CellPos,
+        offset: [i64; 2],
+        include_center: bool,
+    ) -> f32 {
+        // How much darker a vertex gets if every cell around it is opaque.
+        const OCCLUSION_STRENGTH: f32 = 0.5;
+
+        let mut sample_count = 0;
+        let mut opaque_count = 0;
+        let mut total_light: u32 = 0;
+        let center = if include_center { Some([0, 0]) } else { None };
+        for column_offset in center.into_iter().chain(cells_touching_vertex(offset)) {
+            let pos = CellPos {
+                x: layer_pos.x + column_offset[0],
+                y: layer_pos.y + column_offset[1],
+                .. layer_pos
+            };
+            // TODO: look across into neighbouring roots, too.
+            let is_in_root =
+                pos.x >= 0 && pos.x <= self.spec.root_resolution[0] &&
+                pos.y >= 0 && pos.y <= self.spec.root_resolution[1] &&
+                pos.z >= 0;
+            if !is_in_root {
+                continue;
+            }
+            cursor.set_pos(pos);
+            let cell = match cursor.cell() {
+                Some(cell) => cell,
+                None => continue,
+            };
+            sample_count += 1;
+            if materials.is_opaque(cell.material) {
+                opaque_count += 1;
+            } else {
+                total_light += cell.light as u32;
+            }
+        }
+
+        if sample_count == 0 {
+            // Nothing to go on; don't darken it at all.
+            return 1.0;
+        }
+        let see_through_count = sample_count - opaque_count;
+        let light = if see_through_count > 0 {
+            total_light as f32 / see_through_count as f32
+        } else {
+            0.0
+        };
+        let occlusion = OCCLUSION_STRENGTH * opaque_count as f32 / sample_count as f32;
+        lighting::brightness(light) * (1.0 - occlusion)
     }
 
-    fn cull_cell(&self, cursor: &Cursor) -> bool {
+    fn cull_cell(&self, cursor: &Cursor, materials: &MaterialRegistry) -> bool {
         use super::Neighbors;
 
         let resolution = cursor.globe().spec().root_resolution;
@@ -214,13 +333,13 @@ impl View {
         let cell_pos = cursor.pos();
         let mut neighbor_cursor = cursor.clone();
 
-        // If none of the neighboring cells contain air,
+        // If we can't see through any of the neighboring cells,
         // then we won't render the cell at all.
         let neighbors = Neighbors::new(cell_pos, resolution);
         for neighbor_pos in neighbors {
             neighbor_cursor.set_pos(neighbor_pos);
             if let Some(neighbor) = neighbor_cursor.cell() {
-                if neighbor.material == Material::Air {
+                if !materials.is_opaque(neighbor.material) {
                     // This cell can be seen; we can't cull it.
                     return false;
                 }
@@ -232,3 +351,151 @@ impl View {
         true
     }
 }
+
+// Offsets (in cells) from a cell to the other cells in the same layer
+// that touch the point at `offset` (in the units of
+// `cell_shape::DIR_OFFSETS`) on its outline: the two cells either
+// side of a vertex, or the one across the middle of an edge.
+fn cells_touching_vertex(offset: [i64; 2]) -> Vec<[i64; 2]> {
+    use super::cell_shape::DIR_OFFSETS;
+
+    let dir_count = DIR_OFFSETS.len();
+    let i = match DIR_OFFSETS.iter().position(|dir_offset| *dir_offset == offset) {
+        Some(i) => i,
+        // The middle of the cell doesn't touch anything else.
+        None => return Vec::new(),
+    };
+    // Edges are 3 units from the middle of the cell, half way to
+    // the middle of the next one.
+    let neighbor_across = |edge_offset: [i64; 2]| [edge_offset[0] / 3, edge_offset[1] / 3];
+    if i % 2 == 0 {
+        vec![neighbor_across(offset)]
+    } else {
+        vec![
+            neighbor_across(DIR_OFFSETS[(i + dir_count - 1) % dir_count]),
+            neighbor_across(DIR_OFFSETS[(i + 1) % dir_count]),
+        ]
+    }
+}
+
+fn shaded(color: [f32; 3], shading: f32) -> [f32; 3] {
+    [color[0] * shading, color[1] * shading, color[2] * shading]
+}
+
+// Normal for the top of a cell at `pt`; straight up, away from
+// the centre of the globe.
+fn up_normal(pt: Pt3) -> [f32; 3] {
+    use na::Norm;
+    let up = pt.to_vector().normalize();
+    [up.x as f32, up.y as f32, up.z as f32]
+}
+
+// Normal for the side of a cell at the vertex `pt`, pointing
+// horizontally away from the middle of the cell at `center`.
+fn side_normal(center: Pt3, pt: Pt3) -> [f32; 3] {
+    use na::{ Norm, Dot };
+    let up = pt.to_vector().normalize();
+    let outwards = pt - center;
+    let horizontal = outwards - up * outwards.dot(&up);
+    if horizontal.norm() < ::std::f64::EPSILON {
+        // Can't tell which way is out; at least face the sky.
+        return [up.x as f32, up.y as f32, up.z as f32];
+    }
+    let normal = horizontal.normalize();
+    [normal.x as f32, normal.y as f32, normal.z as f32]
+}
+
+// Adds vertices and triangles for a single chunk to the end of
+// vertex and index buffers, reusing vertices if we're asked to.
+struct ChunkVertices<'a> {
+    meshing: Meshing,
+    vertex_data: &'a mut Vec<render::Vertex>,
+    index_data: &'a mut Vec<u32>,
+    first_vertex_index: usize,
+    first_index_index: usize,
+    // Where to find vertices we've already added, keyed
+    // on where they are, what colour they are, and which
+    // way they face.
+    // Only used with `Meshing::SharedVertices`.
+    shared_vertex_indexes: HashMap<VertexKey, u32>,
+}
+
+// Every cell in a chunk is in the same root, so we can tell
+// whether two vertices are in the same place exactly, by their
+// position in the root in the same units as `cell_shape::DIR_OFFSETS`
+// and which layer of cells they're on the bottom of.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct VertexKey {
+    x: IntCoord,
+    y: IntCoord,
+    z: IntCoord,
+    // Colours and normals have to match exactly, so compare their bits.
+    color: [u32; 3],
+    normal: [u32; 3],
+}
+
+impl<'a> ChunkVertices<'a> {
+    fn new(
+        meshing: Meshing,
+        vertex_data: &'a mut Vec<render::Vertex>,
+        index_data: &'a mut Vec<u32>,
+    ) -> ChunkVertices<'a> {
+        let first_vertex_index = vertex_data.len();
+        let first_index_index = index_data.len();
+        ChunkVertices {
+            meshing: meshing,
+            vertex_data: vertex_data,
+            index_data: index_data,
+            first_vertex_index: first_vertex_index,
+            first_index_index: first_index_index,
+            shared_vertex_indexes: HashMap::new(),
+        }
+    }
+
+    // Add a vertex at `offset` from the centre of `cell_pos`, on
+    // the bottom of layer `z`, unless there's already an identical
+    // one we can use. Returns the index of the vertex.
+    fn add(
+        &mut self,
+        cell_pos: CellPos,
+        offset: [i64; 2],
+        z: IntCoord,
+        pt: Pt3,
+        color: [f32; 3],
+        normal: [f32; 3],
+    ) -> u32 {
+        let vertex_data = &mut *self.vertex_data;
+        let mut push_vertex = || {
+            vertex_data.push(render::Vertex::new_with_normal([
+                pt[0] as f32,
+                pt[1] as f32,
+                pt[2] as f32,
+            ], color, normal));
+            (vertex_data.len() - 1) as u32
+        };
+        match self.meshing {
+            Meshing::SeparateFaces => push_vertex(),
+            Meshing::SharedVertices => {
+                let key = VertexKey {
+                    x: cell_pos.x * 6 + offset[0],
+                    y: cell_pos.y * 6 + offset[1],
+                    z: z,
+                    color: [color[0].to_bits(), color[1].to_bits(), color[2].to_bits()],
+                    normal: [normal[0].to_bits(), normal[1].to_bits(), normal[2].to_bits()],
+                };
+                *self.shared_vertex_indexes.entry(key).or_insert_with(push_vertex)
+            },
+        }
+    }
+
+    fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.index_data.extend_from_slice(&[a, b, c]);
+    }
+
+    fn stats(&self) -> MeshStats {
+        MeshStats {
+            vertices: self.vertex_data.len() - self.first_vertex_index,
+            triangles: (self.index_data.len() - self.first_index_index) / 3,
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Which buttons do what.
+//!
+//! Input adapters look up every button they're told about in an
+//! `InputBindings`, rather than each having their own hard-coded keys.
+//! `App` keeps one of these behind a lock, so the bindings can be
+//! changed while the game is running; see `App::set_input_bindings`.
+//!
+//! Bindings can be loaded from a plain text config file, with one
+//! binding per line:
+//!
+//! ```text
+//! # Walk with the arrow keys.
+//! key Up = step_forward
+//! key Down = step_backward
+//! key Left = turn_left
+//! key Right = turn_right
+//! mouse Left = pick_up
+//! mouse Right = place
+//! # Gamepad 0, button 3.
+//! gamepad 0 3 = place
+//! ```
+//!
+//! Keys and mouse buttons are named as in `piston::input`; keys can
+//! also be given by their numeric key code. Blank lines and lines
+//! starting with `#` are ignored.
+
+use std::io;
+use std::io::BufRead;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{ Arc, RwLock };
+use piston::input::{ Button, MouseButton, ControllerButton };
+use piston::input::keyboard::Key;
+use io_util::invalid_data;
+
+/// Something the player can do by pressing a button.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    StepForward,
+    StepBackward,
+    TurnLeft,
+    TurnRight,
+    /// Swim up or down while in a liquid.
+    SwimUp,
+    SwimDown,
+    PickUp,
+    Place,
+    /// Switch between looking around with the mouse
+    /// and having a normal cursor.
+    ToggleMouseLook,
+    /// Switch to the next `render::CameraMode`.
+    CycleCameraMode,
+}
+
+const ACTIONS: &'static [(Action, &'static str)] = &[
+    (Action::StepForward, "step_forward"),
+    (Action::StepBackward, "step_backward"),
+    (Action::TurnLeft, "turn_left"),
+    (Action::TurnRight, "turn_right"),
+    (Action::SwimUp, "swim_up"),
+    (Action::SwimDown, "swim_down"),
+    (Action::PickUp, "pick_up"),
+    (Action::Place, "place"),
+    (Action::ToggleMouseLook, "toggle_mouse_look"),
+    (Action::CycleCameraMode, "cycle_camera_mode"),
+];
+
+/// Maps buttons to the actions they perform.
+///
+/// Each button does at most one thing, but any number
+/// of buttons can do the same thing.
+#[derive(Clone, Debug)]
+pub struct InputBindings {
+    // There are only ever a handful of these,
+    // so there's no need for anything fancier.
+    bindings: Vec<(Button, Action)>,
+}
+
+/// Input bindings shared between the `App` and its input adapters.
+pub type SharedInputBindings = Arc<RwLock<InputBindings>>;
+
+impl Default for InputBindings {
+    /// The keys PlanetKit has always used: I/K/J/L to move around,
+    /// U to pick up blocks, and O to put them down. M toggles mouse-look,
+    /// and C switches camera mode. Swim up with Y, and down with H.
+    fn default() -> InputBindings {
+        let mut bindings = InputBindings::new();
+        bindings.bind(Button::Keyboard(Key::I), Action::StepForward);
+        bindings.bind(Button::Keyboard(Key::K), Action::StepBackward);
+        bindings.bind(Button::Keyboard(Key::J), Action::TurnLeft);
+        bindings.bind(Button::Keyboard(Key::L), Action::TurnRight);
+        bindings.bind(Button::Keyboard(Key::Y), Action::SwimUp);
+        bindings.bind(Button::Keyboard(Key::H), Action::SwimDown);
+        bindings.bind(Button::Keyboard(Key::U), Action::PickUp);
+        bindings.bind(Button::Keyboard(Key::O), Action::Place);
+        bindings.bind(Button::Keyboard(Key::M), Action::ToggleMouseLook);
+        bindings.bind(Button::Keyboard(Key::C), Action::CycleCameraMode);
+        bindings
+    }
+}
+
+impl InputBindings {
+    /// Make an empty set of bindings, where no button does anything.
+    pub fn new() -> InputBindings {
+        InputBindings {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Load bindings from a config file; see the module documentation
+    /// for the format. Only what's in the file is bound.
+    pub fn load_from<R: BufRead>(reader: R) -> io::Result<InputBindings> {
+        let mut bindings = InputBindings::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (button, action) = parse_binding(line)?;
+            bindings.bind(button, action);
+        }
+        Ok(bindings)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<InputBindings> {
+        let file = File::open(path)?;
+        InputBindings::load_from(io::BufReader::new(file))
+    }
+
+    /// Make `button` perform `action`, instead of whatever it did before.
+    pub fn bind(&mut self, button: Button, action: Action) {
+        self.unbind(button);
+        self.bindings.push((button, action));
+    }
+
+    /// Make `button` do nothing.
+    pub fn unbind(&mut self, button: Button) {
+        self.bindings.retain(|&(bound_button, _)| bound_button != button);
+    }
+
+    /// What `button` does, if anything.
+    pub fn action(&self, button: Button) -> Option<Action> {
+        self.bindings.iter()
+            .find(|&&(bound_button, _)| bound_button == button)
+            .map(|&(_, action)| action)
+    }
+
+    /// All the buttons that perform `action`.
+    pub fn buttons(&self, action: Action) -> Vec<Button> {
+        self.bindings.iter()
+            .filter(|&&(_, bound_action)| bound_action == action)
+            .map(|&(button, _)| button)
+            .collect()
+    }
+}
+
+// Keys that can be named in config files. Anything else
+// has to be given by its key code.
+const NAMED_KEYS: &'static [Key] = &[
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::D0, Key::D1, Key::D2, Key::D3, Key::D4,
+    Key::D5, Key::D6, Key::D7, Key::D8, Key::D9,
+    Key::Up, Key::Down, Key::Left, Key::Right,
+    Key::Space, Key::Return, Key::Tab, Key::Backspace, Key::Escape,
+    Key::LShift, Key::RShift, Key::LCtrl, Key::RCtrl, Key::LAlt, Key::RAlt,
+];
+
+const NAMED_MOUSE_BUTTONS: &'static [MouseButton] = &[
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::X1,
+    MouseButton::X2,
+];
+
+fn parse_binding(line: &str) -> io::Result<(Button, Action)> {
+    let mut halves = line.splitn(2, '=');
+    let button_words: Vec<&str> = halves.next()
+        .unwrap_or("")
+        .split_whitespace()
+        .collect();
+    let action_name = match halves.next() {
+        Some(action_name) => action_name.trim(),
+        None => return Err(invalid_data("Expected a button, then '=', then an action")),
+    };
+
+    let button = match (button_words.len(), button_words.first().cloned()) {
+        (2, Some("key")) => Button::Keyboard(parse_key(button_words[1])?),
+        (2, Some("mouse")) => Button::Mouse(parse_mouse_button(button_words[1])?),
+        (3, Some("gamepad")) => {
+            let id: i32 = button_words[1].parse()
+                .map_err(|_| invalid_data("Invalid gamepad ID"))?;
+            let button: u8 = button_words[2].parse()
+                .map_err(|_| invalid_data("Invalid gamepad button number"))?;
+            Button::Controller(ControllerButton::new(id, button))
+        },
+        _ => return Err(invalid_data("Expected 'key <name>', 'mouse <name>', or 'gamepad <id> <button>'")),
+    };
+
+    let action = ACTIONS.iter()
+        .find(|&&(_, name)| name == action_name)
+        .map(|&(action, _)| action)
+        .ok_or_else(|| invalid_data("Unknown action"))?;
+
+    Ok((button, action))
+}
+
+fn parse_key(name: &str) -> io::Result<Key> {
+    let named_key = NAMED_KEYS.iter()
+        .find(|key| format!("{:?}", key) == name)
+        .cloned();
+    match named_key {
+        Some(key) => Ok(key),
+        None => {
+            let code: u32 = name.parse()
+                .map_err(|_| invalid_data("Unknown key name"))?;
+            Ok(Key::from(code))
+        },
+    }
+}
+
+fn parse_mouse_button(name: &str) -> io::Result<MouseButton> {
+    NAMED_MOUSE_BUTTONS.iter()
+        .find(|mouse_button| format!("{:?}", mouse_button) == name)
+        .cloned()
+        .ok_or_else(|| invalid_data("Unknown mouse button name"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_bindings() {
+        let config = b"
+            # Arrows to move around.
+            key Up = step_forward
+            key Left = turn_left
+            mouse Left = pick_up
+            gamepad 0 3 = place
+            # Changed my mind.
+            key Up = step_backward
+        ";
+        let bindings = InputBindings::load_from(&config[..]).unwrap();
+        assert_eq!(Some(Action::StepBackward), bindings.action(Button::Keyboard(Key::Up)));
+        assert_eq!(Some(Action::TurnLeft), bindings.action(Button::Keyboard(Key::Left)));
+        assert_eq!(Some(Action::PickUp), bindings.action(Button::Mouse(MouseButton::Left)));
+        assert_eq!(Some(Action::Place), bindings.action(Button::Controller(ControllerButton::new(0, 3))));
+        // Only what's in the file is bound.
+        assert_eq!(None, bindings.action(Button::Keyboard(Key::I)));
+        assert!(bindings.buttons(Action::StepForward).is_empty());
+    }
+
+    #[test]
+    fn reject_nonsense() {
+        assert!(InputBindings::load_from(&b"key Up step_forward\n"[..]).is_err());
+        assert!(InputBindings::load_from(&b"key Up = jump\n"[..]).is_err());
+        assert!(InputBindings::load_from(&b"mouse Wheel = place\n"[..]).is_err());
+        assert!(InputBindings::load_from(&b"nose Up = place\n"[..]).is_err());
+    }
+}
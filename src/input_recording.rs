@@ -0,0 +1,291 @@
+//! Recording the game-level input events that drive `MovementSystem`
+//! and `MiningSystem`, and playing them back again later.
+//!
+//! An `InputRecorder` sits between the input adapters and the systems,
+//! writing down each event along with the tick it was delivered on.
+//! An `InputReplay` delivers the same events on the same ticks with
+//! no window or input adapters at all, so given the same starting
+//! world it should end up in exactly the same place; e.g. to
+//! reproduce a bug report, or as a regression test.
+//!
+//! Recordings are plain text, one event per line: the tick, the name
+//! of the event, and then its value(s):
+//!
+//! ```text
+//! # PlanetKit input recording
+//! 12 step_forward true
+//! 40 step_forward false
+//! 41 pick_up true
+//! 45 look -0.012 0.003
+//! 50 pan_rate 0.5
+//! ```
+//!
+//! Ticks are counted from zero when the `App` is created. Blank lines
+//! and lines starting with `#` are ignored.
+
+use std::io;
+use std::io::{ BufRead, Write };
+use std::sync::mpsc;
+use slog::Logger;
+
+use cell_dweller::{ MovementEvent, MiningEvent };
+use io_util::invalid_data;
+
+/// Any of the events that input adapters send to systems.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    Movement(MovementEvent),
+    Mining(MiningEvent),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RecordedInputEvent {
+    pub tick: u64,
+    pub event: InputEvent,
+}
+
+/// Something that feeds input events to systems, one tick at a time.
+///
+/// `App` calls `pump` on each of these right before running each tick.
+pub trait InputSource {
+    fn pump(&mut self, tick: u64);
+}
+
+/// Where to send input events so that systems will act on them.
+#[derive(Clone)]
+pub struct InputSenders {
+    pub movement: mpsc::Sender<MovementEvent>,
+    pub mining: mpsc::Sender<MiningEvent>,
+}
+
+impl InputSenders {
+    pub fn send(&self, event: InputEvent) {
+        match event {
+            InputEvent::Movement(event) => self.movement.send(event).unwrap(),
+            InputEvent::Mining(event) => self.mining.send(event).unwrap(),
+        }
+    }
+}
+
+/// Passes input events on from input adapters to systems,
+/// writing each of them to a recording on the way.
+pub struct InputRecorder {
+    log: Logger,
+    movement_receiver: mpsc::Receiver<MovementEvent>,
+    mining_receiver: mpsc::Receiver<MiningEvent>,
+    senders: InputSenders,
+    // `None` if we've given up on writing to it.
+    recording: Option<Box<Write>>,
+}
+
+impl InputRecorder {
+    /// Record events from input adapters sending to `movement_receiver`
+    /// and `mining_receiver`, and then pass them on to `senders`.
+    pub fn new(
+        movement_receiver: mpsc::Receiver<MovementEvent>,
+        mining_receiver: mpsc::Receiver<MiningEvent>,
+        senders: InputSenders,
+        mut recording: Box<Write>,
+        parent_log: &Logger,
+    ) -> InputRecorder {
+        let log = parent_log.new(o!());
+        let recording = match writeln!(recording, "# PlanetKit input recording") {
+            Ok(_) => Some(recording),
+            Err(e) => {
+                warn!(log, "Couldn't write input recording; not recording"; "error" => format!("{}", e));
+                None
+            },
+        };
+        InputRecorder {
+            log: log,
+            movement_receiver: movement_receiver,
+            mining_receiver: mining_receiver,
+            senders: senders,
+            recording: recording,
+        }
+    }
+}
+
+impl InputSource for InputRecorder {
+    fn pump(&mut self, tick: u64) {
+        let mut events: Vec<InputEvent> = Vec::new();
+        events.extend(self.movement_receiver.try_iter().map(InputEvent::Movement));
+        events.extend(self.mining_receiver.try_iter().map(InputEvent::Mining));
+        if events.is_empty() {
+            return;
+        }
+
+        let write_result = match self.recording {
+            Some(ref mut recording) => write_tick(recording, tick, &events),
+            None => Ok(()),
+        };
+        if let Err(e) = write_result {
+            warn!(self.log, "Couldn't write input recording; not recording any more"; "error" => format!("{}", e));
+            self.recording = None;
+        }
+
+        for event in events {
+            self.senders.send(event);
+        }
+    }
+}
+
+fn write_tick<W: Write>(recording: &mut W, tick: u64, events: &[InputEvent]) -> io::Result<()> {
+    for event in events {
+        write_event(recording, &RecordedInputEvent { tick: tick, event: *event })?;
+    }
+    // Flush every tick so that we don't lose
+    // anything if the game crashes.
+    recording.flush()
+}
+
+/// Feeds previously recorded input events to systems,
+/// each on the same tick it was originally recorded on.
+pub struct InputReplay {
+    // In the order they'll be sent.
+    events: Vec<RecordedInputEvent>,
+    next_event_index: usize,
+    senders: InputSenders,
+}
+
+impl InputReplay {
+    pub fn new(mut events: Vec<RecordedInputEvent>, senders: InputSenders) -> InputReplay {
+        // Stable sort, so that events on the same tick stay in order.
+        events.sort_by_key(|event| event.tick);
+        InputReplay {
+            events: events,
+            next_event_index: 0,
+            senders: senders,
+        }
+    }
+
+    /// Whether every event has been sent.
+    pub fn is_finished(&self) -> bool {
+        self.next_event_index >= self.events.len()
+    }
+}
+
+impl InputSource for InputReplay {
+    fn pump(&mut self, tick: u64) {
+        while let Some(event) = self.events.get(self.next_event_index) {
+            if event.tick > tick {
+                break;
+            }
+            // Anything from a tick we've already passed is late,
+            // but that's better than never.
+            self.senders.send(event.event);
+            self.next_event_index += 1;
+        }
+    }
+}
+
+pub fn write_event<W: Write>(writer: &mut W, event: &RecordedInputEvent) -> io::Result<()> {
+    // Rust writes floats with just enough digits to read back exactly
+    // the same number, so replays don't drift.
+    let (name, values) = match event.event {
+        InputEvent::Movement(MovementEvent::StepForward(b)) => ("step_forward", b.to_string()),
+        InputEvent::Movement(MovementEvent::StepBackward(b)) => ("step_backward", b.to_string()),
+        InputEvent::Movement(MovementEvent::TurnLeft(b)) => ("turn_left", b.to_string()),
+        InputEvent::Movement(MovementEvent::TurnRight(b)) => ("turn_right", b.to_string()),
+        InputEvent::Movement(MovementEvent::SwimUp(b)) => ("swim_up", b.to_string()),
+        InputEvent::Movement(MovementEvent::SwimDown(b)) => ("swim_down", b.to_string()),
+        InputEvent::Movement(MovementEvent::StepRate(rate)) => ("step_rate", rate.to_string()),
+        InputEvent::Movement(MovementEvent::PanRate(rate)) => ("pan_rate", rate.to_string()),
+        InputEvent::Movement(MovementEvent::Look { yaw, pitch }) => ("look", format!("{} {}", yaw, pitch)),
+        InputEvent::Mining(MiningEvent::PickUp(b)) => ("pick_up", b.to_string()),
+        InputEvent::Mining(MiningEvent::Place(b)) => ("place", b.to_string()),
+    };
+    writeln!(writer, "{} {} {}", event.tick, name, values)
+}
+
+/// Read a whole recording written by `InputRecorder`.
+pub fn read_recording<R: BufRead>(reader: R) -> io::Result<Vec<RecordedInputEvent>> {
+    let mut events: Vec<RecordedInputEvent> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        events.push(parse_event(line)?);
+    }
+    Ok(events)
+}
+
+fn parse_event(line: &str) -> io::Result<RecordedInputEvent> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 2 {
+        return Err(invalid_data("Expected a tick and an event name"));
+    }
+    let tick: u64 = words[0].parse()
+        .map_err(|_| invalid_data("Invalid tick number"))?;
+    let values = &words[2..];
+    let event = match words[1] {
+        "step_forward" => InputEvent::Movement(MovementEvent::StepForward(parse_bool(values)?)),
+        "step_backward" => InputEvent::Movement(MovementEvent::StepBackward(parse_bool(values)?)),
+        "turn_left" => InputEvent::Movement(MovementEvent::TurnLeft(parse_bool(values)?)),
+        "turn_right" => InputEvent::Movement(MovementEvent::TurnRight(parse_bool(values)?)),
+        "swim_up" => InputEvent::Movement(MovementEvent::SwimUp(parse_bool(values)?)),
+        "swim_down" => InputEvent::Movement(MovementEvent::SwimDown(parse_bool(values)?)),
+        "step_rate" => InputEvent::Movement(MovementEvent::StepRate(parse_floats(values, 1)?[0])),
+        "pan_rate" => InputEvent::Movement(MovementEvent::PanRate(parse_floats(values, 1)?[0])),
+        "look" => {
+            let floats = parse_floats(values, 2)?;
+            InputEvent::Movement(MovementEvent::Look { yaw: floats[0], pitch: floats[1] })
+        },
+        "pick_up" => InputEvent::Mining(MiningEvent::PickUp(parse_bool(values)?)),
+        "place" => InputEvent::Mining(MiningEvent::Place(parse_bool(values)?)),
+        _ => return Err(invalid_data("Unknown event name")),
+    };
+    Ok(RecordedInputEvent {
+        tick: tick,
+        event: event,
+    })
+}
+
+fn parse_bool(values: &[&str]) -> io::Result<bool> {
+    if values.len() != 1 {
+        return Err(invalid_data("Expected true or false"));
+    }
+    values[0].parse()
+        .map_err(|_| invalid_data("Expected true or false"))
+}
+
+fn parse_floats(values: &[&str], count: usize) -> io::Result<Vec<f64>> {
+    if values.len() != count {
+        return Err(invalid_data("Wrong number of values for event"));
+    }
+    values.iter()
+        .map(|value| value.parse().map_err(|_| invalid_data("Invalid number")))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_what_was_written() {
+        let events = vec![
+            RecordedInputEvent { tick: 3, event: InputEvent::Movement(MovementEvent::StepForward(true)) },
+            RecordedInputEvent { tick: 3, event: InputEvent::Movement(MovementEvent::TurnLeft(true)) },
+            RecordedInputEvent { tick: 20, event: InputEvent::Mining(MiningEvent::Place(false)) },
+            RecordedInputEvent { tick: 21, event: InputEvent::Movement(MovementEvent::Look { yaw: -0.1, pitch: 1.0 / 3.0 }) },
+            RecordedInputEvent { tick: 22, event: InputEvent::Movement(MovementEvent::StepRate(0.75)) },
+        ];
+        let mut bytes: Vec<u8> = Vec::new();
+        writeln!(bytes, "# A comment").unwrap();
+        for event in &events {
+            write_event(&mut bytes, event).unwrap();
+        }
+        assert_eq!(events, read_recording(&bytes[..]).unwrap());
+    }
+
+    #[test]
+    fn reject_nonsense() {
+        assert!(read_recording(&b"12 jump true\n"[..]).is_err());
+        assert!(read_recording(&b"twelve step_forward true\n"[..]).is_err());
+        assert!(read_recording(&b"12 step_forward\n"[..]).is_err());
+        assert!(read_recording(&b"12 look 0.5\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use globe;
+use globe::material;
+use cell_dweller;
+use cell_dweller::ClientPlayer;
+use simple;
+use app::ScriptedInput;
+use super::{ discard_log, put_guy_on_globe, SURFACE_Z };
+
+#[test]
+fn intent_driven_dweller_ignores_keyboard_mining() {
+    let root_log = discard_log();
+    let mut app = simple::new_headless(&root_log);
+
+    use globe::{ CellPos, Dir, PosInOwningRoot };
+    let start_pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+    let globe = globe::Globe::new_small_flat(&root_log);
+    let globe_spec = globe.spec();
+    let (globe_entity, npc_entity) = put_guy_on_globe(&mut app, globe, start_pos);
+    app.planner().mut_world().write::<cell_dweller::MiningIntent>()
+        .insert(npc_entity, cell_dweller::MiningIntent::new());
+
+    // Put a block right in front of the NPC, so there's something to pick up.
+    let mut block_pos = start_pos;
+    let mut block_dir = Dir::default();
+    ::movement::move_forward(&mut block_pos, &mut block_dir, globe_spec.root_resolution)
+        .expect("Should have been in good state.");
+    let block_pos = PosInOwningRoot::new(block_pos, globe_spec.root_resolution);
+    {
+        let mut globes = app.planner().mut_world().write::<globe::Globe>();
+        let globe = globes.get_mut(globe_entity).expect("Where did our globe go?");
+        let mut cell = *globe.authoritative_cell(block_pos);
+        cell.material = material::DIRT;
+        globe.set_cell(block_pos, cell);
+    }
+
+    let block_material = |app: &mut ::app::App| {
+        let globes = app.planner().mut_world().read::<globe::Globe>();
+        let globe = globes.get(globe_entity).expect("Where did our globe go?");
+        globe.authoritative_cell(block_pos).material
+    };
+
+    // Hold down the key to pick up blocks; the NPC should leave it alone.
+    use piston::input::{ Input, Button };
+    use piston::input::keyboard::Key;
+    let pick_up = ScriptedInput { tick: 0, input: Input::Press(Button::Keyboard(Key::U)) };
+    app.run_headless(10, 0.02, &[pick_up]);
+    assert_eq!(material::DIRT, block_material(&mut app));
+
+    // Take control of it, and now the keyboard should get through.
+    {
+        let world = app.planner().mut_world();
+        world.write::<cell_dweller::MiningIntent>().remove(npc_entity);
+        world.write::<ClientPlayer>().insert(npc_entity, ClientPlayer);
+    }
+    app.run_headless(10, 0.02, &[]);
+    assert_eq!(material::AIR, block_material(&mut app));
+}
@@ -1 +1,58 @@
 mod random_walk;
+mod npc_go_to;
+mod replay;
+mod swim;
+mod mining;
+mod rebind;
+
+use slog;
+use specs;
+
+use app::App;
+use globe::{ Globe, CellPos, Dir, ChunkAnchor };
+use cell_dweller::CellDweller;
+use ::Spatial;
+
+// The top of the flat land on `Globe::new_small_flat` is at z = 16,
+// so this is where you stand on it.
+const SURFACE_Z: i64 = 17;
+
+// Log to nowhere.
+fn discard_log() -> slog::Logger {
+    let drain = slog::Discard;
+    slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")))
+}
+
+// Put `globe` in the world, and a `CellDweller` on it at `start_pos`,
+// facing the default direction. Chunks are only loaded around anchors,
+// so this holds the spot open for a tick first, to give them something
+// to stand on.
+//
+// Returns the globe's entity and the `CellDweller`'s. Add whatever else
+// it needs (e.g. a `ClientPlayer` or intents) before the next tick.
+fn put_guy_on_globe(app: &mut App, globe: Globe, start_pos: CellPos) -> (specs::Entity, specs::Entity) {
+    let globe_spec = globe.spec();
+    let globe_entity = app.planner().mut_world().create_now()
+        .with(globe)
+        .build();
+
+    let spawn_anchor_entity = app.planner().mut_world().create_now()
+        .with(ChunkAnchor::new(Some(globe_entity), start_pos))
+        .build();
+    app.tick(0.02);
+
+    let mut cd = CellDweller::new(
+        start_pos,
+        Dir::default(),
+        globe_spec,
+        Some(globe_entity),
+    );
+    let spatial = Spatial::new(cd.get_real_transform_and_mark_as_clean());
+    let guy_entity = app.planner().mut_world().create_now()
+        .with(cd)
+        .with(spatial)
+        .build();
+    app.planner().mut_world().delete_later(spawn_anchor_entity);
+
+    (globe_entity, guy_entity)
+}
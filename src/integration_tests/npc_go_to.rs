@@ -0,0 +1,35 @@
+use globe;
+use cell_dweller;
+use simple;
+use super::{ discard_log, put_guy_on_globe, SURFACE_Z };
+
+#[test]
+fn npc_go_to() {
+    let root_log = discard_log();
+    let mut app = simple::new_headless(&root_log);
+
+    // Flat ground, so there's definitely a way there.
+    use globe::{ CellPos, PosInOwningRoot };
+    let start_pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+    let goal_pos = CellPos::default().set_x(6).set_y(5).set_z(SURFACE_Z);
+    let globe = globe::Globe::new_small_flat(&root_log);
+    let globe_spec = globe.spec();
+    let (_, npc_entity) = put_guy_on_globe(&mut app, globe, start_pos);
+    {
+        let world = app.planner().mut_world();
+        world.write::<cell_dweller::MovementIntent>()
+            .insert(npc_entity, cell_dweller::MovementIntent::new());
+        world.write::<cell_dweller::Behaviour>()
+            .insert(npc_entity, cell_dweller::Behaviour::new(cell_dweller::Goal::GoTo(goal_pos)));
+    }
+
+    // Give it plenty of time to get there.
+    app.run_headless(500, 0.02, &[]);
+
+    let cell_dwellers = app.planner().mut_world().read::<cell_dweller::CellDweller>();
+    let npc = cell_dwellers.get(npc_entity).expect("Where did our NPC go?");
+    assert_eq!(
+        PosInOwningRoot::new(goal_pos, globe_spec.root_resolution),
+        PosInOwningRoot::new(npc.pos, globe_spec.root_resolution)
+    );
+}
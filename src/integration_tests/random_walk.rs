@@ -1,115 +1,75 @@
-use std::sync::mpsc;
-
-use slog;
 use globe;
-use specs;
 use cell_dweller;
-
-// TODO: make a proper test harness using `App`, `piston::window::NoWindow`,
-// and some custom systems to drive the tests.
+use simple;
+use app::ScriptedInput;
+use super::{ discard_log, put_guy_on_globe };
 
 #[test]
 fn random_walk() {
-    // Log to nowhere.
-    let drain = slog::Discard;
-    let root_log = slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")));
-
-    // Create Specs `World`.
-    let mut world = specs::World::new();
-
-    // Register all component types.
-    world.register::<::cell_dweller::CellDweller>();
-    world.register::<::Spatial>();
-    world.register::<::globe::Globe>();
-
-    // Create systems.
-    use ::system_priority as prio;
-
-    let chunk_sys = globe::ChunkSystem::new(
-        &root_log,
-    );
-
-    let (movement_input_sender, movement_input_receiver) = mpsc::channel();
-    let movement_sys = cell_dweller::MovementSystem::new(
-        movement_input_receiver,
-        &root_log,
-    );
-
-    // This isn't actually used yet.
-    let physics_sys = cell_dweller::PhysicsSystem::new(
-        &root_log,
-        0.1, // Seconds between falls
-    );
-
-    // Hand the world off to a Specs `Planner`.
-    let mut planner = specs::Planner::new(world, 2);
-    planner.add_system(chunk_sys, "chunk", prio::CHUNK);
-    planner.add_system(movement_sys, "cd_movement", prio::CD_MOVEMENT);
-    planner.add_system(physics_sys, "cd_physics", prio::CD_PHYSICS);
+    let root_log = discard_log();
+    let mut app = simple::new_headless(&root_log);
 
     // Make a flat globe to prevent the CellDweller from ever getting stuck.
     // TODO: actually do this.
     // REVISIT: make the heigh vary by 1, to test gravity / climbing.
     let globe = globe::Globe::new_example(&root_log);
-    // First add the globe to the world so we can get a handle on its entity.
     let globe_spec = globe.spec();
-    let globe_entity = planner.mut_world().create_now()
-        .with(globe)
-        .build();
 
-    // Step the world once before adding our character;
-    // otherwise there won't be any chunks so we won't know where to put him!
-    planner.dispatch(0.02);
-    planner.wait();
+    // Start out around sea level, and then move
+    // down (or up) to wherever the ground is.
+    use globe::CellPos;
+    let spawn_pos = CellPos::default().set_z(
+        ((globe_spec.ocean_radius - globe_spec.floor_radius) / globe_spec.block_height) as i64
+    );
+    let (globe_entity, guy_entity) = put_guy_on_globe(&mut app, globe, spawn_pos);
 
     // Find globe surface and put player character on it.
-    use globe::{ CellPos, Dir };
-    use globe::chunk::Material;
-    let mut guy_pos = CellPos::default();
-    guy_pos = {
-        let globes = planner
-            .mut_world()
-            .read::<globe::Globe>();
+    use globe::material;
+    {
+        let world = app.planner().mut_world();
+        let globes = world.read::<globe::Globe>();
         let globe = globes
             .get(globe_entity)
             .expect("Uh oh, where did our Globe go?");
-        globe.find_lowest_cell_containing(guy_pos, Material::Air)
-            .expect("Uh oh, there's something wrong with our globe.")
-    };
-    planner.mut_world().create_now()
-        .with(cell_dweller::CellDweller::new(
-            guy_pos,
-            Dir::default(),
-            globe_spec,
-            Some(globe_entity),
-        ))
-        .with(::Spatial::root())
-        .build();
-
-    // Start our CellDweller moving forward indefinitely.
-    use cell_dweller::MovementEvent;
-    movement_input_sender.send(MovementEvent::StepForward(true)).unwrap();
+        let guy_pos = globe.find_lowest_cell_containing(CellPos::default(), material::AIR)
+            .expect("Uh oh, there's something wrong with our globe.");
+        let mut cell_dwellers = world.write::<cell_dweller::CellDweller>();
+        let cd = cell_dwellers.get_mut(guy_entity).expect("Where did our guy go?");
+        cd.set_cell_pos(guy_pos);
+        world.write::<cell_dweller::ClientPlayer>()
+            .insert(guy_entity, cell_dweller::ClientPlayer);
+    }
 
-    use rand;
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    for _ in 0..10000 {
+    // Start our CellDweller moving forward indefinitely,
+    // and then mash the turn keys at random.
+    use piston::input::{ Input, Button };
+    use piston::input::keyboard::Key;
+    let press = |tick, key| ScriptedInput { tick: tick, input: Input::Press(Button::Keyboard(key)) };
+    let release = |tick, key| ScriptedInput { tick: tick, input: Input::Release(Button::Keyboard(key)) };
+    let mut script = vec![press(0, Key::I)];
+
+    // Take the same walk every time, so that if it ever
+    // goes wrong, we can do it again.
+    use rand::{ Rng, SeedableRng, XorShiftRng };
+    let seed = [0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb];
+    let mut rng = XorShiftRng::from_seed(seed);
+    for tick in 0..10000 {
         // Maybe turn left or right.
         let f: f32 = rng.gen();
         if f < 0.02 {
             // Turn left.
-            movement_input_sender.send(MovementEvent::TurnLeft(true)).unwrap();
-            movement_input_sender.send(MovementEvent::TurnRight(false)).unwrap();
+            script.push(press(tick, Key::J));
+            script.push(release(tick, Key::L));
         } else if f < 0.01 {
             // Turn right.
-            movement_input_sender.send(MovementEvent::TurnLeft(false)).unwrap();
-            movement_input_sender.send(MovementEvent::TurnRight(true)).unwrap();
+            script.push(release(tick, Key::J));
+            script.push(press(tick, Key::L));
         } else {
             // Walk straight.
-            movement_input_sender.send(MovementEvent::TurnLeft(false)).unwrap();
-            movement_input_sender.send(MovementEvent::TurnRight(false)).unwrap();
+            script.push(release(tick, Key::J));
+            script.push(release(tick, Key::L));
         }
-
-        planner.dispatch(0.02);
     }
+
+    app.run_headless(10000, 0.02, &script);
 }
@@ -0,0 +1,41 @@
+use globe;
+use cell_dweller;
+use simple;
+use app::ScriptedInput;
+use input_bindings::InputBindings;
+use super::{ discard_log, put_guy_on_globe, SURFACE_Z };
+
+#[test]
+fn rebinding_held_key_lets_go_of_it() {
+    let root_log = discard_log();
+    let mut app = simple::new_headless(&root_log);
+
+    let start_pos = globe::CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+    let globe = globe::Globe::new_small_flat(&root_log);
+    let (_, guy_entity) = put_guy_on_globe(&mut app, globe, start_pos);
+    app.planner().mut_world().write::<cell_dweller::ClientPlayer>()
+        .insert(guy_entity, cell_dweller::ClientPlayer);
+
+    let where_is = |app: &mut ::app::App| {
+        let cell_dwellers = app.planner().mut_world().read::<cell_dweller::CellDweller>();
+        cell_dwellers.get(guy_entity).expect("Where did our guy go?").pos
+    };
+
+    // Hold down the key to walk forward, and make sure we're going somewhere.
+    use piston::input::{ Input, Button };
+    use piston::input::keyboard::Key;
+    let step_forward = ScriptedInput { tick: 0, input: Input::Press(Button::Keyboard(Key::I)) };
+    app.run_headless(20, 0.02, &[step_forward]);
+    assert!(where_is(&mut app) != start_pos);
+
+    // Make that key do nothing while it's still held down.
+    let mut bindings = InputBindings::default();
+    bindings.unbind(Button::Keyboard(Key::I));
+    app.set_input_bindings(bindings);
+
+    // We should stop walking, even though the key was never released.
+    app.run_headless(1, 0.02, &[]);
+    let stopped_pos = where_is(&mut app);
+    app.run_headless(50, 0.02, &[]);
+    assert_eq!(stopped_pos, where_is(&mut app));
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::io::Write;
+use std::sync::{ Arc, Mutex };
+use slog;
+use specs;
+
+use globe;
+use cell_dweller;
+use simple;
+use app::{ App, ScriptedInput };
+use input_recording;
+use super::{ discard_log, put_guy_on_globe, SURFACE_Z };
+
+// Lets us read back what an `InputRecorder` wrote
+// after handing it over to the app.
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Put someone on some flat ground, in exactly the same
+// way every time, and return their entity.
+fn set_up_world(app: &mut App, log: &slog::Logger) -> specs::Entity {
+    let start_pos = globe::CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+    let globe = globe::Globe::new_small_flat(log);
+    let (_, guy_entity) = put_guy_on_globe(app, globe, start_pos);
+    let world = app.planner().mut_world();
+    world.write::<cell_dweller::ClientPlayer>()
+        .insert(guy_entity, cell_dweller::ClientPlayer);
+    world.write::<cell_dweller::BlockInventory>()
+        .insert(guy_entity, cell_dweller::BlockInventory::new());
+    guy_entity
+}
+
+fn where_is(app: &mut App, entity: specs::Entity) -> (globe::CellPos, globe::Dir) {
+    let cell_dwellers = app.planner().mut_world().read::<cell_dweller::CellDweller>();
+    let cd = cell_dwellers.get(entity).expect("Where did our guy go?");
+    (cd.pos, cd.dir)
+}
+
+#[test]
+fn replay_recorded_input() {
+    let root_log = discard_log();
+
+    // Walk around and dig a bit, recording everything we do.
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    let mut recording_app = simple::new_headless_recording(&root_log, Box::new(buffer.clone()));
+    let recording_guy = set_up_world(&mut recording_app, &root_log);
+    let (start_pos, _) = where_is(&mut recording_app, recording_guy);
+
+    use piston::input::{ Input, Button };
+    use piston::input::keyboard::Key;
+    let press = |tick, key| ScriptedInput { tick: tick, input: Input::Press(Button::Keyboard(key)) };
+    let release = |tick, key| ScriptedInput { tick: tick, input: Input::Release(Button::Keyboard(key)) };
+    let script = vec![
+        press(0, Key::I),
+        release(12, Key::I),
+        press(13, Key::J),
+        release(23, Key::J),
+        press(30, Key::U),
+        release(31, Key::U),
+        press(40, Key::I),
+        release(47, Key::I),
+    ];
+    recording_app.run_headless(100, 0.02, &script);
+    let recorded_state = where_is(&mut recording_app, recording_guy);
+    // Make sure we actually went somewhere; otherwise standing
+    // still in both runs would look like a perfect replay.
+    assert!(recorded_state.0 != start_pos);
+
+    let recording = {
+        let bytes = buffer.0.lock().unwrap();
+        input_recording::read_recording(&bytes[..]).expect("Couldn't read back recording")
+    };
+    assert!(!recording.is_empty());
+
+    // Now do it all again without any of the original input.
+    let mut replaying_app = simple::new_headless_replaying(&root_log, recording);
+    let replaying_guy = set_up_world(&mut replaying_app, &root_log);
+    replaying_app.run_headless(100, 0.02, &[]);
+    let replayed_state = where_is(&mut replaying_app, replaying_guy);
+
+    assert_eq!(recorded_state, replayed_state);
+}
@@ -0,0 +1,59 @@
+use globe;
+use globe::material;
+use cell_dweller;
+use simple;
+use app::ScriptedInput;
+use super::{ discard_log, put_guy_on_globe, SURFACE_Z };
+
+#[test]
+fn swim_up_and_sink_back_down() {
+    let root_log = discard_log();
+    let mut app = simple::new_headless(&root_log);
+
+    use globe::{ CellPos, PosInOwningRoot };
+    let surface_z = SURFACE_Z;
+    let pool_depth = 3;
+    let start_pos = CellPos::default().set_x(2).set_y(2).set_z(surface_z);
+    let globe = globe::Globe::new_small_flat(&root_log);
+    let globe_spec = globe.spec();
+    let (globe_entity, guy_entity) = put_guy_on_globe(&mut app, globe, start_pos);
+    app.planner().mut_world().write::<cell_dweller::ClientPlayer>()
+        .insert(guy_entity, cell_dweller::ClientPlayer);
+
+    // Fill a pool on top of the land, right where we're standing.
+    // It'll spread out a bit, but there's always water where we put it.
+    {
+        let mut globes = app.planner().mut_world().write::<globe::Globe>();
+        let globe = globes.get_mut(globe_entity).expect("Where did our globe go?");
+        for z in surface_z..(surface_z + pool_depth) {
+            let pos = PosInOwningRoot::new(start_pos.set_z(z), globe_spec.root_resolution);
+            let mut cell = *globe.authoritative_cell(pos);
+            cell.material = material::WATER;
+            globe.set_cell(pos, cell);
+        }
+    }
+
+    let where_is = |app: &mut ::app::App| {
+        let cell_dwellers = app.planner().mut_world().read::<cell_dweller::CellDweller>();
+        let cd = cell_dwellers.get(guy_entity).expect("Where did our guy go?");
+        cd.pos
+    };
+
+    // Swim up to the top of the pool, and come up for air.
+    use piston::input::{ Input, Button };
+    use piston::input::keyboard::Key;
+    let swim_up = ScriptedInput { tick: 0, input: Input::Press(Button::Keyboard(Key::Y)) };
+    app.run_headless(30, 0.02, &[swim_up]);
+    assert_eq!(surface_z + pool_depth, where_is(&mut app).z);
+
+    // Stay there for as long as we keep swimming up.
+    for _ in 0..100 {
+        app.tick(0.02);
+        assert_eq!(surface_z + pool_depth, where_is(&mut app).z);
+    }
+
+    // Stop swimming, and sink back down to the bottom.
+    let stop_swimming = ScriptedInput { tick: 0, input: Input::Release(Button::Keyboard(Key::Y)) };
+    app.run_headless(200, 0.02, &[stop_swimming]);
+    assert_eq!(surface_z, where_is(&mut app).z);
+}
@@ -0,0 +1,6 @@
+use std::io;
+
+/// For rejecting files (or network messages) that don't make sense.
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
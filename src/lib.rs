@@ -34,6 +34,8 @@ extern crate ncollide;
 extern crate test;
 
 pub mod input_adapter;
+pub mod input_bindings;
+pub mod input_recording;
 pub mod globe;
 pub mod types;
 pub mod app;
@@ -42,10 +44,18 @@ pub mod render;
 pub mod simple;
 pub mod cell_dweller;
 pub mod movement;
+pub mod navigation;
+pub mod net;
 pub mod system_priority;
+pub mod worker_pool;
 
 mod spatial;
 pub use spatial::Spatial;
 
+mod sun;
+pub use sun::{ Sun, SunSystem };
+
+mod io_util;
+
 #[cfg(test)]
 mod integration_tests;
@@ -3,7 +3,7 @@ use ::globe::{ IntCoord, CellPos, Dir };
 use super::transform::*;
 use super::util::*;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum TurnDir {
     Left,
     Right,
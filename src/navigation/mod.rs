@@ -0,0 +1,8 @@
+//! Working out how `CellDweller`s can get from one place to another.
+
+mod pathfinder;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::pathfinder::{ find_path, landing_pos };
@@ -0,0 +1,246 @@
+use std::cmp::Ordering;
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+
+use globe::{ Globe, Spec, CellPos, Dir, PosInOwningRoot, MaterialRegistry };
+use movement::{ TurnDir, turn_by_one_hex_edge };
+use cell_dweller::{ walkable_step, ForwardOrBackward };
+
+// Every way a `CellDweller` could turn before stepping forward,
+// cheapest first. A pentagon has one less edge than this assumes,
+// so one of these will end up facing the same way as another,
+// but that just means we'll find the same neighbor twice.
+const TURNS: &'static [(TurnDir, usize)] = &[
+    (TurnDir::Left, 0),
+    (TurnDir::Left, 1),
+    (TurnDir::Right, 1),
+    (TurnDir::Left, 2),
+    (TurnDir::Right, 2),
+    (TurnDir::Left, 3),
+];
+
+struct Node {
+    pos: CellPos,
+    dir: Dir,
+    last_turn_bias: TurnDir,
+    // Cost of the cheapest known path from the start to here.
+    cost: f64,
+    parent: Option<usize>,
+    // States passed through while turning on the spot in
+    // the parent's cell before stepping forward to get here.
+    turns: Vec<(CellPos, Dir)>,
+}
+
+// Entry in the open set. Ordered backwards by estimated total
+// cost so that `BinaryHeap` gives us the cheapest one first.
+struct OpenNode {
+    estimated_cost: f64,
+    index: usize,
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &OpenNode) -> bool {
+        self.estimated_cost == other.estimated_cost
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &OpenNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &OpenNode) -> Ordering {
+        other.estimated_cost.partial_cmp(&self.estimated_cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Find a path for a `CellDweller` standing at `start_pos` and facing
+/// `start_dir` to walk to `goal`, using A* search.
+///
+/// `start_last_turn_bias` should be the `CellDweller`'s `last_turn_bias`;
+/// it decides which way they end up facing after some steps,
+/// so the path wouldn't match what they really do otherwise.
+///
+/// Returns every `(CellPos, Dir)` state the `CellDweller` must pass
+/// through on the way, starting with the one it's in now. Consecutive
+/// states in the same cell mean turning by one hex edge; otherwise they
+/// mean stepping forward. If stepping forward would walk off a ledge,
+/// then the state after it is where they'll land.
+///
+/// Uses the same rules for climbing and support as `MovementSystem`,
+/// via `walkable_step`. Cells in chunks that aren't loaded are treated
+/// as impassable.
+///
+/// Gives up and returns `None` if there's no path, or if it would
+/// have to look at more than `max_visited` cells to find one.
+pub fn find_path(
+    globe: &Globe,
+    materials: &MaterialRegistry,
+    start_pos: CellPos,
+    start_dir: Dir,
+    start_last_turn_bias: TurnDir,
+    goal: CellPos,
+    max_visited: usize,
+) -> Option<Vec<(CellPos, Dir)>> {
+    let spec = globe.spec();
+    let resolution = spec.root_resolution;
+    let goal_in_owning_root = PosInOwningRoot::new(goal, resolution);
+
+    let mut nodes: Vec<Node> = vec![Node {
+        pos: start_pos,
+        dir: start_dir,
+        last_turn_bias: start_last_turn_bias,
+        cost: 0.0,
+        parent: None,
+        turns: Vec::new(),
+    }];
+    let mut open = BinaryHeap::new();
+    open.push(OpenNode {
+        estimated_cost: heuristic(&spec, start_pos, goal),
+        index: 0,
+    });
+    let mut best_costs: HashMap<PosInOwningRoot, f64> = HashMap::new();
+    let mut closed: HashSet<PosInOwningRoot> = HashSet::new();
+
+    while let Some(OpenNode { index, .. }) = open.pop() {
+        let (pos, dir, last_turn_bias, cost) = {
+            let node = &nodes[index];
+            (node.pos, node.dir, node.last_turn_bias, node.cost)
+        };
+        let pos_in_owning_root = PosInOwningRoot::new(pos, resolution);
+        if closed.contains(&pos_in_owning_root) {
+            // We already found a cheaper way here.
+            continue;
+        }
+        if pos_in_owning_root == goal_in_owning_root {
+            return Some(reconstruct_path(&nodes, index));
+        }
+        closed.insert(pos_in_owning_root);
+        if closed.len() > max_visited {
+            return None;
+        }
+
+        for &(turn_dir, turn_count) in TURNS {
+            // Turn on the spot to face the neighbor we're
+            // going to try to step to.
+            let mut facing_pos = pos;
+            let mut facing_dir = dir;
+            let mut turns = Vec::new();
+            for _ in 0..turn_count {
+                turn_by_one_hex_edge(&mut facing_pos, &mut facing_dir, resolution, turn_dir)
+                    .expect("CellDweller should have been in good state.");
+                turns.push((facing_pos, facing_dir));
+            }
+
+            let step = walkable_step(
+                globe,
+                materials,
+                facing_pos,
+                facing_dir,
+                last_turn_bias,
+                ForwardOrBackward::Forward,
+            );
+            let (new_pos, new_dir, new_last_turn_bias) = match step {
+                Some(step) => step,
+                None => continue,
+            };
+            let new_pos = match landing_pos(globe, materials, new_pos) {
+                Some(new_pos) => new_pos,
+                None => continue,
+            };
+
+            let new_pos_in_owning_root = PosInOwningRoot::new(new_pos, resolution);
+            if closed.contains(&new_pos_in_owning_root) {
+                continue;
+            }
+            let new_cost = cost + step_cost(&spec, pos, new_pos);
+            let is_cheaper = best_costs.get(&new_pos_in_owning_root)
+                .map_or(true, |&best_cost| new_cost < best_cost);
+            if !is_cheaper {
+                continue;
+            }
+            best_costs.insert(new_pos_in_owning_root, new_cost);
+
+            nodes.push(Node {
+                pos: new_pos,
+                dir: new_dir,
+                last_turn_bias: new_last_turn_bias,
+                cost: new_cost,
+                parent: Some(index),
+                turns: turns,
+            });
+            open.push(OpenNode {
+                estimated_cost: new_cost + heuristic(&spec, new_pos, goal),
+                index: nodes.len() - 1,
+            });
+        }
+    }
+
+    None
+}
+
+// Follow the parents back from `index` to the start.
+fn reconstruct_path(nodes: &[Node], index: usize) -> Vec<(CellPos, Dir)> {
+    let mut reversed_path = Vec::new();
+    let mut maybe_index = Some(index);
+    while let Some(index) = maybe_index {
+        let node = &nodes[index];
+        reversed_path.push((node.pos, node.dir));
+        reversed_path.extend(node.turns.iter().rev());
+        maybe_index = node.parent;
+    }
+    reversed_path.reverse();
+    reversed_path
+}
+
+/// Where a `CellDweller` that just stepped into `pos` will end up once
+/// `PhysicsSystem` has finished dropping it, or `None` if it would fall
+/// through a chunk that isn't loaded.
+pub fn landing_pos(globe: &Globe, materials: &MaterialRegistry, pos: CellPos) -> Option<CellPos> {
+    let mut pos = pos;
+    loop {
+        if pos.z < 1 {
+            // There's nothing below; someone built a silly globe.
+            return None;
+        }
+        let under_pos = pos.set_z(pos.z - 1);
+        let under_cell = match globe.loaded_cell(under_pos) {
+            Some(under_cell) => under_cell,
+            None => return None,
+        };
+        if materials.is_solid(under_cell.material) {
+            return Some(pos);
+        }
+        pos = under_pos;
+    }
+}
+
+// Angle between the centers of two cells, as seen from
+// the center of the globe.
+fn angle_between(spec: &Spec, a: CellPos, b: CellPos) -> f64 {
+    use na::{ Norm, Dot };
+
+    let a = spec.cell_center_center(a).to_vector().normalize();
+    let b = spec.cell_center_center(b).to_vector().normalize();
+    // Clamp to avoid NaN from rounding error.
+    a.dot(&b).max(-1.0).min(1.0).acos()
+}
+
+// Cost of stepping directly between two adjacent cells:
+// the distance across the floor of the globe, plus any
+// climbing or falling.
+fn step_cost(spec: &Spec, from: CellPos, to: CellPos) -> f64 {
+    let horizontal = angle_between(spec, from, to) * spec.floor_radius;
+    let vertical = (to.z - from.z).abs() as f64 * spec.block_height;
+    horizontal + vertical
+}
+
+// Great-circle distance between two cells, measured across the floor
+// of the globe so that it never overestimates the cost of a path.
+fn heuristic(spec: &Spec, from: CellPos, to: CellPos) -> f64 {
+    angle_between(spec, from, to) * spec.floor_radius
+}
@@ -0,0 +1,158 @@
+use slog;
+
+use globe::{ Globe, ChunkSystem, ChunkOrigin, CellPos, Dir, PosInOwningRoot, Neighbors, MaterialRegistry };
+use globe::material;
+use movement::{ TurnDir, turn_by_one_hex_edge };
+use cell_dweller::{ walkable_step, ForwardOrBackward };
+use super::*;
+
+fn discard_log() -> slog::Logger {
+    let drain = slog::Discard;
+    slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")))
+}
+
+// The top of the flat land is at z = 16,
+// so this is where you stand on it.
+const SURFACE_Z: i64 = 17;
+
+fn flat_globe_with_chunks_loaded(log: &slog::Logger) -> Globe {
+    let mut globe = Globe::new_small_flat(log);
+    let spec = globe.spec();
+    let mut chunk_sys = ChunkSystem::new(log, 1.0);
+    for &(x, y) in &[(0, 0), (4, 0), (0, 4), (4, 4), (0, 8), (4, 8)] {
+        let origin = ChunkOrigin::new(
+            CellPos::default().set_x(x).set_y(y).set_z(16),
+            spec.root_resolution,
+            spec.chunk_resolution,
+        );
+        chunk_sys.build_chunk(&mut globe, origin);
+    }
+    globe.copy_all_authoritative_cells();
+    globe
+}
+
+// Surround `pos` with dirt, `height` cells tall.
+fn build_wall_around(globe: &mut Globe, pos: CellPos, height: i64) {
+    let resolution = globe.spec().root_resolution;
+    let wall_positions: Vec<CellPos> = Neighbors::new(pos, resolution)
+        .filter(|neighbor| neighbor.z == pos.z)
+        .collect();
+    for wall_pos in wall_positions {
+        for z in pos.z..(pos.z + height) {
+            let wall_pos_in_owning_root = PosInOwningRoot::new(wall_pos.set_z(z), resolution);
+            let mut cell = *globe.authoritative_cell(wall_pos_in_owning_root);
+            cell.material = material::DIRT;
+            globe.set_cell(wall_pos_in_owning_root, cell);
+        }
+    }
+}
+
+fn assert_path_between(globe: &Globe, path: &[(CellPos, Dir)], start: CellPos, goal: CellPos) {
+    let resolution = globe.spec().root_resolution;
+    assert_eq!(start, path.first().unwrap().0);
+    assert_eq!(
+        PosInOwningRoot::new(goal, resolution),
+        PosInOwningRoot::new(path.last().unwrap().0, resolution)
+    );
+}
+
+#[test]
+fn find_path_across_flat_ground() {
+    let log = discard_log();
+    let globe = flat_globe_with_chunks_loaded(&log);
+    let materials = MaterialRegistry::new();
+    let start = CellPos::default().set_x(1).set_y(1).set_z(SURFACE_Z);
+    let goal = CellPos::default().set_x(6).set_y(3).set_z(SURFACE_Z);
+
+    let path = find_path(&globe, &materials, start, Dir::default(), TurnDir::Left, goal, 1000)
+        .expect("Should have found a path across flat ground");
+    assert_path_between(&globe, &path, start, goal);
+    // Nothing to climb or fall off.
+    assert!(path.iter().all(|&(pos, _)| pos.z == SURFACE_Z));
+}
+
+#[test]
+fn find_path_climbs_low_walls() {
+    let log = discard_log();
+    let mut globe = flat_globe_with_chunks_loaded(&log);
+    let materials = MaterialRegistry::new();
+    let start = CellPos::default().set_x(1).set_y(1).set_z(SURFACE_Z);
+    let goal = CellPos::default().set_x(5).set_y(5).set_z(SURFACE_Z);
+    build_wall_around(&mut globe, goal, 1);
+
+    let path = find_path(&globe, &materials, start, Dir::default(), TurnDir::Left, goal, 1000)
+        .expect("Should have climbed over the wall");
+    assert_path_between(&globe, &path, start, goal);
+    assert!(path.iter().any(|&(pos, _)| pos.z == SURFACE_Z + 1));
+}
+
+#[test]
+fn no_path_over_high_walls() {
+    let log = discard_log();
+    let mut globe = flat_globe_with_chunks_loaded(&log);
+    let materials = MaterialRegistry::new();
+    let start = CellPos::default().set_x(1).set_y(1).set_z(SURFACE_Z);
+    let goal = CellPos::default().set_x(5).set_y(5).set_z(SURFACE_Z);
+    build_wall_around(&mut globe, goal, 2);
+
+    assert!(find_path(&globe, &materials, start, Dir::default(), TurnDir::Left, goal, 1000).is_none());
+}
+
+// Follow `path` the way a `CellDweller` really would, and make sure
+// every step ends up exactly where the path says it should.
+fn assert_path_is_walkable(
+    globe: &Globe,
+    materials: &MaterialRegistry,
+    path: &[(CellPos, Dir)],
+    start_last_turn_bias: TurnDir,
+) {
+    let resolution = globe.spec().root_resolution;
+    let mut last_turn_bias = start_last_turn_bias;
+    for states in path.windows(2) {
+        let (pos, dir) = states[0];
+        let next_state = states[1];
+
+        // Turning on the spot doesn't change the turn bias.
+        let is_turn = [TurnDir::Left, TurnDir::Right].iter().any(|&turn_dir| {
+            let mut turned_pos = pos;
+            let mut turned_dir = dir;
+            turn_by_one_hex_edge(&mut turned_pos, &mut turned_dir, resolution, turn_dir)
+                .expect("CellDweller should have been in good state.");
+            (turned_pos, turned_dir) == next_state
+        });
+        if is_turn {
+            continue;
+        }
+
+        let (new_pos, new_dir, new_last_turn_bias) = walkable_step(
+            globe,
+            materials,
+            pos,
+            dir,
+            last_turn_bias,
+            ForwardOrBackward::Forward,
+        ).expect("Path should only step where we can walk");
+        let new_pos = landing_pos(globe, materials, new_pos)
+            .expect("Path should only step where we can land");
+        assert_eq!(next_state, (new_pos, new_dir));
+        last_turn_bias = new_last_turn_bias;
+    }
+}
+
+#[test]
+fn find_path_from_pentagon_with_either_turn_bias() {
+    let log = discard_log();
+    let globe = flat_globe_with_chunks_loaded(&log);
+    let materials = MaterialRegistry::new();
+    // Which way you end up facing after stepping out of a pentagon
+    // depends on which way you last had to turn.
+    let start = CellPos::default().set_z(SURFACE_Z);
+    let goal = CellPos::default().set_x(6).set_y(3).set_z(SURFACE_Z);
+
+    for &last_turn_bias in &[TurnDir::Left, TurnDir::Right] {
+        let path = find_path(&globe, &materials, start, Dir::default(), last_turn_bias, goal, 1000)
+            .expect("Should have found a path across flat ground");
+        assert_path_between(&globe, &path, start, goal);
+        assert_path_is_walkable(&globe, &materials, &path, last_turn_bias);
+    }
+}
@@ -0,0 +1,252 @@
+use std::io;
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use specs;
+use slog::Logger;
+
+use types::*;
+use globe::{ Globe, Spec, MaterialRegistry };
+use cell_dweller::{
+    CellDweller,
+    MovementEvent,
+    MiningEvent,
+    MovementIntent,
+    MiningIntent,
+    ClientPlayer,
+};
+use ::Spatial;
+use super::NetworkId;
+use super::connection::Connection;
+use super::codec::{
+    ClientMessage,
+    ServerMessage,
+    CellDwellerState,
+    encode_client_message,
+    decode_server_message,
+};
+
+/// Plays on a globe run by a `ServerSystem` somewhere else.
+///
+/// Sends the server whatever the player is trying to do, according to
+/// the same input events that `MovementSystem` and `MiningSystem` would
+/// otherwise act on, and makes the local copy of the globe and the
+/// `CellDweller`s on it match what the server says.
+///
+/// The server moves everyone a whole step or turn at a time, so
+/// analog input (e.g. from a gamepad) and mouse-look aren't sent;
+/// they're ignored, with a warning the first time.
+///
+/// Don't run those systems (or `PhysicsSystem`) in the same app;
+/// the server is the only one allowed to move things around.
+/// `ChunkSystem` is still needed, to load the chunks around the
+/// player just like usual. So is a `MaterialRegistry` resource;
+/// chunks made of anything that isn't in it are ignored.
+pub struct ClientSystem {
+    log: Logger,
+    // `None` once the server has gone away.
+    connection: Option<Connection>,
+    globe_entity: specs::Entity,
+    movement_input_receiver: mpsc::Receiver<MovementEvent>,
+    mining_input_receiver: mpsc::Receiver<MiningEvent>,
+    movement_intent: MovementIntent,
+    mining_intent: MiningIntent,
+    // What we last told the server, so we only send changes.
+    sent_intents: Option<(MovementIntent, MiningIntent)>,
+    // So we only complain once about input we can't send.
+    has_warned_about_unsent_input: bool,
+    // Which `CellDweller` is ours, once the server has told us.
+    player_id: Option<NetworkId>,
+    // Our copies of the server's `CellDweller`s.
+    replicas: HashMap<NetworkId, specs::Entity>,
+}
+
+impl ClientSystem {
+    /// Play on the server at the other end of `stream`, keeping
+    /// the globe `globe_entity` up to date with what happens there.
+    ///
+    /// The globe must have been made with the same `Spec` and `WorldGen`
+    /// as the server's; only the differences are sent over the network.
+    pub fn new(
+        stream: TcpStream,
+        globe_entity: specs::Entity,
+        movement_input_receiver: mpsc::Receiver<MovementEvent>,
+        mining_input_receiver: mpsc::Receiver<MiningEvent>,
+        parent_log: &Logger,
+    ) -> io::Result<ClientSystem> {
+        let log = parent_log.new(o!("system" => "client"));
+        if let Ok(addr) = stream.peer_addr() {
+            info!(log, "Connecting to server"; "addr" => format!("{}", addr));
+        }
+        Ok(ClientSystem {
+            log: log,
+            connection: Some(Connection::new(stream)?),
+            globe_entity: globe_entity,
+            movement_input_receiver: movement_input_receiver,
+            mining_input_receiver: mining_input_receiver,
+            movement_intent: MovementIntent::new(),
+            mining_intent: MiningIntent::new(),
+            sent_intents: None,
+            has_warned_about_unsent_input: false,
+            player_id: None,
+            replicas: HashMap::new(),
+        })
+    }
+
+    fn consume_input(&mut self) {
+        loop {
+            match self.movement_input_receiver.try_recv() {
+                Ok(MovementEvent::StepForward(b)) => self.movement_intent.step_forward = b,
+                Ok(MovementEvent::StepBackward(b)) => self.movement_intent.step_backward = b,
+                Ok(MovementEvent::TurnLeft(b)) => self.movement_intent.turn_left = b,
+                Ok(MovementEvent::TurnRight(b)) => self.movement_intent.turn_right = b,
+                Ok(MovementEvent::SwimUp(b)) => self.movement_intent.swim_up = b,
+                Ok(MovementEvent::SwimDown(b)) => self.movement_intent.swim_down = b,
+                // There's no way to tell the server about these;
+                // see `ClientSystem`.
+                Ok(MovementEvent::StepRate(_)) |
+                Ok(MovementEvent::PanRate(_)) |
+                Ok(MovementEvent::Look { .. }) => {
+                    if !self.has_warned_about_unsent_input {
+                        warn!(self.log, "Analog input and mouse-look don't work when playing on a server; ignoring them");
+                        self.has_warned_about_unsent_input = true;
+                    }
+                },
+                Err(_) => break,
+            }
+        }
+        loop {
+            match self.mining_input_receiver.try_recv() {
+                Ok(MiningEvent::PickUp(b)) => self.mining_intent.pick_up = b,
+                Ok(MiningEvent::Place(b)) => self.mining_intent.place = b,
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn sync(&mut self, w: &specs::World) {
+        let mut globes = w.write::<Globe>();
+        let globe = match globes.get_mut(self.globe_entity) {
+            Some(globe) => globe,
+            None => {
+                warn!(self.log, "The globe we're playing on is not alive! Can't proceed!");
+                return;
+            },
+        };
+        let spec = globe.spec();
+        let materials = w.read_resource::<MaterialRegistry>();
+
+        let received = {
+            let connection = match self.connection {
+                Some(ref mut connection) => connection,
+                // Nothing more we can do.
+                None => return,
+            };
+
+            // Tell the server if we want to do something different.
+            let intents = (self.movement_intent, self.mining_intent);
+            if self.sent_intents != Some(intents) {
+                connection.send(&encode_client_message(&ClientMessage::Intents {
+                    movement: self.movement_intent,
+                    mining: self.mining_intent,
+                }));
+                self.sent_intents = Some(intents);
+            }
+
+            connection.flush().and_then(|_| connection.receive())
+        };
+        let frames = match received {
+            Ok(frames) => frames,
+            Err(e) => {
+                warn!(self.log, "Lost connection to server"; "reason" => format!("{}", e));
+                self.connection = None;
+                return;
+            },
+        };
+
+        // Only the newest state of each `CellDweller` matters; we might
+        // have heard about several ticks' worth of movement at once.
+        let mut cell_dweller_states: HashMap<NetworkId, CellDwellerState> = HashMap::new();
+        for frame in frames {
+            let message = match decode_server_message(&frame, &spec, &materials) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!(self.log, "Ignoring bad message from server"; "error" => format!("{}", e));
+                    continue;
+                },
+            };
+            match message {
+                ServerMessage::Welcome { spec: server_spec, player_id } => {
+                    if !specs_match(&spec, &server_spec) {
+                        warn!(self.log, "Server's globe doesn't match ours; things are going to look weird");
+                    }
+                    info!(self.log, "Joined server"; "player_id" => player_id.0);
+                    self.player_id = Some(player_id);
+                },
+                ServerMessage::CellDweller(state) => {
+                    cell_dweller_states.insert(state.id, state);
+                },
+                ServerMessage::CellDwellerRemoved(id) => {
+                    cell_dweller_states.remove(&id);
+                    if let Some(entity) = self.replicas.remove(&id) {
+                        w.delete_later(entity);
+                    }
+                },
+                ServerMessage::Chunk(chunk) => {
+                    // Decoding already checked it fits our globe and
+                    // is only made of materials we know about.
+                    trace!(self.log, "Received chunk"; "origin" => format!("{:?}", chunk.origin), "version" => chunk.version);
+                    globe.receive_chunk(chunk);
+                },
+            }
+        }
+
+        let mut cell_dwellers = w.write::<CellDweller>();
+        let mut spatials = w.write::<Spatial>();
+        for (id, state) in cell_dweller_states {
+            if let Some(&entity) = self.replicas.get(&id) {
+                if let (Some(cd), Some(spatial)) = (cell_dwellers.get_mut(entity), spatials.get_mut(entity)) {
+                    cd.set_cell_transform(state.pos, state.dir, state.last_turn_bias);
+                    cd.yaw = state.yaw;
+                    spatial.transform = cd.get_real_transform_and_mark_as_clean();
+                }
+                continue;
+            }
+
+            let mut cd = CellDweller::new(state.pos, state.dir, spec, Some(self.globe_entity));
+            cd.last_turn_bias = state.last_turn_bias;
+            cd.yaw = state.yaw;
+            let spatial = Spatial::new(cd.get_real_transform_and_mark_as_clean());
+            let entity = if Some(id) == self.player_id {
+                w.create_later_build()
+                    .with(cd)
+                    .with(spatial)
+                    .with(id)
+                    .with(ClientPlayer)
+                    .build()
+            } else {
+                w.create_later_build()
+                    .with(cd)
+                    .with(spatial)
+                    .with(id)
+                    .build()
+            };
+            self.replicas.insert(id, entity);
+        }
+    }
+}
+
+impl specs::System<TimeDelta> for ClientSystem {
+    fn run(&mut self, arg: specs::RunArg, _dt: TimeDelta) {
+        self.consume_input();
+        arg.fetch(|w| self.sync(w));
+    }
+}
+
+// Whether two globes have the same cells in the same places;
+// that's all that matters for making sense of the server's messages.
+fn specs_match(a: &Spec, b: &Spec) -> bool {
+    a.seed == b.seed &&
+        a.root_resolution == b.root_resolution &&
+        a.chunk_resolution == b.chunk_resolution
+}
@@ -0,0 +1,237 @@
+//! Binary format for messages between `ServerSystem` and `ClientSystem`.
+//!
+//! Each message is sent as its own frame, and starts with a tag (`u8`)
+//! saying what kind of message it is. Numbers, `Spec`s, and `Chunk`s
+//! are written the same way as in save files; see `globe::save`.
+//!
+//! Client to server:
+//!
+//! - `1`: `Intents`: movement flags (`u8`), then mining flags (`u8`).
+//!
+//! Server to client:
+//!
+//! - `1`: `Welcome`: the globe's `Spec`, then the client's player's `NetworkId` (`u64`).
+//! - `2`: `CellDweller`: `NetworkId` (`u64`), position (root index `u8`,
+//!   then x, y, and z `i64`), direction (`u8`), last turn bias (`u8`),
+//!   and yaw (`f64`).
+//! - `3`: `CellDwellerRemoved`: `NetworkId` (`u64`).
+//! - `4`: `Chunk`: a whole chunk, including its version.
+
+use std::io;
+use std::io::{ Read, Write };
+
+use globe::{ Spec, CellPos, Dir, Root, MaterialRegistry };
+use globe::chunk::Chunk;
+use globe::save;
+use cell_dweller::{ MovementIntent, MiningIntent };
+use movement::TurnDir;
+use super::NetworkId;
+use io_util::invalid_data;
+
+const CLIENT_INTENTS: u8 = 1;
+
+const SERVER_WELCOME: u8 = 1;
+const SERVER_CELL_DWELLER: u8 = 2;
+const SERVER_CELL_DWELLER_REMOVED: u8 = 3;
+const SERVER_CHUNK: u8 = 4;
+
+const STEP_FORWARD: u8 = 1 << 0;
+const STEP_BACKWARD: u8 = 1 << 1;
+const TURN_LEFT: u8 = 1 << 2;
+const TURN_RIGHT: u8 = 1 << 3;
+const SWIM_UP: u8 = 1 << 4;
+const SWIM_DOWN: u8 = 1 << 5;
+
+const PICK_UP: u8 = 1 << 0;
+const PLACE: u8 = 1 << 1;
+
+pub enum ClientMessage {
+    /// What the client's player is trying to do right now.
+    Intents {
+        movement: MovementIntent,
+        mining: MiningIntent,
+    },
+}
+
+/// Where a `CellDweller` is, as far as everyone else needs to know.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CellDwellerState {
+    pub id: NetworkId,
+    pub pos: CellPos,
+    pub dir: Dir,
+    pub last_turn_bias: TurnDir,
+    pub yaw: f64,
+}
+
+pub enum ServerMessage {
+    /// The first thing each client hears.
+    Welcome {
+        spec: Spec,
+        player_id: NetworkId,
+    },
+    /// A `CellDweller` has appeared, or moved.
+    CellDweller(CellDwellerState),
+    CellDwellerRemoved(NetworkId),
+    /// A newer version of a chunk than the client has seen before.
+    Chunk(Chunk),
+}
+
+pub fn encode_client_message(message: &ClientMessage) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    write_client_message(&mut bytes, message)
+        .expect("Writing to a Vec can't fail");
+    bytes
+}
+
+fn write_client_message<W: Write>(writer: &mut W, message: &ClientMessage) -> io::Result<()> {
+    match *message {
+        ClientMessage::Intents { movement, mining } => {
+            save::write_u8(writer, CLIENT_INTENTS)?;
+            let mut movement_flags = 0;
+            if movement.step_forward { movement_flags |= STEP_FORWARD; }
+            if movement.step_backward { movement_flags |= STEP_BACKWARD; }
+            if movement.turn_left { movement_flags |= TURN_LEFT; }
+            if movement.turn_right { movement_flags |= TURN_RIGHT; }
+            if movement.swim_up { movement_flags |= SWIM_UP; }
+            if movement.swim_down { movement_flags |= SWIM_DOWN; }
+            save::write_u8(writer, movement_flags)?;
+            let mut mining_flags = 0;
+            if mining.pick_up { mining_flags |= PICK_UP; }
+            if mining.place { mining_flags |= PLACE; }
+            save::write_u8(writer, mining_flags)
+        },
+    }
+}
+
+pub fn decode_client_message(mut bytes: &[u8]) -> io::Result<ClientMessage> {
+    let reader = &mut bytes;
+    match save::read_u8(reader)? {
+        CLIENT_INTENTS => {
+            let movement_flags = save::read_u8(reader)?;
+            let mining_flags = save::read_u8(reader)?;
+            Ok(ClientMessage::Intents {
+                movement: MovementIntent {
+                    step_forward: movement_flags & STEP_FORWARD != 0,
+                    step_backward: movement_flags & STEP_BACKWARD != 0,
+                    turn_left: movement_flags & TURN_LEFT != 0,
+                    turn_right: movement_flags & TURN_RIGHT != 0,
+                    swim_up: movement_flags & SWIM_UP != 0,
+                    swim_down: movement_flags & SWIM_DOWN != 0,
+                },
+                mining: MiningIntent {
+                    pick_up: mining_flags & PICK_UP != 0,
+                    place: mining_flags & PLACE != 0,
+                },
+            })
+        },
+        _ => Err(invalid_data("Unknown client message")),
+    }
+}
+
+pub fn encode_server_message(message: &ServerMessage) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    write_server_message(&mut bytes, message)
+        .expect("Writing to a Vec can't fail");
+    bytes
+}
+
+fn write_server_message<W: Write>(writer: &mut W, message: &ServerMessage) -> io::Result<()> {
+    match *message {
+        ServerMessage::Welcome { ref spec, player_id } => {
+            save::write_u8(writer, SERVER_WELCOME)?;
+            save::write_spec(writer, spec)?;
+            save::write_u64(writer, player_id.0)
+        },
+        ServerMessage::CellDweller(ref state) => {
+            save::write_u8(writer, SERVER_CELL_DWELLER)?;
+            save::write_u64(writer, state.id.0)?;
+            write_cell_pos(writer, state.pos)?;
+            save::write_u8(writer, state.dir.index)?;
+            save::write_u8(writer, match state.last_turn_bias {
+                TurnDir::Left => 0,
+                TurnDir::Right => 1,
+            })?;
+            save::write_f64(writer, state.yaw)
+        },
+        ServerMessage::CellDwellerRemoved(id) => {
+            save::write_u8(writer, SERVER_CELL_DWELLER_REMOVED)?;
+            save::write_u64(writer, id.0)
+        },
+        ServerMessage::Chunk(ref chunk) => {
+            save::write_u8(writer, SERVER_CHUNK)?;
+            save::write_chunk(writer, chunk)
+        },
+    }
+}
+
+/// Decode a message from the server.
+///
+/// `spec` is needed to make sense of chunks; use whatever the
+/// server sent in its `Welcome`. The `Welcome` itself can be
+/// decoded with any `Spec`. Chunks made of anything that isn't
+/// in `materials` are rejected.
+pub fn decode_server_message(
+    mut bytes: &[u8],
+    spec: &Spec,
+    materials: &MaterialRegistry,
+) -> io::Result<ServerMessage> {
+    let reader = &mut bytes;
+    match save::read_u8(reader)? {
+        SERVER_WELCOME => {
+            let spec = save::read_spec(reader)?;
+            if !spec.is_valid() {
+                return Err(invalid_data("Server sent an invalid spec"));
+            }
+            Ok(ServerMessage::Welcome {
+                spec: spec,
+                player_id: NetworkId(save::read_u64(reader)?),
+            })
+        },
+        SERVER_CELL_DWELLER => {
+            let id = NetworkId(save::read_u64(reader)?);
+            let pos = read_cell_pos(reader)?;
+            let dir_index = save::read_u8(reader)?;
+            if dir_index >= 12 {
+                return Err(invalid_data("Invalid direction"));
+            }
+            let dir = Dir::new(dir_index);
+            let last_turn_bias = match save::read_u8(reader)? {
+                0 => TurnDir::Left,
+                1 => TurnDir::Right,
+                _ => return Err(invalid_data("Unknown turn direction")),
+            };
+            Ok(ServerMessage::CellDweller(CellDwellerState {
+                id: id,
+                pos: pos,
+                dir: dir,
+                last_turn_bias: last_turn_bias,
+                yaw: save::read_f64(reader)?,
+            }))
+        },
+        SERVER_CELL_DWELLER_REMOVED => {
+            Ok(ServerMessage::CellDwellerRemoved(NetworkId(save::read_u64(reader)?)))
+        },
+        SERVER_CHUNK => Ok(ServerMessage::Chunk(save::read_chunk(reader, spec, materials)?)),
+        _ => Err(invalid_data("Unknown server message")),
+    }
+}
+
+fn write_cell_pos<W: Write>(writer: &mut W, pos: CellPos) -> io::Result<()> {
+    save::write_u8(writer, pos.root.index)?;
+    save::write_i64(writer, pos.x)?;
+    save::write_i64(writer, pos.y)?;
+    save::write_i64(writer, pos.z)
+}
+
+fn read_cell_pos<R: Read>(reader: &mut R) -> io::Result<CellPos> {
+    let root_index = save::read_u8(reader)?;
+    if root_index >= 5 {
+        return Err(invalid_data("Invalid root index"));
+    }
+    Ok(CellPos {
+        root: Root::new(root_index),
+        x: save::read_i64(reader)?,
+        y: save::read_i64(reader)?,
+        z: save::read_i64(reader)?,
+    })
+}
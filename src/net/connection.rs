@@ -0,0 +1,100 @@
+use std::io;
+use std::io::{ Read, Write };
+use std::net::TcpStream;
+
+use globe::save;
+
+// Anything bigger than this means the other end is confused,
+// or up to no good; a whole chunk is much smaller.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+// How much to try reading from the socket at a time.
+const READ_BUFFER_LENGTH: usize = 4096;
+
+// A non-blocking TCP connection that sends and receives whole
+// messages ("frames"), each prefixed by its length as a `u32`.
+//
+// Nothing here ever waits for the network; whatever can't be sent
+// right away is kept until the next `flush`, and partial frames
+// are kept until the rest of them arrive.
+pub struct Connection {
+    stream: TcpStream,
+    // Bytes received that don't make up a whole frame yet.
+    incoming: Vec<u8>,
+    // Bytes waiting to be written to the socket.
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nonblocking(true)?;
+        // We send lots of little messages, and want them to arrive
+        // as soon as possible.
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream: stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    // Queue up a frame to be sent on the next `flush`.
+    pub fn send(&mut self, frame: &[u8]) {
+        save::write_u32(&mut self.outgoing, frame.len() as u32)
+            .expect("Writing to a Vec can't fail");
+        self.outgoing.extend_from_slice(frame);
+    }
+
+    // Write as much as we can of whatever is waiting to be sent.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "Connection closed"));
+                },
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Read whatever has arrived, and return all the complete frames in it.
+    //
+    // Returns an error if the other end has hung up.
+    pub fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut buffer = [0u8; READ_BUFFER_LENGTH];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"));
+                },
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        loop {
+            if self.incoming.len() < 4 {
+                break;
+            }
+            let frame_length = save::read_u32(&mut &self.incoming[..4])? as usize;
+            if frame_length > MAX_FRAME_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too long"));
+            }
+            if self.incoming.len() < 4 + frame_length {
+                break;
+            }
+            frames.push(self.incoming[4..(4 + frame_length)].to_vec());
+            self.incoming.drain(..(4 + frame_length));
+        }
+        Ok(frames)
+    }
+}
@@ -0,0 +1,23 @@
+//! Playing together over a network.
+//!
+//! One app runs a `ServerSystem`, which owns the authoritative copy of
+//! a `Globe` and runs all the usual `CellDweller` systems on it. Any
+//! number of other apps each run a `ClientSystem`, which sends the
+//! server what its player is trying to do, and keeps a copy of the
+//! globe and everyone on it up to date with whatever the server says.
+//!
+//! Everything goes over TCP; see `codec` for what the messages look like.
+
+mod network_id;
+mod connection;
+pub mod codec;
+mod server_system;
+mod client_system;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::network_id::NetworkId;
+pub use self::codec::{ ClientMessage, ServerMessage, CellDwellerState };
+pub use self::server_system::ServerSystem;
+pub use self::client_system::ClientSystem;
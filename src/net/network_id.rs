@@ -0,0 +1,12 @@
+use specs;
+
+/// Identifies the same thing on the server and all its clients.
+///
+/// `specs::Entity`s are only meaningful within one `World`, so the
+/// server gives everything it tells clients about one of these.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NetworkId(pub u64);
+
+impl specs::Component for NetworkId {
+    type Storage = specs::HashMapStorage<NetworkId>;
+}
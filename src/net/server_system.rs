@@ -0,0 +1,273 @@
+use std::io;
+use std::collections::{ HashMap, HashSet };
+use std::net::TcpListener;
+use specs;
+use slog::Logger;
+
+use types::*;
+use globe::{ Globe, CellPos, Dir, ChunkOrigin };
+use globe::chunk::Chunk;
+use cell_dweller::{ CellDweller, MovementIntent, MiningIntent, BlockInventory };
+use ::Spatial;
+use super::NetworkId;
+use super::connection::Connection;
+use super::codec::{
+    ClientMessage,
+    ServerMessage,
+    CellDwellerState,
+    encode_server_message,
+    decode_client_message,
+};
+
+struct Client {
+    log: Logger,
+    connection: Connection,
+    // The `CellDweller` this client controls.
+    player_entity: specs::Entity,
+    // The last state we sent this client for each `CellDweller`,
+    // so we only need to tell them about what has changed.
+    known_cell_dwellers: HashMap<NetworkId, CellDwellerState>,
+    // The newest version of each chunk this client has.
+    // Anything missing is still at version 1, i.e. just as
+    // it was generated, and the client can generate it too.
+    known_chunk_versions: HashMap<ChunkOrigin, u64>,
+    // The latest intents we've heard from this client that haven't
+    // been given to its `CellDweller` yet; the player's entity isn't
+    // built until the end of the tick they connected in.
+    unapplied_intents: Option<(MovementIntent, MiningIntent)>,
+}
+
+/// Runs the authoritative copy of a `Globe` for clients to play on.
+///
+/// Each client that connects gets a `CellDweller` of its own, which
+/// the usual `CellDweller` systems move around according to the
+/// intents the client sends. In return, the server sends each client
+/// where all the `CellDweller`s are, and any chunks that have been
+/// modified since the client last saw them.
+pub struct ServerSystem {
+    log: Logger,
+    listener: TcpListener,
+    globe_entity: specs::Entity,
+    spawn_pos: CellPos,
+    clients: Vec<Client>,
+    next_network_id: u64,
+}
+
+impl ServerSystem {
+    /// Accept clients on `listener`, and put each of them on the
+    /// globe `globe_entity` at `spawn_pos`.
+    ///
+    /// Make sure the chunks around `spawn_pos` are loaded (e.g. with a
+    /// `ChunkAnchor`), or clients will fall through the world.
+    pub fn new(
+        listener: TcpListener,
+        globe_entity: specs::Entity,
+        spawn_pos: CellPos,
+        parent_log: &Logger,
+    ) -> io::Result<ServerSystem> {
+        // Never wait around for new clients.
+        listener.set_nonblocking(true)?;
+        let log = parent_log.new(o!("system" => "server"));
+        if let Ok(addr) = listener.local_addr() {
+            info!(log, "Listening for clients"; "addr" => format!("{}", addr));
+        }
+        Ok(ServerSystem {
+            log: log,
+            listener: listener,
+            globe_entity: globe_entity,
+            spawn_pos: spawn_pos,
+            clients: Vec::new(),
+            next_network_id: 1,
+        })
+    }
+
+    fn new_network_id(&mut self) -> NetworkId {
+        let id = NetworkId(self.next_network_id);
+        self.next_network_id += 1;
+        id
+    }
+}
+
+impl specs::System<TimeDelta> for ServerSystem {
+    fn run(&mut self, arg: specs::RunArg, _dt: TimeDelta) {
+        arg.fetch(|w| self.serve(w));
+    }
+}
+
+impl ServerSystem {
+    fn serve(&mut self, w: &specs::World) {
+        use specs::Join;
+        let cell_dwellers = w.read::<CellDweller>();
+        let mut movement_intents = w.write::<MovementIntent>();
+        let mut mining_intents = w.write::<MiningIntent>();
+        let mut network_ids = w.write::<NetworkId>();
+        let globes = w.read::<Globe>();
+        let entities = w.entities();
+
+        let globe = match globes.get(self.globe_entity) {
+            Some(globe) => globe,
+            None => {
+                warn!(self.log, "The globe we're serving is not alive! Can't proceed!");
+                return;
+            },
+        };
+        let spec = globe.spec();
+
+        // Make sure every `CellDweller` has an ID we can tell clients about.
+        for (_, entity) in (&cell_dwellers, &entities).iter() {
+            if network_ids.get(entity).is_none() {
+                let id = self.new_network_id();
+                network_ids.insert(entity, id);
+            }
+        }
+
+        // Welcome any new clients, and give them someone to play as.
+        loop {
+            let (stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(self.log, "Failed to accept client"; "error" => format!("{}", e));
+                    break;
+                },
+            };
+            let mut connection = match Connection::new(stream) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!(self.log, "Failed to set up connection"; "error" => format!("{}", e));
+                    continue;
+                },
+            };
+            let player_id = self.new_network_id();
+            // Nobody else will see this until the end of the tick,
+            // but we'll tell everyone about it next tick.
+            let mut cd = CellDweller::new(
+                self.spawn_pos,
+                Dir::default(),
+                spec,
+                Some(self.globe_entity),
+            );
+            let spatial = Spatial::new(cd.get_real_transform_and_mark_as_clean());
+            let player_entity = w.create_later_build()
+                .with(cd)
+                .with(MovementIntent::new())
+                .with(MiningIntent::new())
+                .with(BlockInventory::new())
+                .with(spatial)
+                .with(player_id)
+                .build();
+            connection.send(&encode_server_message(&ServerMessage::Welcome {
+                spec: spec,
+                player_id: player_id,
+            }));
+            let client_log = self.log.new(o!("client" => format!("{}", addr)));
+            info!(client_log, "Client connected"; "player_id" => player_id.0);
+            self.clients.push(Client {
+                log: client_log,
+                connection: connection,
+                player_entity: player_entity,
+                known_cell_dwellers: HashMap::new(),
+                known_chunk_versions: HashMap::new(),
+                unapplied_intents: None,
+            });
+        }
+
+        // Work out what everyone should be able to see.
+        let cell_dweller_states: Vec<CellDwellerState> = (&cell_dwellers, &network_ids).iter()
+            .filter(|&(cd, _)| cd.globe_entity == Some(self.globe_entity))
+            .map(|(cd, id)| CellDwellerState {
+                id: *id,
+                pos: cd.pos,
+                dir: cd.dir,
+                last_turn_bias: cd.last_turn_bias,
+                yaw: cd.yaw,
+            })
+            .collect();
+        let current_ids: HashSet<NetworkId> = cell_dweller_states.iter()
+            .map(|state| state.id)
+            .collect();
+        let modified_chunks = globe.modified_chunks();
+
+        let mut disconnected_indices: Vec<usize> = Vec::new();
+        for (index, client) in self.clients.iter_mut().enumerate() {
+            // Hear what the client wants to do.
+            let frames = match client.connection.receive() {
+                Ok(frames) => frames,
+                Err(e) => {
+                    info!(client.log, "Client disconnected"; "reason" => format!("{}", e));
+                    disconnected_indices.push(index);
+                    continue;
+                },
+            };
+            for frame in frames {
+                match decode_client_message(&frame) {
+                    Ok(ClientMessage::Intents { movement, mining }) => {
+                        // Only the latest intents matter.
+                        client.unapplied_intents = Some((movement, mining));
+                    },
+                    Err(e) => {
+                        warn!(client.log, "Ignoring bad message from client"; "error" => format!("{}", e));
+                    },
+                }
+            }
+
+            if let Some((movement, mining)) = client.unapplied_intents {
+                let player_intents = (
+                    movement_intents.get_mut(client.player_entity),
+                    mining_intents.get_mut(client.player_entity),
+                );
+                if let (Some(movement_intent), Some(mining_intent)) = player_intents {
+                    *movement_intent = movement;
+                    *mining_intent = mining;
+                    client.unapplied_intents = None;
+                }
+            }
+
+            // Tell the client about anyone who has moved or left.
+            for state in &cell_dweller_states {
+                if client.known_cell_dwellers.get(&state.id) != Some(state) {
+                    client.connection.send(&encode_server_message(&ServerMessage::CellDweller(*state)));
+                    client.known_cell_dwellers.insert(state.id, *state);
+                }
+            }
+            let removed_ids: Vec<NetworkId> = client.known_cell_dwellers.keys()
+                .filter(|id| !current_ids.contains(id))
+                .cloned()
+                .collect();
+            for id in removed_ids {
+                client.connection.send(&encode_server_message(&ServerMessage::CellDwellerRemoved(id)));
+                client.known_cell_dwellers.remove(&id);
+            }
+
+            // Send any chunks that have changed since the client last saw them.
+            for chunk in &modified_chunks {
+                let known_version = client.known_chunk_versions.get(&chunk.origin).cloned().unwrap_or(1);
+                if chunk.version <= known_version {
+                    continue;
+                }
+                // TODO: send just the cells that changed, rather than the whole chunk.
+                let mut chunk_copy = Chunk::new(
+                    chunk.origin,
+                    chunk.cells.clone(),
+                    spec.root_resolution,
+                    spec.chunk_resolution,
+                );
+                chunk_copy.version = chunk.version;
+                client.connection.send(&encode_server_message(&ServerMessage::Chunk(chunk_copy)));
+                client.known_chunk_versions.insert(chunk.origin, chunk.version);
+                trace!(client.log, "Sent chunk"; "origin" => format!("{:?}", chunk.origin), "version" => chunk.version);
+            }
+
+            if let Err(e) = client.connection.flush() {
+                info!(client.log, "Client disconnected"; "reason" => format!("{}", e));
+                disconnected_indices.push(index);
+            }
+        }
+
+        // Take departed clients' `CellDweller`s out of the world.
+        for index in disconnected_indices.into_iter().rev() {
+            let client = self.clients.remove(index);
+            w.delete_later(client.player_entity);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::net::{ TcpListener, TcpStream };
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use slog;
+use specs;
+
+use globe::{ Globe, ChunkSystem, ChunkAnchor, ChunkOrigin, CellPos, Dir, PosInOwningRoot, MaterialRegistry };
+use globe::material;
+use cell_dweller::{ CellDweller, MovementEvent, MovementIntent, MiningIntent, ClientPlayer };
+use movement::TurnDir;
+use app::App;
+use simple;
+use system_priority as prio;
+use ::Spatial;
+use super::*;
+use super::codec::*;
+
+fn discard_log() -> slog::Logger {
+    let drain = slog::Discard;
+    slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")))
+}
+
+// The top of the flat land is at z = 16,
+// so this is where you stand on it.
+const SURFACE_Z: i64 = 17;
+
+const DT: f64 = 1.0 / 60.0;
+
+#[test]
+fn client_messages_survive_round_trip() {
+    let movement = MovementIntent {
+        step_forward: true,
+        turn_right: true,
+        swim_down: true,
+        ..MovementIntent::default()
+    };
+    let mining = MiningIntent {
+        place: true,
+        ..MiningIntent::default()
+    };
+    let bytes = encode_client_message(&ClientMessage::Intents { movement: movement, mining: mining });
+    match decode_client_message(&bytes).expect("Failed to decode message") {
+        ClientMessage::Intents { movement: decoded_movement, mining: decoded_mining } => {
+            assert_eq!(movement, decoded_movement);
+            assert_eq!(mining, decoded_mining);
+        },
+    }
+}
+
+#[test]
+fn server_messages_survive_round_trip() {
+    let log = discard_log();
+    let spec = Globe::new_small_flat(&log).spec();
+    let state = CellDwellerState {
+        id: NetworkId(7),
+        pos: CellPos::default().set_root(3).set_x(2).set_y(5).set_z(SURFACE_Z),
+        dir: Dir::new(4),
+        last_turn_bias: TurnDir::Left,
+        yaw: 0.25,
+    };
+    let materials = MaterialRegistry::new();
+    let bytes = encode_server_message(&ServerMessage::CellDweller(state));
+    match decode_server_message(&bytes, &spec, &materials).expect("Failed to decode message") {
+        ServerMessage::CellDweller(decoded_state) => assert_eq!(state, decoded_state),
+        _ => panic!("Decoded the wrong kind of message"),
+    }
+
+    // Truncated messages should be rejected, not misread.
+    assert!(decode_server_message(&bytes[..bytes.len() - 1], &spec, &materials).is_err());
+}
+
+struct Client {
+    app: App,
+    globe_entity: specs::Entity,
+    movement_input_sender: mpsc::Sender<MovementEvent>,
+}
+
+// An app that only runs what a client needs:
+// a `ClientSystem`, and a `ChunkSystem` to load the globe around it.
+fn connect_client(addr: ::std::net::SocketAddr, log: &slog::Logger) -> Client {
+    let mut app = App::new_headless(log);
+    let (movement_input_sender, movement_input_receiver) = mpsc::channel();
+    let (_mining_input_sender, mining_input_receiver) = mpsc::channel();
+    let globe_entity = {
+        let planner = app.planner();
+        let globe_entity = {
+            let world = planner.mut_world();
+            world.register::<CellDweller>();
+            world.register::<Spatial>();
+            world.register::<Globe>();
+            world.register::<ChunkAnchor>();
+            world.register::<NetworkId>();
+            world.register::<ClientPlayer>();
+            world.add_resource(MaterialRegistry::new());
+            world.create_now()
+                .with(Globe::new_small_flat(log))
+                .build()
+        };
+        let stream = TcpStream::connect(addr).expect("Failed to connect to server");
+        let client_sys = ClientSystem::new(
+            stream,
+            globe_entity,
+            movement_input_receiver,
+            mining_input_receiver,
+            log,
+        ).expect("Failed to set up client");
+        planner.add_system(client_sys, "client", prio::NETWORK);
+        planner.add_system(ChunkSystem::new(log, 0.8), "chunk", prio::CHUNK);
+        globe_entity
+    };
+    Client {
+        app: app,
+        globe_entity: globe_entity,
+        movement_input_sender: movement_input_sender,
+    }
+}
+
+// Tick both apps until `is_done` says so, giving the
+// network a moment to catch up between ticks.
+fn tick_until<F>(server: &mut App, client: &mut App, mut is_done: F)
+    where F: FnMut(&mut App, &mut App) -> bool
+{
+    for _ in 0..500 {
+        server.tick(DT);
+        client.tick(DT);
+        if is_done(server, client) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("Gave up waiting for server and client to agree");
+}
+
+fn client_player_pos(client: &mut App) -> Option<CellPos> {
+    use specs::Join;
+    let world = client.planner().mut_world();
+    let cell_dwellers = world.read::<CellDweller>();
+    let client_players = world.read::<ClientPlayer>();
+    let pos = (&cell_dwellers, &client_players).iter().next().map(|(cd, _)| cd.pos);
+    pos
+}
+
+// Where the only player on the server is.
+fn server_player_pos(server: &mut App) -> Option<CellPos> {
+    use specs::Join;
+    let world = server.planner().mut_world();
+    let cell_dwellers = world.read::<CellDweller>();
+    let movement_intents = world.read::<MovementIntent>();
+    let pos = (&cell_dwellers, &movement_intents).iter().next().map(|(cd, _)| cd.pos);
+    pos
+}
+
+struct Server {
+    app: App,
+    globe_entity: specs::Entity,
+    addr: ::std::net::SocketAddr,
+    spawn_pos: CellPos,
+}
+
+// A full app serving a flat globe, with some ground to stand on.
+fn start_server(log: &slog::Logger) -> Server {
+    let mut app = simple::new_headless(log);
+    let globe = Globe::new_small_flat(log);
+    let spawn_pos = CellPos::default().set_x(2).set_y(2).set_z(SURFACE_Z);
+    let globe_entity = app.planner().mut_world().create_now()
+        .with(globe)
+        .build();
+    app.planner().mut_world().create_now()
+        .with(ChunkAnchor::new(Some(globe_entity), spawn_pos))
+        .build();
+    app.tick(DT);
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen");
+    let addr = listener.local_addr().expect("Failed to get address");
+    let server_sys = ServerSystem::new(listener, globe_entity, spawn_pos, log)
+        .expect("Failed to set up server");
+    app.planner().add_system(server_sys, "server", prio::NETWORK);
+    Server {
+        app: app,
+        globe_entity: globe_entity,
+        addr: addr,
+        spawn_pos: spawn_pos,
+    }
+}
+
+#[test]
+fn client_sees_its_player_move_and_globe_change() {
+    let log = discard_log();
+
+    let Server { app: mut server, globe_entity: server_globe_entity, addr, spawn_pos } = start_server(&log);
+    let spec = Globe::new_small_flat(&log).spec();
+
+    let mut client = connect_client(addr, &log);
+
+    // Wait until we've joined and been told where we are.
+    tick_until(&mut server, &mut client.app, |_, client| client_player_pos(client).is_some());
+    assert_eq!(Some(spawn_pos), client_player_pos(&mut client.app));
+
+    // Walk forward a bit, and then stop.
+    client.movement_input_sender.send(MovementEvent::StepForward(true)).unwrap();
+    tick_until(&mut server, &mut client.app, |server, _| {
+        server_player_pos(server).map_or(false, |pos| pos != spawn_pos)
+    });
+    client.movement_input_sender.send(MovementEvent::StepForward(false)).unwrap();
+    tick_until(&mut server, &mut client.app, |server, client| {
+        client_player_pos(client) == server_player_pos(server)
+    });
+
+    // Dig a hole on the server, and make sure the client hears about it.
+    let hole_pos = CellPos::default().set_x(5).set_y(5).set_z(SURFACE_Z - 1);
+    let hole_pos_in_owning_root = PosInOwningRoot::new(hole_pos, spec.root_resolution);
+    {
+        let world = server.planner().mut_world();
+        let mut globes = world.write::<Globe>();
+        let globe = globes.get_mut(server_globe_entity).unwrap();
+        let mut cell = *globe.authoritative_cell(hole_pos_in_owning_root);
+        assert!(cell.material != material::AIR);
+        cell.material = material::AIR;
+        globe.set_cell(hole_pos_in_owning_root, cell);
+    }
+    let client_globe_entity = client.globe_entity;
+    tick_until(&mut server, &mut client.app, |_, client| {
+        let world = client.planner().mut_world();
+        let globes = world.read::<Globe>();
+        let globe = globes.get(client_globe_entity).unwrap();
+        globe.loaded_cell(hole_pos).map_or(false, |cell| cell.material == material::AIR)
+    });
+
+    // The client should have the same version of the chunk as the server.
+    let origin = ChunkOrigin::new(
+        CellPos::default().set_x(4).set_y(4).set_z(16),
+        spec.root_resolution,
+        spec.chunk_resolution,
+    );
+    let client_version = {
+        let world = client.app.planner().mut_world();
+        let globes = world.read::<Globe>();
+        let globe = globes.get(client_globe_entity).unwrap();
+        let version = globe.modified_chunks().iter()
+            .find(|chunk| chunk.origin == origin)
+            .map(|chunk| chunk.version);
+        version
+    };
+    assert_eq!(Some(2), client_version);
+}
+
+#[test]
+fn server_hears_intents_sent_straight_after_connecting() {
+    let log = discard_log();
+    let Server { app: mut server, addr, .. } = start_server(&log);
+
+    // Say what we want to do before the server has even
+    // accepted the connection, and never say it again.
+    let stream = TcpStream::connect(addr).expect("Failed to connect to server");
+    let mut connection = super::connection::Connection::new(stream)
+        .expect("Failed to set up connection");
+    let movement = MovementIntent {
+        step_forward: true,
+        ..MovementIntent::default()
+    };
+    let mining = MiningIntent {
+        pick_up: true,
+        ..MiningIntent::default()
+    };
+    connection.send(&encode_client_message(&ClientMessage::Intents { movement: movement, mining: mining }));
+    connection.flush().expect("Failed to send intents");
+    // Make sure it's all arrived by the time the server accepts
+    // the connection, so it hears about it in the same tick.
+    thread::sleep(Duration::from_millis(50));
+
+    for _ in 0..3 {
+        server.tick(DT);
+    }
+    let world = server.planner().mut_world();
+    let movement_intents = world.read::<MovementIntent>();
+    let mining_intents = world.read::<MiningIntent>();
+    let cell_dwellers = world.read::<CellDweller>();
+    use specs::Join;
+    let player_intents = (&cell_dwellers, &movement_intents, &mining_intents).iter()
+        .next()
+        .map(|(_, movement, mining)| (*movement, *mining));
+    assert_eq!(Some((movement, mining)), player_intents);
+}
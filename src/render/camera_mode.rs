@@ -0,0 +1,117 @@
+use specs;
+
+/// How the camera follows the `ClientPlayer` around.
+///
+/// Put one of these on the client player's entity to choose a mode;
+/// without one, the camera stays in first person. Players can also
+/// cycle through modes with `Action::CycleCameraMode`, and zoom in
+/// and out with the mouse wheel. `player_camera::System` moves the
+/// camera smoothly from one mode to the next.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraMode {
+    /// Look out through the player's eyes.
+    FirstPerson,
+    /// Follow along behind the player, looking over their shoulder.
+    ThirdPerson {
+        /// How far behind the player to stay.
+        distance: f64,
+        /// How far above the player to stay.
+        height: f64,
+    },
+    /// Circle slowly around the centre of the globe, looking down
+    /// at it from high above, starting from above the player.
+    Orbit {
+        /// How far from the centre of the globe to stay.
+        distance: f64,
+    },
+}
+
+// Don't let anyone zoom right into the player's head,
+// or into the middle of the globe.
+const MIN_THIRD_PERSON_DISTANCE: f64 = 0.02;
+const MIN_ORBIT_DISTANCE: f64 = 1.5;
+
+// How much each click of the mouse wheel zooms in or out.
+const ZOOM_FACTOR_PER_STEP: f64 = 1.1;
+
+impl Default for CameraMode {
+    fn default() -> CameraMode {
+        CameraMode::FirstPerson
+    }
+}
+
+impl CameraMode {
+    pub fn third_person() -> CameraMode {
+        CameraMode::ThirdPerson {
+            distance: 0.15,
+            height: 0.15,
+        }
+    }
+
+    pub fn orbit() -> CameraMode {
+        CameraMode::Orbit {
+            distance: 3.0,
+        }
+    }
+
+    /// The mode after this one, when cycling through them all.
+    pub fn next(&self) -> CameraMode {
+        match *self {
+            CameraMode::FirstPerson => CameraMode::third_person(),
+            CameraMode::ThirdPerson { .. } => CameraMode::orbit(),
+            CameraMode::Orbit { .. } => CameraMode::FirstPerson,
+        }
+    }
+
+    /// This mode, zoomed in (positive `steps`) or out (negative `steps`),
+    /// as if by that many clicks of the mouse wheel.
+    ///
+    /// There's nothing to zoom in first person.
+    pub fn zoomed(&self, steps: f64) -> CameraMode {
+        let factor = ZOOM_FACTOR_PER_STEP.powf(-steps);
+        match *self {
+            CameraMode::FirstPerson => CameraMode::FirstPerson,
+            CameraMode::ThirdPerson { distance, height } => {
+                let new_distance = (distance * factor).max(MIN_THIRD_PERSON_DISTANCE);
+                CameraMode::ThirdPerson {
+                    distance: new_distance,
+                    // Keep looking down at the same angle.
+                    height: height * new_distance / distance,
+                }
+            },
+            CameraMode::Orbit { distance } => CameraMode::Orbit {
+                distance: (distance * factor).max(MIN_ORBIT_DISTANCE),
+            },
+        }
+    }
+}
+
+impl specs::Component for CameraMode {
+    type Storage = specs::HashMapStorage<CameraMode>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zoom_keeps_angle_and_limits() {
+        match CameraMode::third_person().zoomed(1.0) {
+            CameraMode::ThirdPerson { distance, height } => {
+                assert!(distance < 0.15);
+                assert!((distance - height).abs() < 1e-9);
+            },
+            _ => panic!("Zooming shouldn't change mode"),
+        }
+        match CameraMode::orbit().zoomed(1000.0) {
+            CameraMode::Orbit { distance } => assert!(distance >= MIN_ORBIT_DISTANCE),
+            _ => panic!("Zooming shouldn't change mode"),
+        }
+    }
+
+    #[test]
+    fn cycle_through_all_modes() {
+        let mode = CameraMode::FirstPerson;
+        assert_eq!(CameraMode::FirstPerson, mode.next().next().next());
+    }
+}
@@ -11,17 +11,27 @@ gfx_vertex_struct!(
         a_pos: [f32; 4] = "a_pos",
         tex_coord: [f32; 2] = "a_tex_coord",
         a_color: [f32; 3] = "a_color",
+        a_normal: [f32; 3] = "a_normal",
     }
 );
 
 pub type Vertex = _Vertex;
 
 impl Vertex {
+    /// A vertex that isn't lit by the sun; it's always
+    /// drawn in exactly the colour given.
     pub fn new(pos: [f32; 3], color: [f32; 3]) -> Vertex {
+        Vertex::new_with_normal(pos, color, [0.0, 0.0, 0.0])
+    }
+
+    /// A vertex lit by the sun, facing in the direction of `normal`
+    /// (in model space). `normal` doesn't need to be of unit length.
+    pub fn new_with_normal(pos: [f32; 3], color: [f32; 3], normal: [f32; 3]) -> Vertex {
         Vertex {
             a_pos: [pos[0], pos[1], pos[2], 1.0],
             a_color: color,
             tex_coord: [0.0, 0.0],
+            a_normal: normal,
         }
     }
 }
@@ -30,6 +40,13 @@ gfx_pipeline!(
     pipe {
         vbuf: gfx::VertexBuffer<Vertex> = (),
         u_model_view_proj: gfx::Global<[[f32; 4]; 4]> = "u_model_view_proj",
+        // Just the model part, for turning normals into world space.
+        u_model: gfx::Global<[[f32; 4]; 4]> = "u_model",
+        // Unit vector pointing towards the sun, in world space.
+        u_sun_direction: gfx::Global<[f32; 3]> = "u_sun_direction",
+        u_sun_color: gfx::Global<[f32; 3]> = "u_sun_color",
+        // Light that reaches everything, even facing away from the sun.
+        u_ambient_color: gfx::Global<[f32; 3]> = "u_ambient_color",
         t_color: gfx::TextureSampler<[f32; 4]> = "t_color",
         out_color: gfx::RenderTarget<gfx::format::Srgba8> = "o_color",
         out_depth: gfx::DepthTarget<gfx::format::DepthStencil> =
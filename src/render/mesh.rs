@@ -71,6 +71,10 @@ impl<R: gfx::Resources> Mesh<R> {
         let data = pipe::Data {
             vbuf: vbuf.clone(),
             u_model_view_proj: [[0.0; 4]; 4],
+            u_model: [[0.0; 4]; 4],
+            u_sun_direction: [0.0; 3],
+            u_sun_color: [0.0; 3],
+            u_ambient_color: [0.0; 3],
             t_color: (texture_view, factory.create_sampler(sinfo)),
             out_color: output_color,
             out_depth: output_stencil,
@@ -3,6 +3,7 @@ mod default_pipeline;
 mod mesh;
 mod mesh_repository;
 pub mod player_camera;
+mod camera_mode;
 mod proto_mesh;
 mod encoder_channel;
 mod visual;
@@ -18,3 +19,4 @@ pub use self::encoder_channel::EncoderChannel;
 pub use self::visual::Visual;
 pub use self::axes_mesh::make_axes_mesh;
 pub use self::obj_mesh::make_obj_mesh;
+pub use self::camera_mode::CameraMode;
@@ -39,6 +39,7 @@ pub fn make_obj_mesh<
     for object in &obj_set.objects {
         add_object(object, &mtl_map, scale, &mut vertex_data, &mut index_vec);
     }
+    add_smooth_normals(&mut vertex_data, &index_vec);
 
     mesh_repo.create(factory, vertex_data, index_vec)
 }
@@ -76,3 +77,38 @@ fn add_object(object: &obj::Object, mtl_map: &HashMap<&str, &mtl::Material>, sca
         }
     }
 }
+
+// Face every vertex the average of the ways the triangles
+// around it face, weighted by how big they are, so models
+// look smooth when lit by the sun.
+//
+// TODO: read the normals from the obj file if it has them,
+// so models can have sharp edges.
+fn add_smooth_normals(vertex_data: &mut Vec<Vertex>, index_vec: &[u32]) {
+    for vertex in vertex_data.iter_mut() {
+        vertex.a_normal = [0.0, 0.0, 0.0];
+    }
+    for triangle in index_vec.chunks(3) {
+        if triangle.len() < 3 {
+            break;
+        }
+        let a = vertex_data[triangle[0] as usize].a_pos;
+        let b = vertex_data[triangle[1] as usize].a_pos;
+        let c = vertex_data[triangle[2] as usize].a_pos;
+        let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        // Cross product; its length is twice the area of the triangle.
+        let face_normal = [
+            ab[1] * ac[2] - ab[2] * ac[1],
+            ab[2] * ac[0] - ab[0] * ac[2],
+            ab[0] * ac[1] - ab[1] * ac[0],
+        ];
+        for &vertex_index in triangle {
+            let normal = &mut vertex_data[vertex_index as usize].a_normal;
+            for (normal_axis, face_axis) in normal.iter_mut().zip(&face_normal) {
+                *normal_axis += *face_axis;
+            }
+        }
+    }
+    // The shader normalizes them for us, so no need to do it here.
+}
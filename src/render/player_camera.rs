@@ -5,56 +5,208 @@ use specs;
 use specs::Join;
 
 use ::types::*;
+use ::input_bindings::{ Action, SharedInputBindings };
+use super::CameraMode;
 
-#[derive(Default)]
-pub struct ClientPlayer;
-impl specs::Component for ClientPlayer {
-    type Storage = specs::NullStorage<ClientPlayer>;
+// Used to live here; the camera isn't the only thing that cares any more.
+pub use ::cell_dweller::ClientPlayer;
+
+// How long it takes to move the camera from one mode to another.
+const TRANSITION_SECONDS: f64 = 0.6;
+// How high above the bottom of their cell a player's eyes are.
+const EYE_HEIGHT: f64 = 0.02;
+// How far ahead of a player to look when following them.
+const LOOK_AHEAD_DISTANCE: f64 = 0.5;
+// How fast to circle around the globe in orbit mode.
+const ORBIT_RADIANS_PER_SECOND: f64 = 0.1;
+
+// Where the camera is, and which way it's looking.
+#[derive(Clone, Copy)]
+struct Pose {
+    position: Vec3,
+    target: Vec3,
+    up: Vec3,
+}
+
+impl Pose {
+    // `t` of the way from `self` (0.0) to `other` (1.0).
+    fn lerp(&self, other: &Pose, t: f64) -> Pose {
+        use na::Norm;
+        let up = self.up + (other.up - self.up) * t;
+        Pose {
+            position: self.position + (other.position - self.position) * t,
+            target: self.target + (other.target - self.target) * t,
+            // Opposite up vectors could cancel out half way;
+            // just snap to the new one if so.
+            up: if up.norm() > 1e-6 { up.normalize() } else { other.up },
+        }
+    }
 }
 
 // Camera update system
+//
+// Not actually a `specs::System`; see `update`.
 pub struct System {
     camera_input_receiver: mpsc::Receiver<input::Input>,
+    input_bindings: SharedInputBindings,
+    // The mode we were in last time we positioned the camera.
+    mode: Option<CameraMode>,
+    // Where the camera was last time we positioned it.
+    pose: Option<Pose>,
+    // Where the camera was when we last changed mode,
+    // and how long ago that was.
+    transition: Option<(Pose, f64)>,
+    // How far around the globe we've gone since starting to orbit.
+    orbit_angle: f64,
 }
 
 impl System {
-    pub fn new(input_receiver: mpsc::Receiver<input::Input>) -> System {
+    pub fn new(input_receiver: mpsc::Receiver<input::Input>, input_bindings: SharedInputBindings) -> System {
         System {
             camera_input_receiver: input_receiver,
+            input_bindings: input_bindings,
+            mode: None,
+            pose: None,
+            transition: None,
+            orbit_angle: 0.0,
         }
     }
-}
 
-impl specs::System<TimeDelta> for System {
-    fn run(&mut self, arg: specs::RunArg, _: TimeDelta) {
-        use na::{ Norm, Rotate };
+    /// Move the camera to follow the client player, as they
+    /// appear `alpha` of the way between the previous tick
+    /// and the most recent one. `dt` is how much real time
+    /// has passed since the last time this was called.
+    ///
+    /// Like the render system, this runs outside of the `specs::Planner`,
+    /// just before drawing.
+    pub fn update(&mut self, world: &specs::World, alpha: f64, dt: TimeDelta) {
         use ::Spatial;
-        use ::types::Vec3;
 
-        let (client_players, spatials, mut camera) = arg.fetch(|w| {
-            (w.read::<ClientPlayer>(), w.read::<Spatial>(), w.write_resource::<Camera>())
-        });
+        let client_players = world.read::<ClientPlayer>();
+        let spatials = world.read::<Spatial>();
+        let mut camera_modes = world.write::<CameraMode>();
+        let entities = world.entities();
+        let mut camera = world.write_resource::<Camera>();
+
         // Handle incoming keyboard/mouse events for the PlayerCamera
-        while let Ok(_) = self.camera_input_receiver.try_recv() {
-            //camera.event(&e);
+        let mut mode_cycles = 0;
+        let mut zoom_steps = 0.0;
+        while let Ok(input_event) = self.camera_input_receiver.try_recv() {
+            use piston::input::{ PressEvent, MouseScrollEvent };
+            if let Some(button) = input_event.press_args() {
+                let action = self.input_bindings.read().unwrap().action(button);
+                if action == Some(Action::CycleCameraMode) {
+                    mode_cycles += 1;
+                }
+            }
+            if let Some(scroll) = input_event.mouse_scroll_args() {
+                zoom_steps += scroll[1];
+            }
         }
+
         // Update the PlayerCamera's target position
-        for (i, (_, s)) in (&client_players.check(), &spatials).iter().enumerate() {
-            let player_pos = s.transform.translation;
+        for (i, (_, s, entity)) in (&client_players.check(), &spatials, &entities).iter().enumerate() {
+            // Ensure there isn't more than one client player
+            assert!(i == 0, "There is more than one client player!");
 
-            let up = player_pos.normalize(); // TODO player_pos - planet_pos
-            let forward = s.transform.rotation.rotate(&Vec3::new(0.0, 0.0, 1.0));
-            let left = s.transform.rotation.rotate(&Vec3::new(1.0, 0.0, 0.0));
+            let mut mode = camera_modes.get(entity).cloned().unwrap_or_default();
+            for _ in 0..mode_cycles {
+                mode = mode.next();
+            }
+            if zoom_steps != 0.0 {
+                mode = mode.zoomed(zoom_steps);
+            }
+            if camera_modes.get(entity) != Some(&mode) {
+                camera_modes.insert(entity, mode);
+            }
 
-            let target = player_pos + forward * 0.5;
-            let cam_pos = player_pos + up * 0.15 - forward * 0.15 - left * 0.05;
+            // Blend smoothly from wherever the camera was
+            // whenever we switch to a different mode.
+            let is_new_mode = match self.mode {
+                Some(CameraMode::FirstPerson) => mode != CameraMode::FirstPerson,
+                Some(CameraMode::ThirdPerson { .. }) => match mode {
+                    CameraMode::ThirdPerson { .. } => false,
+                    _ => true,
+                },
+                Some(CameraMode::Orbit { .. }) => match mode {
+                    CameraMode::Orbit { .. } => false,
+                    _ => true,
+                },
+                None => false,
+            };
+            if is_new_mode {
+                self.transition = self.pose.map(|pose| (pose, 0.0));
+                self.orbit_angle = 0.0;
+            }
+            self.mode = Some(mode);
 
-            camera.position = [cam_pos.x, cam_pos.y, cam_pos.z];
-            camera.up = [up.x, up.y, up.z];
-            camera.look_at([target.x, target.y, target.z]);
+            if let CameraMode::Orbit { .. } = mode {
+                self.orbit_angle += ORBIT_RADIANS_PER_SECOND * dt;
+            }
+            let desired_pose = desired_pose(mode, &s.interpolated_transform(alpha), self.orbit_angle);
+            let pose = match self.transition {
+                Some((from_pose, elapsed)) => {
+                    let elapsed = elapsed + dt;
+                    let t = (elapsed / TRANSITION_SECONDS).min(1.0);
+                    self.transition = if t < 1.0 { Some((from_pose, elapsed)) } else { None };
+                    // Ease in and out.
+                    let smooth_t = t * t * (3.0 - 2.0 * t);
+                    from_pose.lerp(&desired_pose, smooth_t)
+                },
+                None => desired_pose,
+            };
+            self.pose = Some(pose);
 
-            // Ensure there isn't more than one client player
-            assert!(i == 0, "There is more than one client player!");
+            camera.position = [pose.position.x, pose.position.y, pose.position.z];
+            camera.up = [pose.up.x, pose.up.y, pose.up.z];
+            camera.look_at([pose.target.x, pose.target.y, pose.target.z]);
         }
     }
 }
+
+// Where the camera would be in `mode` for a player at `transform`,
+// if we weren't in the middle of switching modes.
+fn desired_pose(mode: CameraMode, transform: &Iso3, orbit_angle: f64) -> Pose {
+    use na::{ Norm, Rotate };
+
+    let player_pos = transform.translation;
+    let up = player_pos.normalize(); // TODO player_pos - planet_pos
+    let forward = transform.rotation.rotate(&Vec3::new(0.0, 0.0, 1.0));
+
+    match mode {
+        CameraMode::FirstPerson => {
+            let eye = player_pos + up * EYE_HEIGHT;
+            Pose {
+                position: eye,
+                target: eye + forward,
+                // Tilt along with the player's head.
+                up: transform.rotation.rotate(&Vec3::new(0.0, 1.0, 0.0)),
+            }
+        },
+        CameraMode::ThirdPerson { distance, height } => {
+            Pose {
+                position: player_pos + up * height - forward * distance,
+                target: player_pos + forward * LOOK_AHEAD_DISTANCE,
+                up: up,
+            }
+        },
+        CameraMode::Orbit { distance } => {
+            // Circle around the globe's axis, starting right above the player.
+            let axis = Vec3::new(0.0, 0.0, 1.0);
+            let direction = Rot3::new(axis * orbit_angle).rotate(&up);
+            // Keep the axis pointing up the screen, unless
+            // we're looking straight down it.
+            use na::Dot;
+            let camera_up = if direction.dot(&axis).abs() < 0.99 {
+                axis
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            Pose {
+                position: direction * distance,
+                target: Vec3::new(0.0, 0.0, 0.0),
+                up: camera_up,
+            }
+        },
+    }
+}
@@ -14,10 +14,23 @@ use super::EncoderChannel;
 use super::Visual;
 use super::MeshRepository;
 use ::Spatial;
+use ::Sun;
 use ::types::*;
 
+// Colour of the sky, and of the light that comes from it
+// (which reaches everything, even what's facing away from
+// the sun), in broad daylight and in the middle of the night.
+const DAY_SKY_COLOR: [f32; 3] = [0.45, 0.65, 0.9];
+const NIGHT_SKY_COLOR: [f32; 3] = [0.02, 0.02, 0.06];
+const DAY_AMBIENT_COLOR: [f32; 3] = [0.45, 0.45, 0.5];
+const NIGHT_AMBIENT_COLOR: [f32; 3] = [0.12, 0.12, 0.2];
+const SUN_COLOR: [f32; 3] = [0.6, 0.58, 0.5];
+
 // System to render all visible entities. This is back-end agnostic;
 // i.e. nothing in it should be tied to OpenGL, Vulkan, etc.
+//
+// Despite the name, this isn't run by the `specs::Planner`;
+// `App` calls `draw_world` directly whenever it's time to draw.
 
 pub struct System<R: gfx::Resources, C: gfx::CommandBuffer<R>> {
     log: Logger,
@@ -66,6 +79,20 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
         }
     }
 
+    /// Draw every `Visual` in the world.
+    ///
+    /// This happens outside of the `specs::Planner`, so that it can
+    /// run as often as we like, independent of simulation ticks.
+    /// `alpha` says how far we are between the previous tick (0.0)
+    /// and the most recent one (1.0); see `Spatial::interpolated_transform`.
+    pub fn draw_world(&mut self, world: &specs::World, alpha: f64) {
+        let visuals = world.read::<Visual>();
+        let spatials = world.read::<Spatial>();
+        let mut camera = world.write_resource::<Camera>();
+        let sun = world.read_resource::<Sun>();
+        self.draw(alpha, visuals, spatials, &mut *camera, &sun);
+    }
+
     // Abstract over `specs` storage types with `A`, and `D`.
     fn draw<
         A: Deref<Target = specs::Allocator>,
@@ -73,16 +100,14 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
         Sd: Deref<Target = specs::MaskedStorage<Spatial>>,
     >(
         &mut self,
-        dt: TimeDelta,
+        alpha: f64,
         visuals: specs::Storage<Visual, A, Vd>,
         spatials: specs::Storage<Spatial, A, Sd>,
         camera: &mut Camera,
+        sun: &Sun,
     ) {
-        // TODO: Systems are currently run on the main thread,
-        // so we need to `try_recv` to avoid deadlock.
-        // This is only because I don't want to burn CPU, and I've yet
-        // to get around to frame/update rate limiting, so I'm
-        // relying on Piston's for now.
+        // We're called from the same thread that flushes encoders to the
+        // device, so we need to `try_recv` to avoid deadlock.
         use std::sync::mpsc::TryRecvError;
         let mut encoder = match self.encoder_channel.receiver.try_recv() {
             Ok(encoder) => encoder,
@@ -90,8 +115,18 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
             Err(TryRecvError::Disconnected) => panic!("Device owner hung up. That wasn't supposed to happen!"),
         };
 
-        const CLEAR_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
-        encoder.clear(&self.output_color, CLEAR_COLOR);
+        // Light everything for the time of day wherever the camera is.
+        // TODO: relative to whatever globe the camera is near,
+        // when globes have a `Spatial`.
+        let camera_up = Vec3::new(camera.position[0], camera.position[1], camera.position[2]);
+        let daylight = sun.daylight_at(&camera_up) as f32;
+        let sky_color = lerp_color(NIGHT_SKY_COLOR, DAY_SKY_COLOR, daylight);
+        let ambient_color = lerp_color(NIGHT_AMBIENT_COLOR, DAY_AMBIENT_COLOR, daylight);
+        let sun_direction = sun.direction();
+        let sun_direction = [sun_direction.x as f32, sun_direction.y as f32, sun_direction.z as f32];
+
+        let clear_color = [sky_color[0], sky_color[1], sky_color[2], 1.0];
+        encoder.clear(&self.output_color, clear_color);
         encoder.clear_depth(&self.output_stencil, 1.0);
 
         //let cam = self.camera.lock().unwrap();
@@ -108,12 +143,13 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
             };
 
             // TODO: cache the model matrix separately per Visual
+            let transform = s.interpolated_transform(alpha);
             use na;
             use na::{ Vector3, Matrix3, Rotation3, Isometry3, ToHomogeneous };
             // Do some nasty fiddling to cast down to `f32`.
             let transform_f32: Isometry3<f32> = {
-                let translation_f32: Vector3<f32> = na::Cast::<Vector3<f64>>::from(s.transform.translation);
-                let rot_mat_f32: Matrix3<f32> = na::Cast::<Matrix3<f64>>::from(*s.transform.rotation.submatrix());
+                let translation_f32: Vector3<f32> = na::Cast::<Vector3<f64>>::from(transform.translation);
+                let rot_mat_f32: Matrix3<f32> = na::Cast::<Matrix3<f64>>::from(*transform.rotation.submatrix());
                 let rotation_f32 = Rotation3::from_matrix_unchecked(rot_mat_f32);
                 Isometry3::from_rotation_matrix(translation_f32, rotation_f32)
             };
@@ -138,6 +174,10 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
             };
 
             mesh.data_mut().u_model_view_proj = model_view_projection;
+            mesh.data_mut().u_model = model_for_camera_controllers;
+            mesh.data_mut().u_sun_direction = sun_direction;
+            mesh.data_mut().u_sun_color = SUN_COLOR;
+            mesh.data_mut().u_ambient_color = ambient_color;
             encoder.draw(
                 mesh.slice(),
                 &self.pso,
@@ -149,19 +189,11 @@ impl<R: gfx::Resources, C: gfx::CommandBuffer<R>> System<R, C> {
     }
 }
 
-impl<R, C> specs::System<TimeDelta> for System<R, C> where
-R: 'static + gfx::Resources,
-C: 'static + gfx::CommandBuffer<R> + Send,
-{
-    fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
-        let (visuals, spatials, mut camera) = arg.fetch(|w|
-            (w.read::<Visual>(), w.read::<Spatial>(), w.write_resource::<Camera>()),
-        );
-
-        self.draw(dt, visuals, spatials, &mut *camera);
-
-        // TODO: implement own "extrapolated time" concept or similar
-        // to decide how often we should actually be trying to render?
-        // See https://github.com/PistonDevelopers/piston/issues/193
-    }
+// `t` of the way from `a` (0.0) to `b` (1.0).
+fn lerp_color(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
 }
@@ -31,6 +31,12 @@ impl Visual {
     pub fn set_mesh_handle(&mut self, new_mesh_handle: MeshHandle) {
         self.mesh_handle = new_mesh_handle.into();
     }
+
+    /// Stop drawing anything for this visual, without
+    /// getting rid of the mesh it used to have.
+    pub fn clear_mesh_handle(&mut self) {
+        self.mesh_handle = None;
+    }
 }
 
 impl specs::Component for Visual {
@@ -1,4 +1,5 @@
-use std::sync::mpsc;
+use std::io::Write;
+use std::sync::{ Arc, mpsc };
 
 use piston_window::PistonWindow;
 
@@ -7,86 +8,237 @@ use slog_term;
 
 use window;
 use app;
+use input_recording::{ InputSenders, InputRecorder, InputReplay, RecordedInputEvent };
+use worker_pool::WorkerPool;
+
+// Seconds of simulation time from one sunrise to the next.
+const DAY_LENGTH: f64 = 20.0 * 60.0;
+
+// Threads for generating and meshing chunks in the background.
+// TODO: base this on how many cores we've actually got.
+const WORKER_THREADS: usize = 2;
 
 /// Create a new simple PlanetKit app and window.
 ///
 /// Uses all default settings, and logs to standard output.
 pub fn new() -> (app::App, PistonWindow) {
+    new_with_input(|app, input_senders, _log| add_input_adapters(app, input_senders))
+}
+
+/// Create a new simple PlanetKit app and window that records
+/// all player input to `recording`.
+///
+/// See `input_recording` for how to play it back.
+pub fn new_recording(recording: Box<Write>) -> (app::App, PistonWindow) {
+    new_with_input(|app, input_senders, log| {
+        add_recording_input_adapters(app, input_senders, recording, log)
+    })
+}
+
+fn new_with_input<F>(connect_input: F) -> (app::App, PistonWindow)
+    where F: FnOnce(&mut app::App, InputSenders, &slog::Logger)
+{
     // Set up logger to print to standard output.
     use slog::DrainExt;
     let drain = slog_term::streamer().compact().build().fuse();
     let root_log = slog::Logger::root(drain, o!("pk_version" => env!("CARGO_PKG_VERSION")));
     let log = root_log;
 
-    // Event channel for camera system
-    let (camera_input_sender, camera_input_receiver) = mpsc::channel();
-
     let mut window = window::make_window(&log);
-    let mut app = app::App::new(&log, &mut window, camera_input_sender);
+    let mut app = app::App::new(&log, &mut window);
 
-    // Set up input adapters.
-    use cell_dweller;
-    let (movement_input_sender, movement_input_receiver) = mpsc::channel();
-    let movement_input_adapter = cell_dweller::MovementInputAdapter::new(movement_input_sender);
-    app.add_input_adapter(Box::new(movement_input_adapter));
-
-    let (mining_input_sender, mining_input_receiver) = mpsc::channel();
-    let mining_input_adapter = cell_dweller::MiningInputAdapter::new(mining_input_sender);
-    app.add_input_adapter(Box::new(mining_input_adapter));
+    let worker_pool = Arc::new(WorkerPool::new(WORKER_THREADS, &log));
+    let input_senders = add_simulation_systems(&mut app, &log, Some(worker_pool.clone()));
+    connect_input(&mut app, input_senders, &log);
 
     {
         let planner = app.planner();
 
-        {
-            // Register all component types.
-            let world = planner.mut_world();
-            world.register::<::cell_dweller::CellDweller>();
-            world.register::<::render::Visual>();
-            world.register::<::Spatial>();
-            world.register::<::globe::Globe>();
-            world.register::<::globe::ChunkView>();
-            world.register::<::render::player_camera::ClientPlayer>();
-        }
-
         // TODO: move _all_ other system initialization from `app.rs`
         // into here, and then back out into helper functions.
 
         use super::system_priority as prio;
-
-        let movement_sys = cell_dweller::MovementSystem::new(
-            movement_input_receiver,
-            &log,
-        );
-        planner.add_system(movement_sys, "cd_movement", prio::CD_MOVEMENT);
-
-        let mining_sys = cell_dweller::MiningSystem::new(
-            mining_input_receiver,
-            &log,
-        );
-        planner.add_system(mining_sys, "cd_mining", prio::CD_MINING);
-
-        let physics_sys = cell_dweller::PhysicsSystem::new(
-            &log,
-            0.1, // Seconds between falls
-        );
-        planner.add_system(physics_sys, "cd_physics", prio::CD_PHYSICS);
-
         use globe;
-        let chunk_sys = globe::ChunkSystem::new(
-            &log,
-        );
-        planner.add_system(chunk_sys, "chunk", prio::CHUNK);
-        let chunk_view_sys = globe::ChunkViewSystem::new(
+
+        let mut chunk_view_sys = globe::ChunkViewSystem::new(
             &log,
             0.05, // Seconds between geometry creation
         );
+        chunk_view_sys.set_worker_pool(worker_pool);
         planner.add_system(chunk_view_sys, "chunk_view", prio::CHUNK_VIEW);
 
-        let camera_update_sys = ::render::player_camera::System::new(camera_input_receiver);
-        planner.add_system(camera_update_sys, "camera_update", 50);
+        let lod_view_sys = globe::LodViewSystem::new(&log);
+        planner.add_system(lod_view_sys, "lod_view", prio::LOD_VIEW);
     }
 
     app.temp_remove_me_init();
 
     (app, window)
 }
+
+/// Create a new simple PlanetKit app with no window or renderer.
+///
+/// Has all the same systems as `new` apart from those that are only
+/// needed for drawing things, but the world starts out empty.
+/// Drive it with `App::tick` or `App::run_headless`.
+pub fn new_headless(parent_log: &slog::Logger) -> app::App {
+    let mut app = app::App::new_headless(parent_log);
+    let input_senders = add_simulation_systems(&mut app, parent_log, None);
+    add_input_adapters(&mut app, input_senders);
+    app
+}
+
+/// Like `new_headless`, but records all input passed
+/// to the app's input adapters to `recording`.
+pub fn new_headless_recording(parent_log: &slog::Logger, recording: Box<Write>) -> app::App {
+    let mut app = app::App::new_headless(parent_log);
+    let input_senders = add_simulation_systems(&mut app, parent_log, None);
+    add_recording_input_adapters(&mut app, input_senders, recording, parent_log);
+    app
+}
+
+/// Like `new_headless`, but with no input adapters; instead, the
+/// systems get exactly the input events in `recording`, on the same
+/// ticks they were recorded on.
+///
+/// Set up the world the same way as the app that made the recording,
+/// and it should play out the same way again.
+pub fn new_headless_replaying(
+    parent_log: &slog::Logger,
+    recording: Vec<RecordedInputEvent>,
+) -> app::App {
+    let mut app = app::App::new_headless(parent_log);
+    let input_senders = add_simulation_systems(&mut app, parent_log, None);
+    app.add_input_source(Box::new(InputReplay::new(recording, input_senders)));
+    app
+}
+
+// Send input from the usual input adapters straight to systems.
+fn add_input_adapters(app: &mut app::App, input_senders: InputSenders) {
+    use cell_dweller;
+    let movement_input_adapter = cell_dweller::MovementInputAdapter::new(
+        input_senders.movement,
+        app.input_bindings(),
+    );
+    app.add_input_adapter(Box::new(movement_input_adapter));
+
+    let mining_input_adapter = cell_dweller::MiningInputAdapter::new(
+        input_senders.mining,
+        app.input_bindings(),
+    );
+    app.add_input_adapter(Box::new(mining_input_adapter));
+}
+
+// Send input from the usual input adapters to systems
+// by way of an `InputRecorder`.
+fn add_recording_input_adapters(
+    app: &mut app::App,
+    input_senders: InputSenders,
+    recording: Box<Write>,
+    log: &slog::Logger,
+) {
+    let (movement_sender, movement_receiver) = mpsc::channel();
+    let (mining_sender, mining_receiver) = mpsc::channel();
+    add_input_adapters(app, InputSenders {
+        movement: movement_sender,
+        mining: mining_sender,
+    });
+    let recorder = InputRecorder::new(
+        movement_receiver,
+        mining_receiver,
+        input_senders,
+        recording,
+        log,
+    );
+    app.add_input_source(Box::new(recorder));
+}
+
+// Set up component types, and the systems that make the world
+// go around, regardless of whether there's anything to draw it to.
+//
+// Chunks are generated on `worker_pool` if there is one;
+// otherwise they're generated as soon as they're needed,
+// which keeps headless runs deterministic.
+//
+// Returns where to send input for those systems.
+fn add_simulation_systems(
+    app: &mut app::App,
+    log: &slog::Logger,
+    worker_pool: Option<Arc<WorkerPool>>,
+) -> InputSenders {
+    use cell_dweller;
+    let (movement_input_sender, movement_input_receiver) = mpsc::channel();
+    let (mining_input_sender, mining_input_receiver) = mpsc::channel();
+
+    let planner = app.planner();
+
+    {
+        // Register all component types.
+        let world = planner.mut_world();
+        world.register::<::cell_dweller::CellDweller>();
+        world.register::<::cell_dweller::BlockInventory>();
+        world.register::<::cell_dweller::MovementIntent>();
+        world.register::<::cell_dweller::MiningIntent>();
+        world.register::<::cell_dweller::Behaviour>();
+        world.register::<::render::Visual>();
+        world.register::<::Spatial>();
+        world.register::<::globe::Globe>();
+        world.register::<::globe::ChunkView>();
+        world.register::<::globe::ChunkAnchor>();
+        world.register::<::globe::LodView>();
+        world.register::<::cell_dweller::ClientPlayer>();
+        world.register::<::render::CameraMode>();
+        world.register::<::net::NetworkId>();
+
+        world.add_resource(::globe::MaterialRegistry::new());
+        world.add_resource(::Sun::new(DAY_LENGTH));
+    }
+
+    use super::system_priority as prio;
+
+    let ai_sys = cell_dweller::AiSystem::new(log);
+    planner.add_system(ai_sys, "cd_ai", prio::CD_AI);
+
+    let movement_sys = cell_dweller::MovementSystem::new(
+        movement_input_receiver,
+        log,
+    );
+    planner.add_system(movement_sys, "cd_movement", prio::CD_MOVEMENT);
+
+    let mining_sys = cell_dweller::MiningSystem::new(
+        mining_input_receiver,
+        log,
+    );
+    planner.add_system(mining_sys, "cd_mining", prio::CD_MINING);
+
+    let physics_sys = cell_dweller::PhysicsSystem::new(
+        log,
+        0.1, // Seconds between falls
+    );
+    planner.add_system(physics_sys, "cd_physics", prio::CD_PHYSICS);
+
+    use globe;
+    let liquid_sys = globe::LiquidSystem::new(
+        log,
+        0.25, // Seconds between flows
+        256, // Most cells to look at per flow
+    );
+    planner.add_system(liquid_sys, "liquid", prio::LIQUID);
+
+    let sun_sys = ::SunSystem::new();
+    planner.add_system(sun_sys, "sun", prio::SUN);
+
+    let mut chunk_sys = globe::ChunkSystem::new(
+        log,
+        0.8, // Radius around anchors to keep chunks loaded
+    );
+    if let Some(worker_pool) = worker_pool {
+        chunk_sys.set_worker_pool(worker_pool);
+    }
+    planner.add_system(chunk_sys, "chunk", prio::CHUNK);
+
+    InputSenders {
+        movement: movement_input_sender,
+        mining: mining_input_sender,
+    }
+}
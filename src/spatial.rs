@@ -4,18 +4,73 @@ use super::types::*;
 
 pub struct Spatial {
     pub transform: Iso3,
+    // Where we were at the end of the previous simulation tick,
+    // so that we can draw things smoothly in between ticks.
+    pub previous_transform: Iso3,
 }
 
 impl Spatial {
     // TODO: explain about hierarchical coordinate systems plan
     pub fn root() -> Self {
         use num_traits::One;
+        Spatial::new(Iso3::one())
+    }
+
+    /// Start out at `transform`, without being drawn
+    /// moving there from anywhere else.
+    pub fn new(transform: Iso3) -> Self {
         Spatial {
-            transform: Iso3::one(),
+            transform: transform,
+            previous_transform: transform,
         }
     }
+
+    /// Remember the current transform as where we were at the
+    /// end of the last tick. `App` calls this on every `Spatial`
+    /// at the start of each simulation tick.
+    pub fn start_tick(&mut self) {
+        self.previous_transform = self.transform;
+    }
+
+    /// The transform `alpha` of the way from where we were at the
+    /// end of the previous tick (0.0) to where we are now (1.0).
+    pub fn interpolated_transform(&self, alpha: f64) -> Iso3 {
+        use na::{ Rotation, RotationTo };
+
+        let previous = &self.previous_transform;
+        let translation = previous.translation +
+            (self.transform.translation - previous.translation) * alpha;
+        let delta = previous.rotation.rotation_to(&self.transform.rotation);
+        let rotation = previous.rotation.append_rotation(&(delta.rotation() * alpha));
+        Iso3::from_rotation_matrix(translation, rotation)
+    }
 }
 
 impl specs::Component for Spatial {
     type Storage = specs::VecStorage<Spatial>;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interpolate_between_ticks() {
+        let mut spatial = Spatial::root();
+        spatial.start_tick();
+        spatial.transform.translation = Vec3::new(2.0, 0.0, -4.0);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), spatial.interpolated_transform(0.0).translation);
+        assert_eq!(Vec3::new(1.0, 0.0, -2.0), spatial.interpolated_transform(0.5).translation);
+        assert_eq!(Vec3::new(2.0, 0.0, -4.0), spatial.interpolated_transform(1.0).translation);
+    }
+
+    #[test]
+    fn start_where_we_are() {
+        use num_traits::One;
+        let mut transform = Iso3::one();
+        transform.translation = Vec3::new(2.0, 0.0, -4.0);
+        let spatial = Spatial::new(transform);
+        assert_eq!(transform, spatial.interpolated_transform(0.0));
+        assert_eq!(transform, spatial.interpolated_transform(1.0));
+    }
+}
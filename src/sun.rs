@@ -0,0 +1,141 @@
+use std::f64::consts::PI;
+
+use specs;
+
+use types::*;
+
+/// Where the sun is, as a resource in the world.
+///
+/// The sun goes once around the globe every `day_length` seconds
+/// of simulation time, staying in the plane perpendicular to the
+/// z-axis. At the start of the day (time 0.0) it's directly over
+/// the positive y-axis.
+///
+/// TODO: tilt the globe, for seasons, and make the sun go
+/// around the globe's actual poles.
+pub struct Sun {
+    day_length: TimeDelta,
+    // How far through the day we are, from 0.0 up to (but
+    // not including) 1.0.
+    time_of_day: f64,
+}
+
+impl Sun {
+    /// # Panics
+    ///
+    /// Panics if `day_length` isn't positive.
+    pub fn new(day_length: TimeDelta) -> Sun {
+        assert!(day_length > 0.0, "Days need to take some time");
+        Sun {
+            day_length: day_length,
+            time_of_day: 0.0,
+        }
+    }
+
+    /// Seconds of simulation time for the sun to go once around the globe.
+    pub fn day_length(&self) -> TimeDelta {
+        self.day_length
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `day_length` isn't positive.
+    pub fn set_day_length(&mut self, day_length: TimeDelta) {
+        assert!(day_length > 0.0, "Days need to take some time");
+        self.day_length = day_length;
+    }
+
+    /// How far through the day we are, from 0.0 up to (but
+    /// not including) 1.0.
+    pub fn time_of_day(&self) -> f64 {
+        self.time_of_day
+    }
+
+    pub fn set_time_of_day(&mut self, time_of_day: f64) {
+        self.time_of_day = time_of_day - time_of_day.floor();
+    }
+
+    /// Move the sun along by `dt` seconds.
+    pub fn advance(&mut self, dt: TimeDelta) {
+        let time_of_day = self.time_of_day + dt / self.day_length;
+        self.set_time_of_day(time_of_day);
+    }
+
+    /// Unit vector pointing from the centre of the globe towards the sun.
+    pub fn direction(&self) -> Vec3 {
+        let angle = 2.0 * PI * self.time_of_day;
+        Vec3::new(-angle.sin(), angle.cos(), 0.0)
+    }
+
+    /// How much daylight there is for something whose "up" is
+    /// `up`; e.g. the position of something relative to the centre
+    /// of the globe. Goes from 0.0 at night to 1.0 once the sun is
+    /// a little way above the horizon, ramping up smoothly through
+    /// twilight.
+    pub fn daylight_at(&self, up: &Vec3) -> f64 {
+        use na::{ Norm, Dot };
+
+        // How far below and above the horizon twilight lasts, as the
+        // sine of the sun's elevation.
+        const TWILIGHT_START: f64 = -0.1;
+        const TWILIGHT_END: f64 = 0.2;
+
+        if up.norm() < ::std::f64::EPSILON {
+            // Anywhere is up from the middle of the globe.
+            return 1.0;
+        }
+        let elevation = up.normalize().dot(&self.direction());
+        let t = ((elevation - TWILIGHT_START) / (TWILIGHT_END - TWILIGHT_START))
+            .max(0.0)
+            .min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Moves the `Sun` along with simulation time.
+#[derive(Default)]
+pub struct SunSystem;
+
+impl SunSystem {
+    pub fn new() -> SunSystem {
+        SunSystem
+    }
+}
+
+impl specs::System<TimeDelta> for SunSystem {
+    fn run(&mut self, arg: specs::RunArg, dt: TimeDelta) {
+        let mut sun = arg.fetch(|w| w.write_resource::<Sun>());
+        sun.advance(dt);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sun_goes_around_once_a_day() {
+        let mut sun = Sun::new(100.0);
+        let start_direction = sun.direction();
+        sun.advance(25.0);
+        sun.advance(50.0);
+        assert!((sun.time_of_day() - 0.75).abs() < 1e-9);
+        sun.advance(25.0);
+        assert!(sun.time_of_day() < 1e-9 || sun.time_of_day() > 1.0 - 1e-9);
+        use na::Norm;
+        assert!((sun.direction() - start_direction).norm() < 1e-9);
+    }
+
+    #[test]
+    fn day_and_night() {
+        let mut sun = Sun::new(100.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        assert!(sun.daylight_at(&up) > 1.0 - 1e-9);
+        sun.set_time_of_day(0.5);
+        assert!(sun.daylight_at(&up) < 1e-9);
+        // Sunset.
+        sun.set_time_of_day(0.25);
+        let twilight = sun.daylight_at(&up);
+        assert!(twilight > 0.0 && twilight < 1.0);
+    }
+}
@@ -2,8 +2,13 @@ use specs::Priority;
 
 // TODO: express this using constraints instead of magic numbers.
 
+pub const NETWORK: Priority = 130;
 pub const CHUNK: Priority = 120;
+pub const CD_AI: Priority = 115;
 pub const CD_MOVEMENT: Priority = 110;
 pub const CD_MINING: Priority = 100;
+pub const LIQUID: Priority = 95;
 pub const CD_PHYSICS: Priority = 90;
+pub const SUN: Priority = 80;
 pub const CHUNK_VIEW: Priority = 50;
+pub const LOD_VIEW: Priority = 40;
@@ -20,6 +20,7 @@ pub fn make_window(log: &Logger) -> PistonWindow {
         .exit_on_esc(true)
         .build()
         .unwrap();
+    // `App` captures the cursor when switching to mouse-look mode.
     window.set_capture_cursor(false);
     debug!(log, "Main window created");
 
@@ -0,0 +1,263 @@
+//! A handful of background threads for slow work that doesn't need
+//! to happen on the simulation thread; e.g. generating and meshing
+//! chunks.
+//!
+//! Jobs never touch the world directly. Whoever submits a job gives
+//! it everything it needs up front, and has it send its results back
+//! over a channel, so they can be picked up (or thrown away, if
+//! they're not wanted any more) on the thread that owns the world.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, Mutex, Condvar };
+use std::thread;
+
+use slog::Logger;
+
+/// Some work to do on a background thread.
+pub trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+/// Identifies a submitted job, so it can be cancelled
+/// if it's no longer needed before it gets started.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Ticket(u64);
+
+/// Runs `Job`s on a fixed number of background threads,
+/// most urgent first.
+///
+/// The threads are stopped when the pool is dropped; any jobs
+/// that haven't started by then never will.
+///
+/// A job that panics is logged and forgotten about, and its
+/// thread carries on with the next one. The pool doesn't tell
+/// anyone, so a job whose submitter is waiting to hear back
+/// should catch its own panics and report the failure itself.
+pub struct WorkerPool {
+    log: Logger,
+    shared: Arc<Shared>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    job_available: Condvar,
+}
+
+struct Queue {
+    jobs: BinaryHeap<QueuedJob>,
+    next_ticket: u64,
+    is_shutting_down: bool,
+}
+
+struct QueuedJob {
+    // Lower is more urgent.
+    priority: f64,
+    ticket: Ticket,
+    job: Box<Job>,
+}
+
+// `BinaryHeap` pops the greatest element first, so order jobs
+// from least to most urgent: highest priority value first, and then
+// most recently submitted, so ties are broken first-come-first-served.
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &QueuedJob) -> Ordering {
+        other.priority.partial_cmp(&self.priority)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.ticket.0.cmp(&self.ticket.0))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &QueuedJob) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &QueuedJob) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl WorkerPool {
+    /// Start `thread_count` worker threads.
+    pub fn new(thread_count: usize, parent_log: &Logger) -> WorkerPool {
+        let log = parent_log.new(o!());
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: BinaryHeap::new(),
+                next_ticket: 0,
+                is_shutting_down: false,
+            }),
+            job_available: Condvar::new(),
+        });
+        let threads = (0..thread_count).map(|i| {
+            let shared = shared.clone();
+            let worker_log = log.new(o!("worker" => i));
+            thread::Builder::new()
+                .name(format!("planetkit-worker-{}", i))
+                .spawn(move || work(&shared, &worker_log))
+                .expect("Couldn't start worker thread")
+        }).collect();
+        debug!(log, "Started worker pool"; "threads" => thread_count);
+        WorkerPool {
+            log: log,
+            shared: shared,
+            threads: threads,
+        }
+    }
+
+    /// Queue `job` to be run as soon as there's a thread free
+    /// and nothing more urgent to do. Jobs with lower `priority`
+    /// values go first; e.g. use the distance from the player.
+    pub fn submit(&self, priority: f64, job: Box<Job>) -> Ticket {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let ticket = Ticket(queue.next_ticket);
+        queue.next_ticket += 1;
+        queue.jobs.push(QueuedJob {
+            priority: priority,
+            ticket: ticket,
+            job: job,
+        });
+        self.shared.job_available.notify_one();
+        ticket
+    }
+
+    /// Don't bother running the job with the given ticket.
+    ///
+    /// Returns `false` if it has already been started (or finished),
+    /// in which case whatever it sends back will still turn up.
+    pub fn cancel(&self, ticket: Ticket) -> bool {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let old_len = queue.jobs.len();
+        let jobs: Vec<QueuedJob> = queue.jobs.drain()
+            .filter(|queued_job| queued_job.ticket != ticket)
+            .collect();
+        queue.jobs = jobs.into();
+        queue.jobs.len() < old_len
+    }
+
+    /// Number of jobs that are waiting for a thread.
+    pub fn queued_job_count(&self) -> usize {
+        self.shared.queue.lock().unwrap().jobs.len()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.is_shutting_down = true;
+            self.shared.job_available.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                warn!(self.log, "A worker thread panicked");
+            }
+        }
+    }
+}
+
+// Run jobs until the pool is dropped.
+fn work(shared: &Shared, log: &Logger) {
+    loop {
+        let queued_job = {
+            let mut queue = shared.queue.lock().unwrap();
+            while queue.jobs.is_empty() && !queue.is_shutting_down {
+                queue = shared.job_available.wait(queue).unwrap();
+            }
+            if queue.is_shutting_down {
+                return;
+            }
+            queue.jobs.pop().expect("Just checked there was a job waiting")
+        };
+        // Don't let one bad job take the whole thread down with it;
+        // there'd be nobody left to run the rest. The job is consumed
+        // either way, so nothing can see it half-done afterwards.
+        let job = queued_job.job;
+        if panic::catch_unwind(AssertUnwindSafe(move || job.run())).is_err() {
+            error!(log, "A job panicked; carrying on without it"; "ticket" => queued_job.ticket.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use slog;
+    use super::*;
+
+    struct SendNumber {
+        number: u32,
+        sender: mpsc::Sender<u32>,
+    }
+
+    impl Job for SendNumber {
+        fn run(self: Box<Self>) {
+            self.sender.send(self.number).unwrap();
+        }
+    }
+
+    #[test]
+    fn most_urgent_jobs_run_first() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        // No threads, so nothing gets run until we say so.
+        let pool = WorkerPool::new(0, &log);
+        let (sender, receiver) = mpsc::channel();
+        for &(priority, number) in &[(3.0, 3), (1.0, 1), (2.0, 2), (1.0, 10)] {
+            pool.submit(priority, Box::new(SendNumber { number: number, sender: sender.clone() }));
+        }
+        let cancelled = pool.submit(0.0, Box::new(SendNumber { number: 0, sender: sender.clone() }));
+        assert!(pool.cancel(cancelled));
+        assert!(!pool.cancel(cancelled));
+        assert_eq!(4, pool.queued_job_count());
+
+        // Run them all on this thread, in the order the workers would.
+        loop {
+            let queued_job = pool.shared.queue.lock().unwrap().jobs.pop();
+            match queued_job {
+                Some(queued_job) => queued_job.job.run(),
+                None => break,
+            }
+        }
+        let numbers: Vec<u32> = receiver.try_iter().collect();
+        assert_eq!(vec![1, 10, 2, 3], numbers);
+    }
+
+    #[test]
+    fn workers_run_jobs() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let pool = WorkerPool::new(2, &log);
+        let (sender, receiver) = mpsc::channel();
+        for number in 0..10 {
+            pool.submit(0.0, Box::new(SendNumber { number: number, sender: sender.clone() }));
+        }
+        let mut numbers: Vec<u32> = (0..10).map(|_| receiver.recv().unwrap()).collect();
+        numbers.sort();
+        assert_eq!((0..10).collect::<Vec<u32>>(), numbers);
+    }
+
+    struct Panic;
+
+    impl Job for Panic {
+        fn run(self: Box<Self>) {
+            panic!("Job failed on purpose");
+        }
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        // Just one thread, so if it dies, nothing else gets run.
+        let pool = WorkerPool::new(1, &log);
+        let (sender, receiver) = mpsc::channel();
+        pool.submit(0.0, Box::new(Panic));
+        pool.submit(1.0, Box::new(SendNumber { number: 7, sender: sender.clone() }));
+        assert_eq!(7, receiver.recv().unwrap());
+    }
+}